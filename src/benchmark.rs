@@ -0,0 +1,421 @@
+//runtime benchmark: sweeps sample fraction and bootstrap count over a list of queries and
+//records where the time goes and how accurate the estimates are, for plotting
+use rusqlite::Connection;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::{
+    engine::{
+        estimate, exact_answers, join_level, parse_query, query_result, resolve_subqueries,
+        separate_conditions, table_exists, Evaluator, GroundTruth,
+    },
+    error::AqpError,
+    output::{json_number, json_object, json_string},
+    sampling::{create_scratch_sample_tables, drop_sample_set, SampleSet},
+    validation::{sample_schema, validate_query},
+    workload::WorkloadQuery,
+};
+
+//one aggregate of a query answered on one sample with one bootstrap count
+pub struct BenchmarkRecord {
+    name: String,
+    query: String,
+    aggregate: String,
+    group: Option<String>,
+    sample_fraction: f64,
+    sample_rows: i64,
+    bootstrap_size: usize,
+    sampling_time: f64,
+    evaluation_time: f64,
+    bootstrap_time: f64,
+    estimate: f64,
+    ci_relative_width: f64,
+    ground_truth: Option<f64>,
+    exact_time: Option<f64>,
+}
+
+impl BenchmarkRecord {
+    //error of the estimate relative to the exact answer, when the base tables are there
+    pub fn relative_error(&self) -> Option<f64> {
+        self.ground_truth
+            .map(|ground_truth| (self.estimate - ground_truth).abs() / ground_truth.abs())
+    }
+
+    //total time of the approximate answer, sampling excluded since samples are built once
+    pub fn query_time(&self) -> f64 {
+        self.evaluation_time + self.bootstrap_time
+    }
+}
+
+const CSV_HEADER: [&str; 16] = [
+    "name",
+    "query",
+    "aggregate",
+    "group",
+    "sample_fraction",
+    "sample_rows",
+    "bootstrap_size",
+    "sampling_time",
+    "evaluation_time",
+    "bootstrap_time",
+    "query_time",
+    "estimate",
+    "ci_relative_width",
+    "ground_truth",
+    "exact_time",
+    "relative_error",
+];
+
+//exact answers of the aggregates of a query on the base tables and the time they took
+struct ExactAnswer {
+    ground_truth: GroundTruth,
+    exact_time: f64,
+}
+
+//exact answer of a query, or None without the base tables
+fn exact_answer(
+    conn: &Connection,
+    query: &WorkloadQuery,
+    has_base_tables: bool,
+) -> Result<Option<ExactAnswer>, AqpError> {
+    parse_query(query.get_sql())?;
+    if !has_base_tables {
+        return Ok(None);
+    }
+    let start = Instant::now();
+    let ground_truth = exact_answers(conn, query.get_sql())?;
+    Ok(Some(ExactAnswer {
+        ground_truth,
+        exact_time: start.elapsed().as_secs_f64(),
+    }))
+}
+
+//suffix of the sample tables the sweep builds, so the samples in the catalog are left alone
+const BENCHMARK_SUFFIX: &str = "_bench";
+
+//answer every query on a sample of every fraction with every bootstrap count; the samples
+//are built under their own suffix and dropped afterwards, the catalog is not touched
+pub fn run_benchmark(
+    conn: &Connection,
+    queries: &[WorkloadQuery],
+    sample_fractions: &[f64],
+    bootstrap_sizes: &[usize],
+    evaluator: Evaluator,
+    seed: Option<u64>,
+) -> Result<Vec<BenchmarkRecord>, AqpError> {
+    // queries that cannot be parsed or answered exactly are left out of the sweep
+    let has_base_tables = table_exists(conn, "lineitem")?;
+    let mut runnable = Vec::with_capacity(queries.len());
+    for query in queries {
+        match exact_answer(conn, query, has_base_tables) {
+            Ok(exact_answer) => runnable.push((query, exact_answer)),
+            Err(error) => eprintln!("{}: {}, skipped", query.get_name(), error),
+        }
+    }
+    let mut records = Vec::new();
+
+    for &sample_fraction in sample_fractions {
+        // every fraction gets its own sample, so the sampling time is measured on its own
+        let sampling_start = Instant::now();
+        let sample_set =
+            create_scratch_sample_tables(conn, BENCHMARK_SUFFIX, sample_fraction, seed)?;
+        let sampling_time = sampling_start.elapsed().as_secs_f64();
+
+        let sample_records = benchmark_sample(
+            conn,
+            &runnable,
+            &sample_set,
+            sampling_time,
+            bootstrap_sizes,
+            evaluator,
+        );
+        drop_sample_set(conn, &sample_set)?;
+        records.extend(sample_records?);
+    }
+
+    Ok(records)
+}
+
+//answer every query on one sample with every bootstrap count
+fn benchmark_sample(
+    conn: &Connection,
+    runnable: &[(&WorkloadQuery, Option<ExactAnswer>)],
+    sample_set: &SampleSet,
+    sampling_time: f64,
+    bootstrap_sizes: &[usize],
+    evaluator: Evaluator,
+) -> Result<Vec<BenchmarkRecord>, AqpError> {
+    let sample_fraction = sample_set.get_fraction();
+    let mut records = Vec::new();
+
+    for (query, exact_answer) in runnable {
+        let sql_query = parse_query(query.get_sql())?;
+        if let Err(error) = validate_query(&sql_query, &sample_schema(conn, sample_set)?) {
+            eprintln!("{}: {}, skipped", query.get_name(), error);
+            continue;
+        }
+        let select = sql_query.get_select();
+        let where_conditions = select.get_where_clause().clone().unwrap_or_default();
+        let (join_conditions, selection_conditions) = separate_conditions(where_conditions);
+        // subqueries are answered once per query, their time counts in every evaluation
+        let subquery_start = Instant::now();
+        let selection_conditions = resolve_subqueries(conn, selection_conditions)?;
+        let subquery_time = subquery_start.elapsed().as_secs_f64();
+
+        for &bootstrap_size in bootstrap_sizes {
+            let evaluation_start = Instant::now();
+            let sample_results = query_result(
+                conn,
+                sample_set,
+                select,
+                &join_conditions,
+                &selection_conditions,
+                evaluator,
+            )?;
+            let evaluation_time = subquery_time + evaluation_start.elapsed().as_secs_f64();
+
+            let estimates = match estimate(
+                &sample_results,
+                select,
+                sample_set,
+                join_level(&join_conditions)? + 1,
+                bootstrap_size,
+            ) {
+                Ok(estimates) => estimates,
+                Err(error) => {
+                    eprintln!(
+                        "{} on {}%: {}, skipped",
+                        query.get_name(),
+                        sample_fraction * 100.0,
+                        error
+                    );
+                    continue;
+                }
+            };
+
+            // the aggregates share the evaluation and the bootstrap, so their times repeat
+            for estimate in &estimates {
+                let record = BenchmarkRecord {
+                    name: query.get_name().to_string(),
+                    query: query.get_sql().to_string(),
+                    aggregate: estimate.get_aggregate().to_string(),
+                    group: estimate.get_group().map(str::to_string),
+                    sample_fraction,
+                    sample_rows: sample_set.get_rows(),
+                    bootstrap_size,
+                    sampling_time,
+                    evaluation_time,
+                    bootstrap_time: estimate.get_bootstrap_time(),
+                    estimate: estimate.get_estimate(),
+                    ci_relative_width: estimate.relative_error(),
+                    ground_truth: exact_answer
+                        .as_ref()
+                        .and_then(|exact| exact.ground_truth.value_of(estimate)),
+                    exact_time: exact_answer.as_ref().map(|exact| exact.exact_time),
+                };
+                info!(
+                    "{} {}{} on {}% / {} resamples: {:.4}s evaluation, {:.4}s bootstrap, relative error {}",
+                    query.get_name(),
+                    record.aggregate,
+                    record
+                        .group
+                        .as_ref()
+                        .map_or(String::new(), |group| format!(" [{}]", group)),
+                    sample_fraction * 100.0,
+                    bootstrap_size,
+                    record.evaluation_time,
+                    record.bootstrap_time,
+                    record
+                        .relative_error()
+                        .map_or("n/a".to_string(), |error| format!("{:.4}", error))
+                );
+                records.push(record);
+            }
+        }
+    }
+
+    Ok(records)
+}
+
+fn optional(value: Option<f64>) -> String {
+    value.map_or(String::new(), |value| value.to_string())
+}
+
+fn write_csv(records: &[BenchmarkRecord], path: &Path) -> Result<(), AqpError> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(CSV_HEADER)?;
+    for record in records {
+        writer.write_record([
+            record.name.clone(),
+            record.query.clone(),
+            record.aggregate.clone(),
+            record.group.clone().unwrap_or_default(),
+            record.sample_fraction.to_string(),
+            record.sample_rows.to_string(),
+            record.bootstrap_size.to_string(),
+            record.sampling_time.to_string(),
+            record.evaluation_time.to_string(),
+            record.bootstrap_time.to_string(),
+            record.query_time().to_string(),
+            record.estimate.to_string(),
+            record.ci_relative_width.to_string(),
+            optional(record.ground_truth),
+            optional(record.exact_time),
+            optional(record.relative_error()),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_json(records: &[BenchmarkRecord], path: &Path) -> Result<(), AqpError> {
+    let objects: Vec<String> = records
+        .iter()
+        .map(|record| {
+            let values = [
+                json_string(&record.name),
+                json_string(&record.query),
+                json_string(&record.aggregate),
+                record
+                    .group
+                    .as_ref()
+                    .map_or("null".to_string(), |group| json_string(group)),
+                json_number(Some(record.sample_fraction)),
+                record.sample_rows.to_string(),
+                record.bootstrap_size.to_string(),
+                json_number(Some(record.sampling_time)),
+                json_number(Some(record.evaluation_time)),
+                json_number(Some(record.bootstrap_time)),
+                json_number(Some(record.query_time())),
+                json_number(Some(record.estimate)),
+                json_number(Some(record.ci_relative_width)),
+                json_number(record.ground_truth),
+                json_number(record.exact_time),
+                json_number(record.relative_error()),
+            ];
+            format!("  {}", json_object(&CSV_HEADER, values.to_vec()))
+        })
+        .collect();
+
+    std::fs::write(path, format!("[\n{}\n]\n", objects.join(",\n")))?;
+    Ok(())
+}
+
+//write the records as json when the file name ends with .json, as csv otherwise
+pub fn write_benchmark(records: &[BenchmarkRecord], path: &Path) -> Result<(), AqpError> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("json") => write_json(records, path),
+        _ => write_csv(records, path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::{create_sample_ladder, load_sample_catalog, sample_version};
+    use crate::test_support;
+
+    fn queries() -> Vec<WorkloadQuery> {
+        vec![
+            WorkloadQuery::new(
+                "big_orders".to_string(),
+                "select sum(l_quantity) from lineitem, orders where l_orderkey = o_orderkey and o_totalprice > 100000".to_string(),
+            ),
+            WorkloadQuery::new(
+                "broken".to_string(),
+                "select count(*) from lineitem where".to_string(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn every_query_is_answered_on_every_fraction_with_every_bootstrap_size() {
+        let conn = test_support::database(3);
+        let records = run_benchmark(
+            &conn,
+            &queries(),
+            &[0.1, 0.5],
+            &[20, 40],
+            Evaluator::Sql,
+            Some(3),
+        )
+        .unwrap();
+
+        // the broken query is skipped, the other gets one record per setting
+        assert_eq!(records.len(), 2 * 2);
+        let settings: Vec<(f64, usize)> = records
+            .iter()
+            .map(|record| (record.sample_fraction, record.bootstrap_size))
+            .collect();
+        assert_eq!(settings, vec![(0.1, 20), (0.1, 40), (0.5, 20), (0.5, 40)]);
+        for record in &records {
+            assert_eq!(record.name, "big_orders");
+            assert!(record.ground_truth.is_some() && record.exact_time.is_some());
+            assert!(record.relative_error().unwrap().is_finite());
+            assert_eq!(
+                record.query_time(),
+                record.evaluation_time + record.bootstrap_time
+            );
+        }
+        // a larger fraction gives a larger sample
+        assert!(records[0].sample_rows < records[2].sample_rows);
+    }
+
+    #[test]
+    fn sweep_leaves_the_samples_in_the_catalog_alone() {
+        let conn = test_support::database(3);
+        let ladder = create_sample_ladder(&conn, &[0.05, 0.2], Some(3)).unwrap();
+        let version = sample_version(&conn).unwrap();
+        let tables = |conn: &Connection| -> Vec<String> {
+            let mut stmt = conn
+                .prepare("SELECT name FROM sqlite_master WHERE name LIKE 's%_sample%' ORDER BY 1")
+                .unwrap();
+            stmt.query_map([], |row| row.get(0))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap()
+        };
+        let before = tables(&conn);
+
+        run_benchmark(&conn, &queries(), &[0.1], &[20], Evaluator::Sql, Some(3)).unwrap();
+        assert_eq!(tables(&conn), before);
+        assert_eq!(sample_version(&conn).unwrap(), version);
+        let catalog: Vec<String> = load_sample_catalog(&conn)
+            .unwrap()
+            .iter()
+            .map(|set| set.get_suffix().to_string())
+            .collect();
+        let suffixes: Vec<String> = ladder
+            .iter()
+            .map(|set| set.get_suffix().to_string())
+            .collect();
+        assert_eq!(catalog, suffixes);
+    }
+
+    #[test]
+    fn records_are_written_as_csv_or_json_by_extension() {
+        let conn = test_support::database(3);
+        let records =
+            run_benchmark(&conn, &queries(), &[0.2], &[20], Evaluator::Rust, Some(3)).unwrap();
+        let dir = std::env::temp_dir().join(format!("aqprius_benchmark_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        write_benchmark(&records, &dir.join("runtime.csv")).unwrap();
+        let csv = std::fs::read_to_string(dir.join("runtime.csv")).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 1 + records.len());
+        assert_eq!(lines[0], CSV_HEADER.join(","));
+        assert!(lines[1].starts_with("big_orders,"));
+
+        write_benchmark(&records, &dir.join("runtime.json")).unwrap();
+        let json = std::fs::read_to_string(dir.join("runtime.json")).unwrap();
+        assert!(json.starts_with("[\n  {") && json.ends_with("}\n]\n"));
+        assert_eq!(
+            json.matches("\"name\": \"big_orders\"").count(),
+            records.len()
+        );
+        assert!(!json.contains("\"ground_truth\": null"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,57 +1,53 @@
 use rand::prelude::*;
-use rand::seq::SliceRandom;
 use rayon::prelude::*;
 use std::time::Instant;
-/*creating a resampling function for generic datatype.
-This function takes a reference to a vector of generic datatype and returns a vector of generic datatype.
+/*creating a resampling function for row indices.
+This function draws size row indices out of 0..size with replacement, so that every
+aggregate computed from the same indices sees the same resample.
 */
-pub fn random_sample_with_replacement(sample: &[i64], size: usize) -> Vec<i64> {
-    let resampled: Vec<i64> = (0..size)
-        .into_par_iter()
-        .map(|_| {
-            let mut rng = thread_rng();
-            *sample.choose(&mut rng).unwrap()
-        })
-        .collect();
-
-    resampled
+pub fn resample_indices(size: usize) -> Vec<usize> {
+    let mut rng = thread_rng();
+    (0..size).map(|_| rng.gen_range(0..size)).collect()
 }
 
-// //generating bootstrapping sample groundtruth using simple random sampling with replacement
-pub fn bootstrap_sums(data: &[i64], num_resamples: usize, sample_fraction: f64) -> (Vec<i64>, f64) {
+//generating bootstrapping estimates of several statistics computed from the same resampled
+//row indices, one vector of statistics per resample
+pub fn bootstrap_statistics<F>(
+    size: usize,
+    num_resamples: usize,
+    statistics: F,
+) -> (Vec<Vec<f64>>, f64)
+where
+    F: Fn(&[usize]) -> Vec<f64> + Sync,
+{
     let start_time = Instant::now();
-    let bootstrap_sums: Vec<i64> = (0..num_resamples)
+    let estimates: Vec<Vec<f64>> = (0..num_resamples)
         .into_par_iter()
-        .map(|_| {
-            let resampled_data = random_sample_with_replacement(&data, data.len());
-            let sum: i64 = resampled_data.iter().sum();
-            (sum as f64 / sample_fraction) as i64
-        })
+        .map(|_| statistics(&resample_indices(size)))
         .collect();
 
     let elapsed_time = start_time.elapsed().as_secs_f64();
 
-    (bootstrap_sums, elapsed_time)
+    (estimates, elapsed_time)
 }
 
 //calculating mean of bootstrapping ground truth sample
-pub fn calculate_mean(bootstrap_sums: &[i64], bootstrap_size: usize) -> f64 {
-    let sum: i64 = bootstrap_sums.par_iter().sum();
-    sum as f64 / bootstrap_size as f64
+pub fn calculate_mean(bootstrap_sums: &[f64], bootstrap_size: usize) -> f64 {
+    let sum: f64 = bootstrap_sums.par_iter().sum();
+    sum / bootstrap_size as f64
 }
 
 //calculating standard deviation of bootstrapping ground truth sample
-pub fn calculate_variance(bootstrap_sums: &[i64], bootstrap_size: usize) -> f64 {
+pub fn calculate_variance(bootstrap_sums: &[f64], bootstrap_size: usize) -> f64 {
     let mean = calculate_mean(bootstrap_sums, bootstrap_size);
     let variance: f64 = bootstrap_sums
         .par_iter()
         .map(|&value| {
-            let diff = value as f64 - mean;
+            let diff = value - mean;
             diff * diff
         })
         .sum::<f64>()
-        / bootstrap_size as f64
-        - 1.0;
+        / (bootstrap_size as f64 - 1.0);
 
     variance.sqrt()
 }
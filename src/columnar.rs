@@ -0,0 +1,432 @@
+use crate::error::AqpError;
+use crate::expression::{Expr, Value};
+use crate::parser::Where;
+use crate::pushdown::{is_text_type, table_columns};
+use crate::samples::SampleResult;
+use rayon::prelude::*;
+use rusqlite::{params, types::ValueRef, Connection, Result};
+use std::collections::HashMap;
+use std::time::Instant;
+
+const WORD_BITS: usize = 64;
+
+//only the columns referenced by the query, loaded once as typed arrays
+pub struct ColumnarSample {
+    rows: usize,
+    columns: HashMap<String, Vec<f64>>,
+    //text columns, with the numbers and dates they hold already parsed
+    text_columns: HashMap<String, Vec<Value>>,
+}
+
+impl ColumnarSample {
+    pub fn new(rows: usize, columns: HashMap<String, Vec<f64>>) -> Self {
+        ColumnarSample {
+            rows,
+            columns,
+            text_columns: HashMap::new(),
+        }
+    }
+
+    pub fn with_text_columns(mut self, text_columns: HashMap<String, Vec<Value>>) -> Self {
+        self.text_columns = text_columns;
+        self
+    }
+
+    pub fn get_rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn get_column(&self, name: &str) -> Option<&Vec<f64>> {
+        self.columns.get(name)
+    }
+
+    pub fn get_text_column(&self, name: &str) -> Option<&Vec<Value>> {
+        self.text_columns.get(name)
+    }
+}
+
+//same conversion as the hashmap path: numbers as they are, text parsed as f64 or 0.0
+fn value_to_f64(value: ValueRef) -> f64 {
+    match value {
+        ValueRef::Integer(i) => i as f64,
+        ValueRef::Real(f) => f,
+        ValueRef::Text(t) => std::str::from_utf8(t)
+            .ok()
+            .and_then(|s| s.parse::<f64>().ok())
+            .unwrap_or(0.0),
+        _ => 0.0,
+    }
+}
+
+//columns referenced by the selection conditions and the aggregates
+pub fn referenced_columns(conditions: &[Where], arguments: &[Option<&Expr>]) -> Vec<String> {
+    let mut names: Vec<String> = conditions
+        .iter()
+        .flat_map(|condition| {
+            let mut columns = condition.get_left_expr().columns();
+            columns.extend(condition.get_right_expr().columns());
+            columns
+        })
+        .chain(arguments.iter().flatten().flat_map(|argument| argument.columns()))
+        .map(|(_, column)| column.to_string())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+//load the columns used by the selection conditions and the aggregates from a sample table
+pub fn fetch_columnar_sample(
+    conn: &Connection,
+    table: &str,
+    conditions: &[Where],
+    arguments: &[Option<&Expr>],
+) -> Result<ColumnarSample> {
+    let table_columns = table_columns(conn, table)?;
+
+    let names: Vec<String> = referenced_columns(conditions, arguments)
+        .into_iter()
+        .filter(|name| table_columns.contains_key(name))
+        .collect();
+
+    let projection = if names.is_empty() {
+        "1".to_string()
+    } else {
+        names.join(", ")
+    };
+
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM {}", projection, table))?;
+    let mut rows = stmt.query(params![])?;
+
+    // text columns keep their values, like the hashmap sample rows
+    let text: Vec<bool> = names
+        .iter()
+        .map(|name| is_text_type(&table_columns[name]))
+        .collect();
+    let mut arrays: Vec<Vec<f64>> = vec![Vec::new(); names.len()];
+    let mut text_arrays: Vec<Vec<Value>> = vec![Vec::new(); names.len()];
+    let mut row_count = 0;
+    while let Some(row) = rows.next()? {
+        for (i, is_text) in text.iter().enumerate() {
+            match (is_text, row.get_ref(i)?) {
+                (true, ValueRef::Null) => text_arrays[i].push(Value::Null),
+                (true, value) => text_arrays[i].push(Value::parse(value.as_str()?)),
+                (false, value) => arrays[i].push(value_to_f64(value)),
+            }
+        }
+        row_count += 1;
+    }
+
+    let mut columns = HashMap::new();
+    let mut text_columns = HashMap::new();
+    for (((name, is_text), array), text_array) in
+        names.into_iter().zip(text).zip(arrays).zip(text_arrays)
+    {
+        if is_text {
+            text_columns.insert(name, text_array);
+        } else {
+            columns.insert(name, array);
+        }
+    }
+
+    Ok(ColumnarSample::new(row_count, columns).with_text_columns(text_columns))
+}
+
+//selection bitmap with one bit per sample row, all rows selected
+fn full_bitmap(rows: usize) -> Vec<u64> {
+    let mut bitmap = vec![u64::MAX; rows.div_ceil(WORD_BITS)];
+    if !rows.is_multiple_of(WORD_BITS) {
+        if let Some(last) = bitmap.last_mut() {
+            *last = (1u64 << (rows % WORD_BITS)) - 1;
+        }
+    }
+    bitmap
+}
+
+//clear the bits of the rows failing one condition, a word of 64 rows at a time
+fn apply_condition(bitmap: &mut [u64], column: &[f64], operator: &str, value: f64) {
+    bitmap
+        .par_iter_mut()
+        .zip(column.par_chunks(WORD_BITS))
+        .for_each(|(word, chunk)| {
+            let mut mask = 0u64;
+            for (bit, &column_value) in chunk.iter().enumerate() {
+                let passed = match operator {
+                    "=" => column_value == value,
+                    "<>" => column_value != value,
+                    "<" => column_value < value,
+                    "<=" => column_value <= value,
+                    ">" => column_value > value,
+                    ">=" => column_value >= value,
+                    _ => false,
+                };
+                mask |= (passed as u64) << bit;
+            }
+            *word &= mask;
+        });
+}
+
+//clear the bits of the rows of a text column other than the given text
+fn apply_text_condition(bitmap: &mut [u64], column: &[Value], text: &str) {
+    bitmap
+        .par_iter_mut()
+        .zip(column.par_chunks(WORD_BITS))
+        .for_each(|(word, chunk)| {
+            let mut mask = 0u64;
+            for (bit, column_value) in chunk.iter().enumerate() {
+                let passed = matches!(column_value, Value::Text(value) if value == text);
+                mask |= (passed as u64) << bit;
+            }
+            *word &= mask;
+        });
+}
+
+//clear the bits of the rows failing any other condition, evaluated row by row
+fn apply_row_condition(bitmap: &mut [u64], sample: &ColumnarSample, condition: &Where) {
+    let passed: Vec<bool> = (0..sample.get_rows())
+        .into_par_iter()
+        .map(|i| condition.evaluate(&|column: &str| column_value(sample, column, i)))
+        .collect();
+    for (word, chunk) in bitmap.iter_mut().zip(passed.chunks(WORD_BITS)) {
+        let mask = chunk
+            .iter()
+            .enumerate()
+            .fold(0u64, |mask, (bit, &passed)| mask | ((passed as u64) << bit));
+        *word &= mask;
+    }
+}
+
+//value of a column in row i of a columnar sample
+fn column_value(sample: &ColumnarSample, column: &str, i: usize) -> Value {
+    match (sample.get_column(column), sample.get_text_column(column)) {
+        (Some(values), _) => Value::Number(values[i]),
+        (None, Some(values)) => values[i].clone(),
+        (None, None) => Value::Null,
+    }
+}
+
+//value of an expression for every row of a columnar sample as a key, None for null
+pub fn get_columnar_keys(sample: &ColumnarSample, argument: &Expr) -> Vec<Option<String>> {
+    (0..sample.get_rows())
+        .into_par_iter()
+        .map(|i| {
+            argument
+                .evaluate(&|column: &str| column_value(sample, column, i))
+                .key()
+        })
+        .collect()
+}
+
+//evaluate the selection conditions column by column and return 1 or 0 per sample row
+pub fn get_columnar_query_result(sample: &ColumnarSample, conditions: &[Where]) -> Vec<i64> {
+    let mut bitmap = full_bitmap(sample.get_rows());
+
+    for condition in conditions {
+        let column = match condition.get_left_column() {
+            Some(column) if condition.is_simple() => column,
+            _ => {
+                apply_row_condition(&mut bitmap, sample, condition);
+                continue;
+            }
+        };
+        match (
+            sample.get_column(column),
+            sample.get_text_column(column),
+            condition.get_value(),
+            condition.get_text(),
+        ) {
+            (Some(values), _, Some(value), _) => {
+                apply_condition(&mut bitmap, values, condition.get_operator(), value)
+            }
+            (_, Some(values), _, Some(text)) => apply_text_condition(&mut bitmap, values, text),
+            // a column missing from the sample selects no row, like in get_query_result
+            (None, None, _, _) => bitmap.iter_mut().for_each(|word| *word = 0),
+            // a number compared with a text column, or text with a numeric one
+            _ => apply_row_condition(&mut bitmap, sample, condition),
+        }
+    }
+
+    (0..sample.get_rows())
+        .into_par_iter()
+        .map(|i| ((bitmap[i / WORD_BITS] >> (i % WORD_BITS)) & 1) as i64)
+        .collect()
+}
+
+//selection flags of a columnar sample, evaluated once, with the values of every aggregate
+pub fn get_columnar_sample_result(
+    sample: &ColumnarSample,
+    conditions: &[Where],
+    arguments: &[Option<&Expr>],
+) -> Vec<SampleResult> {
+    let selected = get_columnar_query_result(sample, conditions);
+    arguments
+        .iter()
+        .map(|argument| {
+            let values = match argument {
+                Some(Expr::Column(_, column)) if sample.get_text_column(column).is_none() => sample
+                    .get_column(column)
+                    .cloned()
+                    .unwrap_or_else(|| vec![0.0; sample.get_rows()]),
+                Some(argument) => (0..sample.get_rows())
+                    .into_par_iter()
+                    .map(|i| {
+                        argument
+                            .evaluate(&|column: &str| column_value(sample, column, i))
+                            .to_f64()
+                    })
+                    .collect(),
+                None => Vec::new(),
+            };
+            SampleResult::new(selected.clone(), values)
+        })
+        .collect()
+}
+
+//first row where the columnar evaluator does not give what the hashmap one gives, as an error
+//naming both values
+fn check_agreement(
+    table: &str,
+    hashmap_result: &[SampleResult],
+    columnar_result: &[SampleResult],
+) -> std::result::Result<(), AqpError> {
+    for (aggregate, (hashmap, columnar)) in hashmap_result.iter().zip(columnar_result).enumerate() {
+        let selected = hashmap.selected.iter().zip(&columnar.selected);
+        if let Some((row, (h, c))) = selected.enumerate().find(|(_, (h, c))| h != c) {
+            return Err(AqpError::Statistical(format!(
+                "the columnar evaluator selects {} for row {} of {} where the hashmap evaluator selects {}",
+                c, row, table, h
+            )));
+        }
+        let values = hashmap.values.iter().zip(&columnar.values);
+        if let Some((row, (h, c))) = values.enumerate().find(|(_, (h, c))| h != c) {
+            return Err(AqpError::Statistical(format!(
+                "the columnar evaluator gives {} for aggregate {} in row {} of {} where the hashmap evaluator gives {}",
+                c,
+                aggregate + 1,
+                row,
+                table,
+                h
+            )));
+        }
+    }
+    Ok(())
+}
+
+//time the hashmap evaluator against the columnar one on the same sample table, failing when
+//they disagree
+pub fn benchmark_evaluators<F>(
+    conn: &Connection,
+    table: &str,
+    conditions: &[Where],
+    arguments: &[Option<&Expr>],
+    hashmap_query_result: F,
+) -> std::result::Result<Vec<SampleResult>, AqpError>
+where
+    F: Fn() -> Result<Vec<SampleResult>>,
+{
+    let hashmap_start = Instant::now();
+    let hashmap_result = hashmap_query_result()?;
+    let hashmap_time = hashmap_start.elapsed().as_secs_f64();
+
+    let columnar_start = Instant::now();
+    let sample = fetch_columnar_sample(conn, table, conditions, arguments)?;
+    let load_time = columnar_start.elapsed().as_secs_f64();
+    let columnar_result = get_columnar_sample_result(&sample, conditions, arguments);
+    let columnar_time = columnar_start.elapsed().as_secs_f64();
+
+    check_agreement(table, &hashmap_result, &columnar_result)?;
+
+    info!(
+        "Hashmap evaluator: {:.4}s\nColumnar evaluator: {:.4}s (load {:.4}s, evaluate {:.4}s)\nSpeedup: {:.1}x over {} rows",
+        hashmap_time,
+        columnar_time,
+        load_time,
+        columnar_time - load_time,
+        hashmap_time / columnar_time,
+        sample.get_rows()
+    );
+
+    Ok(columnar_result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::hashmap_query_result;
+    use crate::parser::Aggregate;
+    use crate::test_support;
+
+    fn database() -> (Connection, String) {
+        let (conn, sample_set) = test_support::sampled_database(0.1, 5);
+        (conn, sample_set.table(1))
+    }
+
+    fn column(name: &str) -> Expr {
+        Expr::Column(None, name.to_string())
+    }
+
+    #[test]
+    fn columnar_evaluator_agrees_with_the_hashmap_one() {
+        let (conn, table) = database();
+        let conditions = [Where::new(
+            column("l_quantity"),
+            Expr::Number(20.0),
+            ">".to_string(),
+        )];
+        let sum = Aggregate::Sum(column("l_extendedprice"));
+        let argument = column("l_extendedprice");
+
+        let result = benchmark_evaluators(&conn, &table, &conditions, &[Some(&argument)], || {
+            hashmap_query_result(&conn, 1, &table, &conditions, &[&sum], &[])
+        })
+        .unwrap();
+        let selected = result[0].selected.iter().filter(|&&s| s == 1).count();
+        assert!(selected > 0 && selected < result[0].selected.len());
+    }
+
+    #[test]
+    fn every_comparison_and_text_equality_agree_with_the_hashmap_evaluator() {
+        let (conn, table) = database();
+        let sum = Aggregate::Sum(column("l_extendedprice"));
+        let argument = column("l_extendedprice");
+        for (left, operator, right) in [
+            ("l_quantity", "=", Expr::Number(20.0)),
+            ("l_quantity", "<>", Expr::Number(20.0)),
+            ("l_quantity", "<=", Expr::Number(20.0)),
+            ("l_quantity", ">=", Expr::Number(20.0)),
+            ("l_shipmode", "=", Expr::Text("AIR".to_string())),
+        ] {
+            let conditions = [Where::new(column(left), right, operator.to_string())];
+            assert!(conditions[0].is_simple());
+            let result =
+                benchmark_evaluators(&conn, &table, &conditions, &[Some(&argument)], || {
+                    hashmap_query_result(&conn, 1, &table, &conditions, &[&sum], &[])
+                })
+                .unwrap();
+            assert!(result[0].selected.contains(&1), "{} {}", left, operator);
+        }
+    }
+
+    #[test]
+    fn disagreeing_evaluators_are_an_error_naming_both_values() {
+        let (conn, table) = database();
+        let argument = column("l_quantity");
+        let sample = fetch_columnar_sample(&conn, &table, &[], &[Some(&argument)]).unwrap();
+        let rows = sample.get_rows();
+
+        let none_selected = || Ok(vec![SampleResult::new(vec![0; rows], Vec::new())]);
+        let error = benchmark_evaluators(&conn, &table, &[], &[None], none_selected).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("cannot estimate: the columnar evaluator selects 1 for row 0 of {} where the hashmap evaluator selects 0", table)
+        );
+
+        let wrong_values = || Ok(vec![SampleResult::new(vec![1; rows], vec![-1.0; rows])]);
+        let error =
+            benchmark_evaluators(&conn, &table, &[], &[Some(&argument)], wrong_values).unwrap_err();
+        assert!(matches!(error, AqpError::Statistical(_)));
+        assert!(error
+            .to_string()
+            .contains("where the hashmap evaluator gives -1"));
+    }
+}
@@ -1,6 +1,9 @@
 use rand::seq::IteratorRandom;
-use rusqlite::{params, Connection};
-use std::collections::HashMap;
+use rusqlite::{params, types::ValueRef, Connection, Row};
+use std::collections::{HashMap, HashSet};
+
+use crate::error::AqpError;
+use crate::expression::Value;
 
 //s*1 and the lineitem are same (s*1 is the sample(SRSWOR) of lineitem)
 #[derive(Clone, Debug)]
@@ -70,23 +73,80 @@ pub fn create_sample(
         .cloned()
         .choose_multiple(&mut rng, sample_size);
 
-    // Close the database connection
-    drop(stmt);
-    drop(conn);
-
     Ok(sample)
 }
 
-//Function to get the groundtruth
-pub fn groundtruth(conn: &Connection, query: &str) -> Result<i64, Box<dyn std::error::Error>> {
+//Function to get the groundtruth of every aggregate of the select list, the columns of the
+//first row of the query
+pub fn groundtruths(conn: &Connection, query: &str) -> Result<Vec<f64>, AqpError> {
+    let mut stmt = conn.prepare(query)?;
+    let columns = stmt.column_count();
+    let mut rows = stmt.query(params![])?;
+
+    match rows.next()? {
+        Some(row) => (0..columns)
+            .map(|i| Ok(row.get::<_, Option<f64>>(i)?.unwrap_or(0.0)))
+            .collect(),
+        None => Ok(vec![0.0; columns]),
+    }
+}
+
+//keys of a group and the exact values of its aggregates
+pub type GroupRow = (Vec<Option<String>>, Vec<f64>);
+
+//Function to get the groundtruth of a grouped query, one row per group: the keys of the
+//group (the first key_count columns, as Value::key gives them for the sample rows) and the
+//aggregates that follow
+pub fn grouped_groundtruths(
+    conn: &Connection,
+    query: &str,
+    key_count: usize,
+) -> Result<Vec<GroupRow>, AqpError> {
     let mut stmt = conn.prepare(query)?;
+    let columns = stmt.column_count();
     let mut rows = stmt.query(params![])?;
 
-    if let Some(row) = rows.next()? {
-        return Ok(row.get(0)?);
+    let mut groups = Vec::new();
+    while let Some(row) = rows.next()? {
+        let keys = (0..key_count)
+            .map(|i| column_key(row, i))
+            .collect::<Result<Vec<Option<String>>, rusqlite::Error>>()?;
+        let values = (key_count..columns)
+            .map(|i| Ok(row.get::<_, Option<f64>>(i)?.unwrap_or(0.0)))
+            .collect::<Result<Vec<f64>, AqpError>>()?;
+        groups.push((keys, values));
     }
+    Ok(groups)
+}
 
-    Ok(0)
+//value of column i of a result row as Value::key gives it for the sample rows
+fn column_key(row: &Row, i: usize) -> Result<Option<String>, rusqlite::Error> {
+    let text = match row.get_ref(i)? {
+        ValueRef::Null => return Ok(None),
+        ValueRef::Integer(integer) => integer.to_string(),
+        ValueRef::Real(real) => real.to_string(),
+        ValueRef::Text(text) | ValueRef::Blob(text) => String::from_utf8_lossy(text).into_owned(),
+    };
+    Ok(Value::parse(&text).key())
+}
+
+//keys of the single column a subquery selects, nulls left out
+pub fn subquery_keys(conn: &Connection, query: &str) -> Result<HashSet<String>, AqpError> {
+    let mut stmt = conn.prepare(query)?;
+    if stmt.column_count() != 1 {
+        return Err(AqpError::Unsupported(format!(
+            "the subquery {} selects {} columns, in compares with one",
+            query,
+            stmt.column_count()
+        )));
+    }
+    let mut rows = stmt.query(params![])?;
+
+    let mut keys = HashSet::new();
+    while let Some(row) = rows.next()? {
+        keys.extend(column_key(row, 0)?);
+    }
+    Ok(keys)
 }
 
 //hashmap for S*1 Sample with SRSWOR
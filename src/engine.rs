@@ -0,0 +1,2282 @@
+//the approximate query engine: picks the sample of a query, evaluates its selection
+//conditions over it and turns the result into an estimate with a bootstrap confidence interval
+use rusqlite::Connection;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::{
+    bootstrap::{bootstrap_statistics, calculate_variance},
+    columnar::{
+        benchmark_evaluators, fetch_columnar_sample, get_columnar_keys, get_columnar_sample_result,
+    },
+    data_sampling::{groundtruths, grouped_groundtruths, subquery_keys},
+    error::AqpError,
+    expression::{Expr, Value},
+    ingest::ingest,
+    parquet_store::{export_samples, fetch_parquet_sample, load_parquet_catalog, sample_file},
+    parser::{
+        parse_sql_query, unsupported_join, Aggregate, OrderItem, SQLQuery, Select, TableRef, Where,
+    },
+    pushdown::{is_numeric_type, pushdown_query_result, table_columns},
+    samples::{
+        fetch_s1_sample, fetch_s2_sample, fetch_s3_sample, fetch_s4_sample, fetch_s5_sample,
+        get_expression_keys, get_expression_values, get_query_result, s1_sample_to_hashmap,
+        s2_sample_to_hashmap, s3_sample_to_hashmap, s4_sample_to_hashmap, s5_sample_to_hashmap,
+        SampleResult,
+    },
+    sampling::{
+        create_sample_ladder, create_sample_tables, create_seeded_sample_tables,
+        is_sample_fraction, load_sample_catalog, sample_version, SampleSet,
+    },
+    tpch_gen::generate_tpch,
+    validation::{sample_schema, validate_query, SAMPLE_TABLES},
+};
+
+//check whether a table exists in the main database
+pub fn table_exists(conn: &Connection, table: &str) -> Result<bool, rusqlite::Error> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?",
+        [table],
+        |row| row.get::<_, i64>(0),
+    )
+    .map(|count| count > 0)
+}
+
+//parse a query, reporting where the parser stopped on malformed or unsupported syntax
+pub fn parse_query(sql: &str) -> Result<SQLQuery, AqpError> {
+    let (rest, sql_query) = parse_sql_query(sql).map_err(|error| match &error {
+        nom::Err::Failure(failure) => match unsupported_join(failure.input) {
+            Some(join) => AqpError::Unsupported(format!(
+                "{} is not supported, the samples only answer inner joins of lineitem with orders, customer, nation and region",
+                join
+            )),
+            None => AqpError::from_nom(sql, error),
+        },
+        _ => AqpError::from_nom(sql, error),
+    })?;
+    if !rest.is_empty() {
+        return Err(AqpError::trailing_input(sql, rest));
+    }
+    Ok(sql_query)
+}
+
+//columns of the base tables stored as text, dates among them
+fn text_columns(conn: &Connection) -> Result<HashSet<String>, AqpError> {
+    let mut text_columns = HashSet::new();
+    for table in SAMPLE_TABLES {
+        for (column, column_type) in table_columns(conn, table)? {
+            if !is_numeric_type(&column_type) {
+                text_columns.insert(column);
+            }
+        }
+    }
+    Ok(text_columns)
+}
+
+//a query in the sqlite syntax of the base tables, for its exact answer
+pub fn exact_sql(conn: &Connection, sql: &str) -> Result<String, AqpError> {
+    let sql_query = parse_query(sql)?;
+    let text_columns = text_columns(conn)?;
+    Ok(sql_query.to_sql(&|column| text_columns.contains(column)))
+}
+
+//answer the subqueries of in conditions exactly over the base tables, so that every sample
+//row is kept or dropped by the same keys as its row of the base tables and the estimators
+//stay unbiased
+pub fn resolve_subqueries(
+    conn: &Connection,
+    conditions: Vec<Where>,
+) -> Result<Vec<Where>, AqpError> {
+    let text_columns = text_columns(conn)?;
+    conditions
+        .into_iter()
+        .map(|condition| match condition.get_right_expr() {
+            Expr::Subquery(subquery) => {
+                let sql = subquery.to_sql(&|column| text_columns.contains(column));
+                let keys = subquery_keys(conn, &sql).map_err(|error| match error {
+                    AqpError::Unsupported(_) => error,
+                    error => AqpError::Unsupported(format!(
+                        "the subquery {} is answered exactly over the base tables, which failed: {}",
+                        subquery, error
+                    )),
+                })?;
+                Ok(Where::new(
+                    condition.get_left_expr().clone(),
+                    Expr::Keys(Arc::new(keys)),
+                    condition.get_operator().to_string(),
+                ))
+            }
+            _ => Ok(condition),
+        })
+        .collect()
+}
+
+//exact answers of a query over the base tables, by group for a grouped query
+pub struct GroundTruth {
+    aggregates: Vec<String>,
+    groups: HashMap<Option<String>, Vec<f64>>,
+}
+
+impl GroundTruth {
+    //exact value of the aggregate and group of an estimate, None for a percentile of a grouped
+    //query, which sqlite does not answer (see Select::exact_functions)
+    pub fn value_of(&self, estimate: &Estimate) -> Option<f64> {
+        let column = self
+            .aggregates
+            .iter()
+            .position(|aggregate| aggregate == estimate.get_aggregate())?;
+        self.groups
+            .get(&estimate.group)
+            .map(|values| values[column])
+    }
+}
+
+//exact answer of a query over the base tables
+pub fn exact_answers(conn: &Connection, sql: &str) -> Result<GroundTruth, AqpError> {
+    let sql_query = parse_query(sql)?;
+    let select = sql_query.get_select();
+    let exact = exact_sql(conn, sql)?;
+    let groups = if select.is_grouped() {
+        grouped_groundtruths(conn, &exact, select.get_group_by().len())?
+            .into_iter()
+            .map(|(keys, values)| (Some(group_label(&keys)), values))
+            .collect()
+    } else {
+        HashMap::from([(None, groundtruths(conn, &exact)?)])
+    };
+    Ok(GroundTruth {
+        aggregates: select
+            .exact_functions()
+            .iter()
+            .map(|function| function.to_string())
+            .collect(),
+        groups,
+    })
+}
+
+//exact answer of a query the samples cannot approximate, `reason` saying why, as estimates
+//whose interval is the exact value: the aggregates of the select list for every group the
+//query returns, with having, order by and limit applied like for estimates. None when it
+//needs the percentile of a group, which sqlite does not compute
+pub fn exact_estimates(
+    conn: &Connection,
+    sql: &str,
+    reason: &str,
+) -> Result<Option<Vec<Estimate>>, AqpError> {
+    let start = Instant::now();
+    let sql_query = parse_query(sql)?;
+    let select = sql_query.get_select();
+    let aggregates = computed_aggregates(select);
+    let functions: Vec<&Aggregate> = aggregates.iter().collect();
+    let has_percentile = functions
+        .iter()
+        .flat_map(|function| function.base_aggregates())
+        .any(|base| matches!(base, Aggregate::Percentile(..)));
+    if select.is_grouped() && has_percentile {
+        return Ok(None);
+    }
+
+    let text_columns = text_columns(conn)?;
+    let exact = sql_query.aggregates_to_sql(&functions, &|column| text_columns.contains(column));
+    let (group_keys, values) = if select.is_grouped() {
+        let mut rows = grouped_groundtruths(conn, &exact, select.get_group_by().len())?;
+        // in the order of their keys, which result_groups expects
+        rows.sort_by(|(a, _), (b, _)| compare_keys(a, b));
+        let (group_keys, values): (Vec<_>, Vec<_>) = rows.into_iter().unzip();
+        (group_keys, values.concat())
+    } else {
+        (vec![Vec::new()], groundtruths(conn, &exact)?)
+    };
+    if group_keys.is_empty() {
+        return Ok(Some(Vec::new()));
+    }
+    let evaluation_time = start.elapsed().as_secs_f64();
+
+    let tables: Vec<&str> = select.get_table().iter().map(TableRef::get_name).collect();
+    let width = functions.len();
+    let mut estimates = Vec::new();
+    for group in result_groups(select, &group_keys, &values) {
+        for (i, function) in select.get_functions().iter().enumerate() {
+            let value = values[group * width + i];
+            estimates.push(Estimate {
+                aggregate: function.to_string(),
+                group: select.is_grouped().then(|| group_label(&group_keys[group])),
+                sample_table: tables.join(", "),
+                sample_fraction: 1.0,
+                sample_ground_truth: value,
+                std_error: None,
+                lower_bound: value,
+                upper_bound: value,
+                confidence_level: None,
+                membership_probability: None,
+                exact_reason: Some(reason.to_string()),
+                sample_rows: 0,
+                matched_rows: 0,
+                low_selectivity: false,
+                bootstrap_size: 0,
+                evaluation_time,
+                bootstrap_time: 0.0,
+                cached: false,
+            });
+        }
+    }
+    Ok(Some(estimates))
+}
+
+//estimates of the queries already answered, one row per estimate in the order they were
+//returned, with the normalized query and the settings they were computed with and the
+//version of the samples they come from
+fn create_query_cache(conn: &Connection) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS query_cache (
+            query TEXT NOT NULL,
+            settings TEXT NOT NULL,
+            position INTEGER NOT NULL,
+            version INTEGER NOT NULL,
+            aggregate TEXT NOT NULL,
+            grp TEXT,
+            sample_table TEXT NOT NULL,
+            sample_fraction REAL NOT NULL,
+            estimate REAL,
+            std_error REAL,
+            lower_bound REAL,
+            upper_bound REAL,
+            confidence_level REAL,
+            membership_probability REAL,
+            exact_reason TEXT,
+            sample_rows INTEGER NOT NULL,
+            matched_rows INTEGER NOT NULL,
+            low_selectivity INTEGER NOT NULL,
+            bootstrap_size INTEGER NOT NULL,
+            evaluation_time REAL NOT NULL,
+            bootstrap_time REAL NOT NULL,
+            PRIMARY KEY (query, settings, position)
+         )",
+        [],
+    )?;
+    Ok(())
+}
+
+//estimates cached for a query and its settings over the current samples, None when it was
+//not answered since they were last built
+pub fn cached_estimates(
+    conn: &Connection,
+    query: &str,
+    settings: &str,
+) -> Result<Option<Vec<Estimate>>, AqpError> {
+    create_query_cache(conn)?;
+    let mut stmt = conn.prepare(
+        "SELECT aggregate, grp, sample_table, sample_fraction, estimate, std_error, lower_bound,
+                upper_bound, confidence_level, membership_probability, exact_reason, sample_rows,
+                matched_rows, low_selectivity, bootstrap_size, evaluation_time, bootstrap_time,
+                position
+         FROM query_cache WHERE query = ? AND settings = ? AND version = ? ORDER BY position",
+    )?;
+    // sqlite stores NaN as null
+    let number = |value: Option<f64>| value.unwrap_or(f64::NAN);
+    let rows = stmt
+        .query_map(
+            rusqlite::params![query, settings, sample_version(conn)?],
+            |row| {
+                let position: i64 = row.get(17)?;
+                Ok((
+                    position,
+                    Estimate {
+                        aggregate: row.get(0)?,
+                        group: row.get(1)?,
+                        sample_table: row.get(2)?,
+                        sample_fraction: row.get(3)?,
+                        sample_ground_truth: number(row.get(4)?),
+                        std_error: row.get(5)?,
+                        lower_bound: number(row.get(6)?),
+                        upper_bound: number(row.get(7)?),
+                        confidence_level: row.get(8)?,
+                        membership_probability: row.get(9)?,
+                        exact_reason: row.get(10)?,
+                        sample_rows: row.get::<_, i64>(11)? as usize,
+                        matched_rows: row.get::<_, i64>(12)? as usize,
+                        low_selectivity: row.get(13)?,
+                        bootstrap_size: row.get::<_, i64>(14)? as usize,
+                        evaluation_time: row.get(15)?,
+                        bootstrap_time: row.get(16)?,
+                        cached: true,
+                    },
+                ))
+            },
+        )?
+        .collect::<Result<Vec<(i64, Estimate)>, rusqlite::Error>>()?;
+    if rows.is_empty() {
+        return Ok(None);
+    }
+    // skip the marker of an answer without estimates, see cache_estimates
+    Ok(Some(
+        rows.into_iter()
+            .filter(|(position, _)| *position >= 0)
+            .map(|(_, estimate)| estimate)
+            .collect(),
+    ))
+}
+
+//remember the estimates of a query and its settings over the current samples, replacing what
+//was cached for it and dropping what was cached over older samples
+pub fn cache_estimates(
+    conn: &Connection,
+    query: &str,
+    settings: &str,
+    estimates: &[Estimate],
+) -> Result<(), AqpError> {
+    create_query_cache(conn)?;
+    let version = sample_version(conn)?;
+    // rolled back when dropped on an error, so the connection stays usable
+    let transaction = conn.unchecked_transaction()?;
+    transaction.execute(
+        "DELETE FROM query_cache WHERE version <> ? OR (query = ? AND settings = ?)",
+        rusqlite::params![version, query, settings],
+    )?;
+    let mut stmt = transaction.prepare(
+        "INSERT INTO query_cache VALUES
+         (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )?;
+    for (position, estimate) in estimates.iter().enumerate() {
+        stmt.execute(rusqlite::params![
+            query,
+            settings,
+            position as i64,
+            version,
+            estimate.aggregate,
+            estimate.group,
+            estimate.sample_table,
+            estimate.sample_fraction,
+            estimate.sample_ground_truth,
+            estimate.std_error,
+            estimate.lower_bound,
+            estimate.upper_bound,
+            estimate.confidence_level,
+            estimate.membership_probability,
+            estimate.exact_reason,
+            estimate.sample_rows as i64,
+            estimate.matched_rows as i64,
+            estimate.low_selectivity,
+            estimate.bootstrap_size as i64,
+            estimate.evaluation_time,
+            estimate.bootstrap_time,
+        ])?;
+    }
+    // an answer without estimates, e.g. no group passing having, is remembered by a marker
+    // row at position -1 that cached_estimates skips
+    if estimates.is_empty() {
+        transaction.execute(
+            "INSERT INTO query_cache (query, settings, position, version, aggregate, sample_table,
+                sample_fraction, sample_rows, matched_rows, low_selectivity, bootstrap_size,
+                evaluation_time, bootstrap_time)
+             VALUES (?, ?, -1, ?, '', '', 0, 0, 0, 0, 0, 0, 0)",
+            rusqlite::params![query, settings, version],
+        )?;
+    }
+    drop(stmt);
+    transaction.commit()?;
+    Ok(())
+}
+
+//forget every cached estimate, e.g. when the base tables the exact answers come from change
+pub fn clear_query_cache(conn: &Connection) -> Result<(), AqpError> {
+    create_query_cache(conn)?;
+    conn.execute("DELETE FROM query_cache", [])?;
+    Ok(())
+}
+
+//function to seperate join_condtion and selection condition
+pub fn separate_conditions(where_conditions: Vec<Where>) -> (Vec<Where>, Vec<Where>) {
+    let join_conditions: Vec<Where> = where_conditions
+        .clone()
+        .into_iter()
+        .filter(is_sample_join)
+        .collect();
+
+    let selection_conditions: Vec<Where> = where_conditions
+        .into_iter()
+        .filter(|condition| !is_sample_join(condition))
+        .collect();
+
+    (join_conditions, selection_conditions)
+}
+
+//how the selection conditions are evaluated over a sample
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Evaluator {
+    // pushed down into sqlite, falling back to Rust when it cannot be expressed
+    Sql,
+    // row by row over the sample stored as hashmaps
+    Rust,
+    // column-wise selection bitmaps over the referenced columns only
+    Columnar,
+    // time Rust against Columnar on the same sample
+    Bench,
+}
+
+//estimate with 95% bootstrap confidence interval computed from one sample
+pub struct Estimate {
+    aggregate: String,
+    group: Option<String>,
+    sample_table: String,
+    sample_fraction: f64,
+    sample_ground_truth: f64,
+    std_error: Option<f64>,
+    lower_bound: f64,
+    upper_bound: f64,
+    confidence_level: Option<f64>,
+    membership_probability: Option<f64>,
+    //why the samples could not approximate the query, when it was answered exactly instead
+    exact_reason: Option<String>,
+    sample_rows: usize,
+    matched_rows: usize,
+    //fewer than MIN_MATCHED_ROWS sample rows match the selection (of the group)
+    low_selectivity: bool,
+    bootstrap_size: usize,
+    evaluation_time: f64,
+    bootstrap_time: f64,
+    //read back from the query cache instead of computed, see Engine::with_cache
+    cached: bool,
+}
+
+impl Estimate {
+    //getter methods for the Estimate struct
+    pub fn get_aggregate(&self) -> &str {
+        &self.aggregate
+    }
+
+    //keys of the group the estimate is for, None without group by
+    pub fn get_group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    pub fn get_sample_table(&self) -> &str {
+        &self.sample_table
+    }
+
+    pub fn get_sample_fraction(&self) -> f64 {
+        self.sample_fraction
+    }
+
+    pub fn get_estimate(&self) -> f64 {
+        self.sample_ground_truth
+    }
+
+    //None for count(distinct ...), whose bounds do not come from a standard error
+    pub fn get_std_error(&self) -> Option<f64> {
+        self.std_error
+    }
+
+    pub fn get_lower_bound(&self) -> f64 {
+        self.lower_bound
+    }
+
+    pub fn get_upper_bound(&self) -> f64 {
+        self.upper_bound
+    }
+
+    //None when the bounds are not a confidence interval, see distinct_estimate
+    pub fn get_confidence_level(&self) -> Option<f64> {
+        self.confidence_level
+    }
+
+    //share of the bootstrap resamples in which the group satisfies the having conditions and
+    //ranks within the limit, None when the query has neither
+    pub fn get_membership_probability(&self) -> Option<f64> {
+        self.membership_probability
+    }
+
+    //the reason an exact answer was given, None for an estimate over a sample
+    pub fn get_exact_reason(&self) -> Option<&str> {
+        self.exact_reason.as_deref()
+    }
+
+    pub fn get_sample_rows(&self) -> usize {
+        self.sample_rows
+    }
+
+    pub fn get_matched_rows(&self) -> usize {
+        self.matched_rows
+    }
+
+    //whether too few sample rows match for the interval to be trusted, see MIN_MATCHED_ROWS
+    pub fn is_low_selectivity(&self) -> bool {
+        self.low_selectivity
+    }
+
+    pub fn get_bootstrap_size(&self) -> usize {
+        self.bootstrap_size
+    }
+
+    pub fn get_evaluation_time(&self) -> f64 {
+        self.evaluation_time
+    }
+
+    pub fn get_bootstrap_time(&self) -> f64 {
+        self.bootstrap_time
+    }
+
+    pub fn is_cached(&self) -> bool {
+        self.cached
+    }
+
+    //whether the interval holds a value, e.g. the exact answer
+    pub fn contains(&self, value: f64) -> bool {
+        self.lower_bound <= value && value <= self.upper_bound
+    }
+
+    //distance from the estimate to the upper bound relative to the estimate
+    pub fn relative_error(&self) -> f64 {
+        if self.sample_ground_truth == 0.0 {
+            return f64::INFINITY;
+        }
+        (self.upper_bound - self.sample_ground_truth) / self.sample_ground_truth.abs()
+    }
+}
+
+//aggregates estimated for a query, the select list first, then both sides of every having
+//condition and, for a grouped query, the aggregates of order by and count(*), which tells
+//whether a group is in a resample at all
+pub fn computed_aggregates(select: &Select) -> Vec<Aggregate> {
+    let mut aggregates = select.get_functions().to_vec();
+    for condition in select.get_having() {
+        aggregates.push(condition.get_left().clone());
+        aggregates.push(condition.get_right().clone());
+    }
+    if select.is_grouped() {
+        for order_by in select.get_order_by() {
+            if let OrderItem::Aggregate(aggregate) = order_by.get_item() {
+                aggregates.push(aggregate.clone());
+            }
+        }
+        aggregates.push(Aggregate::Count);
+    }
+    aggregates
+}
+
+//order of two group keys as the values they hold: numbers as numbers, dates as dates and
+//nulls first, like sqlite sorts them
+fn compare_key(a: &Option<String>, b: &Option<String>) -> Ordering {
+    let value = |key: &Option<String>| key.as_deref().map_or(Value::Null, Value::parse);
+    let rank = |value: &Value| match value {
+        Value::Null => 0,
+        Value::Number(_) => 1,
+        Value::Date(_) => 2,
+        _ => 3,
+    };
+    let (a, b) = (value(a), value(b));
+    a.compare(&b).unwrap_or_else(|| rank(&a).cmp(&rank(&b)))
+}
+
+//order of the keys of two groups, key after key
+fn compare_keys(a: &[Option<String>], b: &[Option<String>]) -> Ordering {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| compare_key(a, b))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+//keys of a group as reports show them, e.g. `R, F`
+fn group_label(keys: &[Option<String>]) -> String {
+    let keys: Vec<&str> = keys
+        .iter()
+        .map(|key| key.as_deref().unwrap_or("null"))
+        .collect();
+    keys.join(", ")
+}
+
+//groups of the selected sample rows sorted by their keys: the group of every row (0 for the
+//rows not selected, which add nothing to any group) and the keys of every group. A query
+//without group by is a single group without keys, even when no row is selected
+fn find_groups(selected: &[i64], keys: &[SampleResult]) -> (Vec<usize>, Vec<Vec<Option<String>>>) {
+    if keys.is_empty() {
+        return (vec![0; selected.len()], vec![Vec::new()]);
+    }
+    let row_keys = |i: usize| -> Vec<Option<String>> {
+        keys.iter().map(|result| result.keys[i].clone()).collect()
+    };
+    let mut group_keys: Vec<Vec<Option<String>>> = (0..selected.len())
+        .filter(|&i| selected[i] != 0)
+        .map(row_keys)
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    group_keys.sort_by(|a, b| compare_keys(a, b));
+    let index: HashMap<&[Option<String>], usize> = group_keys
+        .iter()
+        .enumerate()
+        .map(|(group, keys)| (keys.as_slice(), group))
+        .collect();
+    let groups = (0..selected.len())
+        .map(|i| {
+            if selected[i] == 0 {
+                0
+            } else {
+                index[row_keys(i).as_slice()]
+            }
+        })
+        .collect();
+    (groups, group_keys)
+}
+
+//groups a query returns given the values of its computed aggregates for every group, group
+//after group: the groups with rows that satisfy the having conditions, sorted by order by
+//(and by their keys otherwise), up to the limit
+fn result_groups(
+    select: &Select,
+    group_keys: &[Vec<Option<String>>],
+    values: &[f64],
+) -> Vec<usize> {
+    let width = values.len() / group_keys.len();
+    let having_start = select.get_functions().len();
+    let order_start = having_start + 2 * select.get_having().len();
+    let group_values = |group: usize| &values[group * width..(group + 1) * width];
+
+    let mut groups: Vec<usize> = (0..group_keys.len())
+        .filter(|&group| {
+            let values = group_values(group);
+            // count(*) is the last computed aggregate of a grouped query
+            (!select.is_grouped() || values[width - 1] > 0.0)
+                && select
+                    .get_having()
+                    .iter()
+                    .enumerate()
+                    .all(|(i, condition)| {
+                        condition.holds(
+                            values[having_start + 2 * i],
+                            values[having_start + 2 * i + 1],
+                        )
+                    })
+        })
+        .collect();
+    if !select.is_grouped() {
+        return groups;
+    }
+
+    // the groups are in the order of their keys already and the sort is stable
+    groups.sort_by(|&a, &b| {
+        let mut position = order_start;
+        for order_by in select.get_order_by() {
+            let ordering = match order_by.get_item() {
+                OrderItem::Aggregate(_) => {
+                    position += 1;
+                    group_values(a)[position - 1].total_cmp(&group_values(b)[position - 1])
+                }
+                OrderItem::Key(key) => {
+                    let key = select
+                        .get_group_by()
+                        .iter()
+                        .position(|group_by| group_by.is_same(key))
+                        .unwrap_or(0);
+                    compare_key(&group_keys[a][key], &group_keys[b][key])
+                }
+            };
+            let ordering = if order_by.is_descending() {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+            if ordering.is_ne() {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    });
+    if let Some(limit) = select.get_limit() {
+        groups.truncate(limit);
+    }
+    groups
+}
+
+//estimate of every aggregate of the select list, for every group the query returns, from the
+//results of the base aggregates of computed_aggregates over the same sample followed by the
+//keys of every group by expression. the aggregates are bootstrapped together: every resample
+//draws its rows once and all the aggregates of all the groups are computed from them, so their
+//intervals are consistent with each other, the resampling is paid once, and arithmetic such
+//as sum(a) / sum(b) is computed from paired values of its operands instead of combining their
+//intervals. The same resamples give the share of them in which a returned group still
+//satisfies the having conditions and ranks within the limit, since ranking noisy estimates
+//can put the wrong groups on top
+pub fn estimate(
+    sample_results: &[SampleResult],
+    select: &Select,
+    sample_set: &SampleSet,
+    level: usize,
+    bootstrap_size: usize,
+) -> Result<Vec<Estimate>, AqpError> {
+    let sample_fraction = sample_set.get_fraction();
+    let sample_table = sample_set.table(level);
+    let aggregates = computed_aggregates(select);
+    let bases: Vec<&Aggregate> = aggregates
+        .iter()
+        .flat_map(Aggregate::base_aggregates)
+        .collect();
+    let (sample_results, key_results) = sample_results.split_at(bases.len());
+    // the aggregates share the selection, only their values differ
+    let selected = &sample_results[0].selected;
+    let matched_rows = selected.iter().filter(|&&flag| flag != 0).count();
+    let is_bootstrapped = |aggregate: &Aggregate| !matches!(aggregate, Aggregate::CountDistinct(_));
+    if selected.is_empty() {
+        return Err(AqpError::Statistical(format!(
+            "the sample {} has no rows",
+            sample_table
+        )));
+    }
+    if bootstrap_size < 2 && aggregates.iter().any(is_bootstrapped) {
+        return Err(AqpError::Statistical(
+            "at least 2 bootstrap resamples are needed for a standard error".to_string(),
+        ));
+    }
+    // without a matching row a count would be 0 with an interval of width 0, which says
+    // nothing about how rare the rows are in the table
+    if matched_rows == 0 {
+        let what = if select.is_grouped() {
+            "there is no group to estimate"
+        } else {
+            "the sample cannot tell the answer apart from none"
+        };
+        return Err(AqpError::Statistical(format!(
+            "no row of {} matches the selection, so {}",
+            sample_table, what
+        )));
+    }
+
+    let (groups, group_keys) = find_groups(selected, key_results);
+    let group_count = group_keys.len();
+    let width = aggregates.len();
+    let mut group_rows = vec![0; group_count];
+    for (&group, _) in groups.iter().zip(selected).filter(|(_, &flag)| flag != 0) {
+        group_rows[group] += 1;
+    }
+
+    //calulating the sample ground truth and resampling the query result with replacement
+    let contributions: Vec<Vec<f64>> = sample_results
+        .iter()
+        .map(SampleResult::contributions)
+        .collect();
+    let statistics = |indices: &[usize]| -> Vec<f64> {
+        let base_values: Vec<Vec<f64>> = bases
+            .iter()
+            .zip(sample_results)
+            .zip(&contributions)
+            .map(|((aggregate, sample_result), contributions)| {
+                statistic(
+                    aggregate,
+                    sample_result,
+                    contributions,
+                    sample_fraction,
+                    indices,
+                    &groups,
+                    group_count,
+                )
+            })
+            .collect();
+        let mut values = Vec::with_capacity(group_count * width);
+        for group in 0..group_count {
+            let mut group_values = base_values.iter().map(|values| values[group]);
+            values.extend(
+                aggregates
+                    .iter()
+                    .map(|aggregate| aggregate.evaluate(&mut group_values)),
+            );
+        }
+        values
+    };
+    let all_rows: Vec<usize> = (0..selected.len()).collect();
+    let sample_ground_truths = statistics(&all_rows);
+    let (bootstrap_samples, bootstrap_time) = if aggregates.iter().any(is_bootstrapped) {
+        bootstrap_statistics(selected.len(), bootstrap_size, statistics)
+    } else {
+        (Vec::new(), 0.0)
+    };
+
+    // how often a resample returns each group, when that can differ from the sample
+    let ranked =
+        !select.get_having().is_empty() || (select.is_grouped() && select.get_limit().is_some());
+    let mut memberships = vec![0; group_count];
+    if ranked {
+        for bootstrap_sample in &bootstrap_samples {
+            for group in result_groups(select, &group_keys, bootstrap_sample) {
+                memberships[group] += 1;
+            }
+        }
+    }
+
+    // z-score for 95% confidence level
+    let z_score = 1.960;
+
+    let returned = result_groups(select, &group_keys, &sample_ground_truths);
+    let mut estimates = Vec::with_capacity(returned.len() * select.get_functions().len());
+    for group in returned {
+        let label = select.is_grouped().then(|| group_label(&group_keys[group]));
+        let membership_probability =
+            ranked.then(|| memberships[group] as f64 / bootstrap_samples.len().max(1) as f64);
+        let in_group = match &label {
+            Some(label) => format!(" for the group {}", label),
+            None => String::new(),
+        };
+
+        let mut base = 0;
+        for (i, aggregate) in select.get_functions().iter().enumerate() {
+            let sample_result = &sample_results[base];
+            base += aggregate.base_aggregates().len();
+            let mut estimate = if !is_bootstrapped(aggregate) {
+                distinct_estimate(
+                    sample_result,
+                    aggregate,
+                    sample_set,
+                    &sample_table,
+                    &groups,
+                    group,
+                )
+            } else {
+                let index = group * width + i;
+                let sample_ground_truth = sample_ground_truths[index];
+                if !sample_ground_truth.is_finite() {
+                    return Err(AqpError::Statistical(format!(
+                        "{} divides by 0 on the sample {}{}",
+                        aggregate, sample_table, in_group
+                    )));
+                }
+                let bootstrap_sample: Vec<f64> = bootstrap_samples
+                    .iter()
+                    .map(|statistics| statistics[index])
+                    .collect();
+
+                let std_error = calculate_variance(&bootstrap_sample, bootstrap_size);
+                let cib = z_score * std_error;
+                let rows = if select.is_grouped() {
+                    group_rows[group]
+                } else {
+                    matched_rows
+                };
+                let (std_error, lower_bound, upper_bound) = match aggregate {
+                    Aggregate::Count if rows < MIN_MATCHED_ROWS => {
+                        wilson_interval(rows, selected.len(), sample_fraction, z_score)
+                    }
+                    _ => (
+                        std_error,
+                        sample_ground_truth - cib,
+                        sample_ground_truth + cib,
+                    ),
+                };
+
+                Estimate {
+                    aggregate: aggregate.to_string(),
+                    group: None,
+                    sample_table: sample_table.clone(),
+                    sample_fraction,
+                    sample_ground_truth,
+                    std_error: Some(std_error),
+                    lower_bound,
+                    upper_bound,
+                    confidence_level: Some(0.95),
+                    membership_probability: None,
+                    exact_reason: None,
+                    sample_rows: selected.len(),
+                    matched_rows: 0,
+                    low_selectivity: false,
+                    bootstrap_size,
+                    evaluation_time: 0.0,
+                    bootstrap_time,
+                    cached: false,
+                }
+            };
+            estimate.group = label.clone();
+            estimate.membership_probability = membership_probability;
+            estimate.matched_rows = if select.is_grouped() {
+                group_rows[group]
+            } else {
+                matched_rows
+            };
+            estimate.low_selectivity = estimate.matched_rows < MIN_MATCHED_ROWS;
+            estimates.push(estimate);
+        }
+    }
+
+    Ok(estimates)
+}
+
+//standard error and bounds of a count from the Wilson score interval of the share of the
+//`rows` sample rows that match, scaled up to the table. Over a handful of matching rows the
+//bootstrap interval of a count is too narrow, while the Wilson interval keeps its coverage for
+//small counts and never shrinks to a single value
+fn wilson_interval(matched: usize, rows: usize, sample_fraction: f64, z: f64) -> (f64, f64, f64) {
+    let (k, n) = (matched as f64, rows as f64);
+    let share = k / n;
+    let denominator = 1.0 + z * z / n;
+    let center = (share + z * z / (2.0 * n)) / denominator;
+    let half = z / denominator * (share * (1.0 - share) / n + z * z / (4.0 * n * n)).sqrt();
+    let scale = n / sample_fraction;
+    (
+        (n * share * (1.0 - share)).sqrt() / sample_fraction,
+        (center - half).max(0.0) * scale,
+        (center + half) * scale,
+    )
+}
+
+//value of an aggregate for each of the group_count groups over the sample rows drawn by the
+//indices, scaled up to the table for count and sum; `groups` gives the group of every row
+fn statistic(
+    aggregate: &Aggregate,
+    sample_result: &SampleResult,
+    contributions: &[f64],
+    sample_fraction: f64,
+    indices: &[usize],
+    groups: &[usize],
+    group_count: usize,
+) -> Vec<f64> {
+    let selected = &sample_result.selected;
+    match aggregate {
+        Aggregate::Count | Aggregate::Sum(_) => {
+            let mut sums = vec![0.0; group_count];
+            for &i in indices {
+                sums[groups[i]] += contributions[i];
+            }
+            sums.into_iter().map(|sum| sum / sample_fraction).collect()
+        }
+        Aggregate::Avg(_) => {
+            // ratio of the selected sum to the selected count, both taken from the same resample
+            let mut sums = vec![(0.0, 0i64); group_count];
+            for &i in indices {
+                let (sum, count) = &mut sums[groups[i]];
+                *sum += contributions[i];
+                *count += selected[i];
+            }
+            sums.into_iter()
+                .map(|(sum, count)| if count == 0 { 0.0 } else { sum / count as f64 })
+                .collect()
+        }
+        Aggregate::Percentile(_, fraction) => {
+            // the selected values of a resample ranked, which the bootstrap handles well for
+            // quantiles that are not in the extreme tails
+            let mut values = vec![Vec::new(); group_count];
+            for &i in indices.iter().filter(|&&i| selected[i] != 0) {
+                values[groups[i]].push(sample_result.values[i]);
+            }
+            values
+                .into_iter()
+                .map(|mut values| {
+                    if values.is_empty() {
+                        return 0.0;
+                    }
+                    let rank = (fraction * (values.len() - 1) as f64) as usize;
+                    *values.select_nth_unstable_by(rank, f64::total_cmp).1
+                })
+                .collect()
+        }
+        // distinct counts are not bootstrapped, see distinct_estimate
+        Aggregate::CountDistinct(_) => vec![f64::NAN; group_count],
+        Aggregate::Min(_) | Aggregate::Max(_) => {
+            unreachable!("min and max are answered exactly, see validate_aggregate")
+        }
+        Aggregate::Constant(_) | Aggregate::Arithmetic(..) => {
+            unreachable!("arithmetic is computed from its base aggregates")
+        }
+    }
+}
+
+//number of distinct values with the Guaranteed-Error Estimator (GEE) of Charikar et al.,
+//sqrt(1/q) f1 + f2 + f3 + ... where q is the sample fraction and fj the number of values
+//seen j times among the selected rows. GEE is guaranteed to be within a ratio of sqrt(1/q)
+//of the truth, and no estimator can do better on every table, so its bounds are
+//GEE / sqrt(1/q) and GEE sqrt(1/q), never below the distinct values seen in the sample,
+//which is certain. The bounds are not a confidence interval and there is no bootstrap:
+//resampling the sample never brings in the values it is missing, so the bootstrap
+//distribution of a distinct count sits below the truth and its intervals are far too narrow
+fn distinct_estimate(
+    sample_result: &SampleResult,
+    aggregate: &Aggregate,
+    sample_set: &SampleSet,
+    sample_table: &str,
+    groups: &[usize],
+    group: usize,
+) -> Estimate {
+    let sample_fraction = sample_set.get_fraction();
+    let frequencies = sample_result.key_frequencies(groups, group);
+    let seen = frequencies.len() as f64;
+    let singletons = frequencies.values().filter(|&&count| count == 1).count() as f64;
+    let ratio = (1.0 / sample_fraction).sqrt();
+    let gee = seen + (ratio - 1.0) * singletons;
+
+    Estimate {
+        aggregate: aggregate.to_string(),
+        group: None,
+        sample_table: sample_table.to_string(),
+        sample_fraction,
+        sample_ground_truth: gee,
+        std_error: None,
+        lower_bound: (gee / ratio).max(seen),
+        upper_bound: (gee * ratio).max(seen),
+        confidence_level: None,
+        membership_probability: None,
+        exact_reason: None,
+        sample_rows: sample_result.selected.len(),
+        low_selectivity: false,
+        matched_rows: sample_result
+            .selected
+            .iter()
+            .zip(groups)
+            .filter(|(&flag, &row_group)| flag != 0 && row_group == group)
+            .count(),
+        bootstrap_size: 0,
+        evaluation_time: 0.0,
+        bootstrap_time: 0.0,
+        cached: false,
+    }
+}
+
+//pick the smallest sample in the ladder on which every aggregate meets the relative error
+//target, moving to a larger one only while the latency target allows it
+#[allow(clippy::too_many_arguments)]
+pub fn estimate_with_ladder(
+    conn: &Connection,
+    ladder: &[SampleSet],
+    select: &Select,
+    join_conditions: &[Where],
+    selection_conditions: &[Where],
+    bootstrap_size: usize,
+    error_target: Option<f64>,
+    latency_target: Option<f64>,
+    evaluator: Evaluator,
+) -> Result<Vec<Estimate>, AqpError> {
+    // without any target the largest sample gives the most accurate answer
+    let first = if error_target.is_none() && latency_target.is_none() {
+        ladder.len() - 1
+    } else {
+        0
+    };
+
+    let level = join_level(join_conditions)? + 1;
+    let start = Instant::now();
+    let mut rung = first;
+    loop {
+        let rung_start = Instant::now();
+        let sample_set = &ladder[rung];
+        let query_result = query_result(
+            conn,
+            sample_set,
+            select,
+            join_conditions,
+            selection_conditions,
+            evaluator,
+        )?;
+        let evaluation_time = rung_start.elapsed().as_secs_f64();
+        let result = estimate(&query_result, select, sample_set, level, bootstrap_size);
+        let mut estimates = match result {
+            Ok(estimates) => estimates,
+            // a larger sample may still have rows to estimate from
+            Err(AqpError::Statistical(message)) if rung + 1 < ladder.len() => {
+                info!("{}: {}", sample_set.table(level), message);
+                rung += 1;
+                continue;
+            }
+            Err(error) => return Err(error),
+        };
+        for estimate in &mut estimates {
+            estimate.evaluation_time = evaluation_time;
+        }
+        let rung_time = rung_start.elapsed().as_secs_f64();
+        // the least accurate aggregate of any group decides whether the sample is large enough
+        let relative_error = estimates
+            .iter()
+            .map(Estimate::relative_error)
+            .fold(0.0, f64::max);
+        let values: Vec<String> = estimates
+            .iter()
+            .map(|estimate| format!("{:.2}", estimate.sample_ground_truth))
+            .collect();
+        info!(
+            "{}: estimate {}, relative error {:.4}, {:.2}s",
+            sample_set.table(level),
+            values.join(", "),
+            relative_error,
+            rung_time
+        );
+
+        if rung + 1 == ladder.len() {
+            return Ok(estimates);
+        }
+        if let Some(error_target) = error_target {
+            // a narrow interval over a few matching rows is not trusted
+            let low_selectivity = estimates.iter().any(Estimate::is_low_selectivity);
+            if relative_error <= error_target && !low_selectivity {
+                return Ok(estimates);
+            }
+            if low_selectivity {
+                info!(
+                    "{}: fewer than {} sample rows match the selection, trying a larger sample",
+                    sample_set.table(level),
+                    MIN_MATCHED_ROWS
+                );
+            }
+        }
+        if let Some(latency_target) = latency_target {
+            // assume the time grows linearly with the number of sampled rows
+            let next = &ladder[rung + 1];
+            let predicted = rung_time * next.get_rows() as f64 / sample_set.get_rows().max(1) as f64;
+            if start.elapsed().as_secs_f64() + predicted > latency_target {
+                return Ok(estimates);
+            }
+        }
+        rung += 1;
+    }
+}
+
+//join keys of the s2..s5 samples, in the order the samples add their tables
+pub const JOIN_KEYS: [(&str, &str); 4] = [
+    ("l_orderkey", "o_orderkey"),
+    ("o_custkey", "c_custkey"),
+    ("c_nationkey", "n_nationkey"),
+    ("n_regionkey", "r_regionkey"),
+];
+
+//whether a condition joins on the two keys, written in either order and either in the
+//where clause or in a join ... on
+fn joins_on(condition: &Where, (left, right): (&str, &str)) -> bool {
+    let columns = (condition.get_left_column(), condition.get_right_column());
+    condition.is_join()
+        && (columns == (Some(left), Some(right)) || columns == (Some(right), Some(left)))
+}
+
+//whether a condition is one of the joins the samples are built on
+pub fn is_sample_join(condition: &Where) -> bool {
+    JOIN_KEYS.iter().any(|&keys| joins_on(condition, keys))
+}
+
+//number of joins of the query along lineitem - orders - customer - nation - region,
+//which have to start at lineitem since every sample does; no join at all is lineitem alone
+pub fn join_level(join_conditions: &[Where]) -> Result<usize, AqpError> {
+    let found: Vec<bool> = JOIN_KEYS
+        .iter()
+        .map(|&keys| join_conditions.iter().any(|condition| joins_on(condition, keys)))
+        .collect();
+    let join_count = found.iter().take_while(|&&found| found).count();
+
+    if found[join_count..].iter().any(|&found| found) {
+        let (left, right) = JOIN_KEYS[join_count];
+        return Err(AqpError::Unsupported(format!(
+            "the joins skip {} = {}, only a chain starting at lineitem has a sample",
+            left, right
+        )));
+    }
+    Ok(join_count)
+}
+
+//Selectiong sample based on join condition, and evaluating the selection once for all the
+//aggregates of the query, one result per base aggregate of computed_aggregates followed by the
+//keys of every group by expression
+pub fn query_result(
+    conn: &Connection,
+    sample_set: &SampleSet,
+    select: &Select,
+    join_conditions: &[Where],
+    selection_conditions: &[Where],
+    evaluator: Evaluator,
+) -> Result<Vec<SampleResult>, AqpError> {
+    let join_count = join_level(join_conditions)?;
+    let level = join_count + 1;
+    let table = sample_set.table(level);
+    let aggregates = computed_aggregates(select);
+    let aggregates: Vec<&Aggregate> = aggregates
+        .iter()
+        .flat_map(Aggregate::base_aggregates)
+        .collect();
+    let aggregates = &aggregates[..];
+    let group_by = select.get_group_by();
+    let columns: Vec<Option<&str>> = aggregates.iter().map(|aggregate| aggregate.get_column()).collect();
+    let arguments: Vec<Option<&Expr>> = aggregates
+        .iter()
+        .map(|aggregate| aggregate.get_argument())
+        .collect();
+
+    // samples persisted as parquet are loaded as arrow record batches and evaluated column-wise
+    if let Some(dir) = sample_set.get_parquet_dir() {
+        let path = sample_file(dir, sample_set, level);
+        let mut arguments = arguments;
+        arguments.extend(group_by.iter().map(Some));
+        let sample = fetch_parquet_sample(&path, selection_conditions, &arguments)?;
+        let results = get_columnar_sample_result(&sample, selection_conditions, &arguments);
+        return Ok(results
+            .into_iter()
+            .zip(&arguments)
+            .enumerate()
+            .map(
+                |(i, (result, argument))| match (aggregates.get(i), argument) {
+                    // text and date keys keep their values, numbers are their own keys
+                    (Some(Aggregate::CountDistinct(_)) | None, Some(argument)) => {
+                        SampleResult::with_keys(
+                            result.selected,
+                            get_columnar_keys(&sample, argument),
+                        )
+                    }
+                    _ => result,
+                },
+            )
+            .collect());
+    }
+
+    // other conditions and expressions are evaluated per row in Rust, where text and date
+    // columns keep their values
+    let simple = aggregates.iter().all(|aggregate| aggregate.is_simple())
+        && selection_conditions.iter().all(Where::is_simple)
+        && !select.is_grouped();
+    let hashmap_result = || {
+        hashmap_query_result(
+            conn,
+            level,
+            &table,
+            selection_conditions,
+            aggregates,
+            group_by,
+        )
+    };
+    let result = match evaluator {
+        _ if !simple => hashmap_result()?,
+        Evaluator::Sql => {
+            // evaluate the selection conditions inside sqlite when they can be expressed there
+            match pushdown_query_result(conn, &table, selection_conditions, &columns)? {
+                Some(result) => result,
+                None => hashmap_result()?,
+            }
+        }
+        Evaluator::Rust => hashmap_result()?,
+        Evaluator::Columnar => {
+            let sample = fetch_columnar_sample(conn, &table, selection_conditions, &arguments)?;
+            get_columnar_sample_result(&sample, selection_conditions, &arguments)
+        }
+        Evaluator::Bench => benchmark_evaluators(
+            conn,
+            &table,
+            selection_conditions,
+            &arguments,
+            hashmap_result,
+        )?,
+    };
+
+    Ok(result)
+}
+
+//evaluate the selection conditions in Rust over the s*level sample rows stored as hashmaps,
+//with the keys of the group by expressions after the aggregates
+pub fn hashmap_query_result(
+    conn: &Connection,
+    level: usize,
+    table: &str,
+    selection_conditions: &[Where],
+    aggregates: &[&Aggregate],
+    group_by: &[Expr],
+) -> Result<Vec<SampleResult>, rusqlite::Error> {
+    let hashmap = match level {
+        1 => {
+            let s1_sample = fetch_s1_sample(conn, table)?;
+            s1_sample_to_hashmap(&s1_sample)
+        }
+        2 => {
+            let s2_sample = fetch_s2_sample(conn, table)?;
+            s2_sample_to_hashmap(&s2_sample)
+        }
+        3 => {
+            let s3_sample = fetch_s3_sample(conn, table)?;
+            s3_sample_to_hashmap(&s3_sample)
+        }
+        4 => {
+            let s4_sample = fetch_s4_sample(conn, table)?;
+            s4_sample_to_hashmap(&s4_sample)
+        }
+        5 => {
+            let s5_sample = fetch_s5_sample(conn, table)?;
+            s5_sample_to_hashmap(&s5_sample)
+        }
+        _ => Vec::new(),
+    };
+
+    let selected = get_query_result(&hashmap, selection_conditions);
+    let mut results: Vec<SampleResult> = aggregates
+        .iter()
+        .map(|aggregate| match aggregate {
+            Aggregate::Count | Aggregate::Constant(_) | Aggregate::Arithmetic(..) => {
+                SampleResult::new(selected.clone(), Vec::new())
+            }
+            Aggregate::Sum(argument)
+            | Aggregate::Avg(argument)
+            | Aggregate::Percentile(argument, _)
+            | Aggregate::Min(argument)
+            | Aggregate::Max(argument) => {
+                SampleResult::new(selected.clone(), get_expression_values(&hashmap, argument))
+            }
+            Aggregate::CountDistinct(argument) => {
+                SampleResult::with_keys(selected.clone(), get_expression_keys(&hashmap, argument))
+            }
+        })
+        .collect();
+    results.extend(
+        group_by.iter().map(|key| {
+            SampleResult::with_keys(selected.clone(), get_expression_keys(&hashmap, key))
+        }),
+    );
+    Ok(results)
+}
+
+//matching sample rows below which an estimate is flagged as low selectivity, counts get a
+//Wilson interval and the ladder moves on to a larger sample when it has an error target
+pub const MIN_MATCHED_ROWS: usize = 30;
+
+//reject sample fractions outside (0, 1], before anything is dropped or drawn
+fn check_fractions(fractions: &[f64]) -> Result<(), AqpError> {
+    let invalid = fractions
+        .iter()
+        .find(|&&fraction| !is_sample_fraction(fraction));
+    match invalid {
+        Some(fraction) => Err(AqpError::InvalidInput(format!(
+            "sample fraction {} is not a share of the rows in (0, 1]",
+            fraction
+        ))),
+        None if fractions.is_empty() => Err(AqpError::InvalidInput(
+            "no sample fraction given".to_string(),
+        )),
+        None => Ok(()),
+    }
+}
+
+//bootstrap resamples used when the caller does not set any
+pub const DEFAULT_BOOTSTRAP_SIZE: usize = 1000;
+
+//an open database with its sample sets and the settings used to answer queries over them
+pub struct Engine {
+    conn: Connection,
+    samples: Vec<SampleSet>,
+    bootstrap_size: usize,
+    evaluator: Evaluator,
+    error_target: Option<f64>,
+    latency_target: Option<f64>,
+    exact_fallback: bool,
+    cache: bool,
+}
+
+impl Engine {
+    pub fn new(conn: Connection) -> Self {
+        Engine {
+            conn,
+            samples: Vec::new(),
+            bootstrap_size: DEFAULT_BOOTSTRAP_SIZE,
+            evaluator: Evaluator::Sql,
+            error_target: None,
+            latency_target: None,
+            exact_fallback: false,
+            cache: false,
+        }
+    }
+
+    //open (or create) a sqlite database
+    pub fn open(path: &Path) -> Result<Self, AqpError> {
+        Ok(Engine::new(Connection::open(path)?))
+    }
+
+    pub fn with_bootstrap_size(mut self, bootstrap_size: usize) -> Self {
+        self.bootstrap_size = bootstrap_size;
+        self
+    }
+
+    pub fn with_evaluator(mut self, evaluator: Evaluator) -> Self {
+        self.evaluator = evaluator;
+        self
+    }
+
+    //smallest sample of the ladder whose relative error is below the target
+    pub fn with_error_target(mut self, error_target: Option<f64>) -> Self {
+        self.error_target = error_target;
+        self
+    }
+
+    //largest sample of the ladder that answers within the target seconds
+    pub fn with_latency_target(mut self, latency_target: Option<f64>) -> Self {
+        self.latency_target = latency_target;
+        self
+    }
+
+    //answer exactly over the base tables the queries the samples cannot approximate, instead
+    //of failing with the reason
+    pub fn with_exact_fallback(mut self, exact_fallback: bool) -> Self {
+        self.exact_fallback = exact_fallback;
+        self
+    }
+
+    //keep the estimates of every query in the database and return them again when the same
+    //query is asked with the same settings, until the samples are rebuilt
+    pub fn with_cache(mut self, cache: bool) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    pub fn get_connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    pub fn get_samples(&self) -> &[SampleSet] {
+        &self.samples
+    }
+
+    pub fn get_bootstrap_size(&self) -> usize {
+        self.bootstrap_size
+    }
+
+    pub fn get_evaluator(&self) -> Evaluator {
+        self.evaluator
+    }
+
+    //fill the database with generated TPC-H tables
+    pub fn generate_tpch(&self, scale_factor: f64, seed: u64) -> Result<(), AqpError> {
+        generate_tpch(&self.conn, scale_factor, seed)?;
+        // exact answers came from the previous tables
+        clear_query_cache(&self.conn)?;
+        Ok(())
+    }
+
+    //draw one sample set of lineitem, reproducible when a seed is given
+    pub fn build_samples(
+        &mut self,
+        sample_fraction: f64,
+        seed: Option<u64>,
+    ) -> Result<&[SampleSet], AqpError> {
+        check_fractions(&[sample_fraction])?;
+        let set = match seed {
+            Some(seed) => create_seeded_sample_tables(&self.conn, sample_fraction, seed)?,
+            None => create_sample_tables(&self.conn, sample_fraction)?,
+        };
+        self.samples = vec![set];
+        Ok(&self.samples)
+    }
+
+    //draw a ladder of nested sample sets, one per fraction, reproducible when a seed is given
+    pub fn build_sample_ladder(
+        &mut self,
+        sample_fractions: &[f64],
+        seed: Option<u64>,
+    ) -> Result<&[SampleSet], AqpError> {
+        check_fractions(sample_fractions)?;
+        self.samples = create_sample_ladder(&self.conn, sample_fractions, seed)?;
+        Ok(&self.samples)
+    }
+
+    //sample dbgen .tbl or csv files while loading them, reproducible when a seed is given
+    pub fn ingest(
+        &mut self,
+        dir: &Path,
+        sample_fractions: &[f64],
+        keep_base_tables: bool,
+        seed: Option<u64>,
+    ) -> Result<&[SampleSet], AqpError> {
+        check_fractions(sample_fractions)?;
+        self.samples = ingest(&self.conn, dir, sample_fractions, keep_base_tables, seed)?;
+        Ok(&self.samples)
+    }
+
+    //reuse the sample sets recorded in the sample catalog of the database
+    pub fn load_samples(&mut self) -> Result<&[SampleSet], AqpError> {
+        self.samples = load_sample_catalog(&self.conn)?;
+        Ok(&self.samples)
+    }
+
+    //reuse sample sets persisted as parquet files
+    pub fn load_parquet_samples(&mut self, dir: &Path) -> Result<&[SampleSet], AqpError> {
+        self.samples = load_parquet_catalog(dir)?;
+        Ok(&self.samples)
+    }
+
+    //persist the current sample sets as parquet files
+    pub fn export_samples(&self, dir: &Path) -> Result<(), AqpError> {
+        export_samples(&self.conn, &self.samples, dir)
+    }
+
+    //whether the base tables are there to answer queries exactly
+    pub fn has_base_tables(&self) -> Result<bool, AqpError> {
+        Ok(table_exists(&self.conn, "lineitem")?)
+    }
+
+    //exact answer of every aggregate of a query over the base tables, None without them
+    pub fn ground_truth(&self, sql: &str) -> Result<Option<GroundTruth>, AqpError> {
+        if !self.has_base_tables()? {
+            return Ok(None);
+        }
+        Ok(Some(exact_answers(&self.conn, sql)?))
+    }
+
+    //approximate answer of every aggregate of a query over the samples, in the order of the
+    //select list, each with its interval; for a grouped query the aggregates of every group it
+    //returns, group after group. With the exact fallback, a query the samples cannot answer
+    //(a table without sample, min or max, no matching sample row, ...) is answered exactly
+    //when the base tables are there, see exact_estimates. With the cache, a query answered
+    //before over the same samples is read back instead
+    pub fn estimate(&self, sql: &str) -> Result<Vec<Estimate>, AqpError> {
+        let key = self.cache_key(sql);
+        if let Some((query, settings)) = &key {
+            if let Some(estimates) = cached_estimates(&self.conn, query, settings)? {
+                return Ok(estimates);
+            }
+        }
+        let estimates = self.answer(sql)?;
+        if let Some((query, settings)) = &key {
+            cache_estimates(&self.conn, query, settings, &estimates)?;
+        }
+        Ok(estimates)
+    }
+
+    //the query as parsed and the settings its estimates depend on, which identify it in the
+    //query cache; None without the cache, for a query that does not parse (its error is not
+    //cached), for parquet samples, which the sample version of the database does not follow,
+    //and with a latency target, whose sample depends on how long the smaller ones took to read
+    fn cache_key(&self, sql: &str) -> Option<(String, String)> {
+        if !self.cache
+            || self.latency_target.is_some()
+            || self
+                .samples
+                .iter()
+                .any(|set| set.get_parquet_dir().is_some())
+        {
+            return None;
+        }
+        let query = parse_query(sql).ok()?;
+        let settings = format!(
+            "bootstrap {} error {:?} exact fallback {}",
+            self.bootstrap_size, self.error_target, self.exact_fallback
+        );
+        Some((query.to_string(), settings))
+    }
+
+    fn answer(&self, sql: &str) -> Result<Vec<Estimate>, AqpError> {
+        let error = match self.approximate(sql) {
+            Err(
+                error @ (AqpError::Unsupported(_)
+                | AqpError::Statistical(_)
+                | AqpError::MissingSamples(_)),
+            ) if self.exact_fallback => error,
+            result => return result,
+        };
+        if !self.has_base_tables()? {
+            return Err(error);
+        }
+        exact_estimates(&self.conn, sql, &error.to_string())?.ok_or(error)
+    }
+
+    fn approximate(&self, sql: &str) -> Result<Vec<Estimate>, AqpError> {
+        if self.samples.is_empty() {
+            return Err(AqpError::MissingSamples(
+                "build or load samples before estimating".to_string(),
+            ));
+        }
+
+        let sql_query = parse_query(sql)?;
+        // every sample set of the ladder has the same schema
+        validate_query(&sql_query, &sample_schema(&self.conn, &self.samples[0])?)?;
+        let select = sql_query.get_select();
+        let where_conditions = select.get_where_clause().clone().unwrap_or_default();
+        let (join_conditions, selection_conditions) = separate_conditions(where_conditions);
+        let selection_conditions = resolve_subqueries(&self.conn, selection_conditions)?;
+
+        estimate_with_ladder(
+            &self.conn,
+            &self.samples,
+            select,
+            &join_conditions,
+            &selection_conditions,
+            self.bootstrap_size,
+            self.error_target,
+            self.latency_target,
+            self.evaluator,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    const QUERY: &str =
+        "select count(*), avg(l_quantity) from lineitem, orders where l_orderkey = o_orderkey and o_totalprice > 100000";
+
+    //an engine over a small generated database with one seeded 10% sample
+    fn engine() -> Engine {
+        test_support::engine(0.1, 3)
+    }
+
+    fn values(estimates: &[Estimate]) -> Vec<(f64, f64, f64)> {
+        estimates
+            .iter()
+            .map(|estimate| {
+                (
+                    estimate.get_estimate(),
+                    estimate.get_lower_bound(),
+                    estimate.get_upper_bound(),
+                )
+            })
+            .collect()
+    }
+
+    fn where_conditions(sql: &str) -> Vec<Where> {
+        let query = parse_query(sql).unwrap();
+        query
+            .get_select()
+            .get_where_clause()
+            .clone()
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn joins_are_found_in_either_order_and_in_join_on() {
+        let (joins, selections) = separate_conditions(where_conditions(
+            "select count(*) from lineitem l join orders o on o.o_orderkey = l.l_orderkey, customer where c_custkey = o_custkey and o_totalprice > 100000",
+        ));
+        assert_eq!(join_level(&joins).unwrap(), 2);
+        let joins: Vec<String> = joins.iter().map(Where::to_string).collect();
+        assert_eq!(
+            joins,
+            vec!["o.o_orderkey = l.l_orderkey", "c_custkey = o_custkey"]
+        );
+        let selections: Vec<String> = selections.iter().map(Where::to_string).collect();
+        assert_eq!(selections, vec!["o_totalprice > 100000"]);
+    }
+
+    #[test]
+    fn join_level_counts_the_chain_from_lineitem() {
+        let level = |sql: &str| join_level(&separate_conditions(where_conditions(sql)).0);
+        assert_eq!(level("select count(*) from lineitem").unwrap(), 0);
+        assert_eq!(
+            level("select count(*) from lineitem, orders, customer, nation, region where r_regionkey = n_regionkey and n_nationkey = c_nationkey and c_custkey = o_custkey and o_orderkey = l_orderkey").unwrap(),
+            4
+        );
+        assert!(matches!(
+            level("select count(*) from lineitem, orders, customer where o_orderkey = l_orderkey and c_nationkey = n_nationkey"),
+            Err(AqpError::Unsupported(message)) if message.contains("o_custkey = c_custkey")
+        ));
+    }
+
+    #[test]
+    fn join_on_gives_the_same_answer_as_the_where_clause() {
+        let engine = engine();
+        let answer = |sql: &str| engine.estimate(sql).unwrap()[0].get_estimate();
+        assert_eq!(
+            answer("select avg(l_quantity) from lineitem, orders where l_orderkey = o_orderkey and o_totalprice > 100000"),
+            answer("select avg(l_quantity) from lineitem join orders on o_orderkey = l_orderkey where o_totalprice > 100000")
+        );
+    }
+
+    //estimates of a query from made-up results of its base aggregates over a sample of the
+    //given fraction, one row per flag
+    fn estimate_from(
+        sql: &str,
+        results: &[SampleResult],
+        sample_fraction: f64,
+    ) -> Result<Vec<Estimate>, AqpError> {
+        let query = parse_query(sql).unwrap();
+        let rows = results[0].selected.len() as i64;
+        let sample_set = SampleSet::new(String::new(), sample_fraction, rows);
+        estimate(results, query.get_select(), &sample_set, 1, 200)
+    }
+
+    fn keys(keys: &[Option<&str>]) -> Vec<Option<String>> {
+        keys.iter().map(|key| key.map(str::to_string)).collect()
+    }
+
+    #[test]
+    fn distinct_count_is_bounded_by_the_gee_ratio_and_the_values_seen() {
+        // a, b, c, d are selected and b, d seen once; e is not selected and null is not counted
+        let result = SampleResult::with_keys(
+            vec![1, 1, 1, 1, 1, 1, 1, 0, 1],
+            keys(&[
+                Some("a"),
+                Some("a"),
+                Some("b"),
+                Some("c"),
+                Some("c"),
+                Some("c"),
+                Some("d"),
+                Some("e"),
+                None,
+            ]),
+        );
+        let estimates = estimate_from(
+            "select count(distinct l_suppkey) from lineitem, orders where l_orderkey = o_orderkey",
+            &[result],
+            0.01,
+        )
+        .unwrap();
+        let estimate = &estimates[0];
+        assert_eq!(estimate.get_estimate(), 4.0 + (10.0 - 1.0) * 2.0);
+        // 22 / 10 is below the 4 values seen
+        assert_eq!(estimate.get_lower_bound(), 4.0);
+        assert_eq!(estimate.get_upper_bound(), 22.0 * 10.0);
+        assert_eq!(estimate.get_std_error(), None);
+        assert_eq!(estimate.get_confidence_level(), None);
+        assert_eq!(estimate.get_bootstrap_size(), 0);
+        assert_eq!(estimate.get_matched_rows(), 8);
+    }
+
+    #[test]
+    fn distinct_count_bounds_hold_the_exact_answer() {
+        let engine = engine();
+        let query = "select count(distinct o_custkey), count(distinct l_shipmode) from lineitem, orders where l_orderkey = o_orderkey and l_quantity > 20";
+        let estimates = engine.estimate(query).unwrap();
+        let exact: (f64, f64) = engine
+            .get_connection()
+            .query_row(
+                "SELECT COUNT(DISTINCT o_custkey), COUNT(DISTINCT l_shipmode) FROM lineitem, orders WHERE l_orderkey = o_orderkey AND l_quantity > 20",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        for (estimate, exact) in estimates.iter().zip([exact.0, exact.1]) {
+            assert!(estimate.get_lower_bound() <= exact && exact <= estimate.get_upper_bound());
+            assert!(estimate.get_lower_bound() <= estimate.get_estimate());
+            assert!(estimate.get_estimate() <= estimate.get_upper_bound());
+        }
+        // every ship mode is frequent, so the sample has seen them all
+        assert_eq!(estimates[1].get_estimate(), exact.1);
+    }
+
+    #[test]
+    fn percentile_is_the_value_at_its_rank_rounded_down() {
+        let values: Vec<f64> = (1..=101).map(f64::from).collect();
+        let result = SampleResult::new(vec![1; 101], values);
+        let estimates = estimate_from(
+            "select percentile(l_quantity, 0.9), median(l_quantity) from lineitem, orders where l_orderkey = o_orderkey",
+            &[result.clone(), result],
+            0.1,
+        )
+        .unwrap();
+        assert_eq!(estimates[0].get_estimate(), 91.0);
+        assert_eq!(estimates[1].get_estimate(), 51.0);
+        for estimate in &estimates {
+            assert!(estimate.get_lower_bound() <= estimate.get_estimate());
+            assert!(estimate.get_estimate() <= estimate.get_upper_bound());
+        }
+
+        // the rank 1.5 of the selected 4, 1, 3, 2 is rounded down to the second value
+        let result = SampleResult::new(vec![1, 1, 0, 1, 1], vec![4.0, 1.0, 100.0, 3.0, 2.0]);
+        let estimates = estimate_from(
+            "select median(l_quantity) from lineitem, orders where l_orderkey = o_orderkey",
+            &[result],
+            0.1,
+        )
+        .unwrap();
+        assert_eq!(estimates[0].get_estimate(), 2.0);
+    }
+
+    #[test]
+    fn percentile_ground_truth_uses_the_same_rank() {
+        let engine = engine();
+        let query = "select percentile(l_extendedprice, 0.25) from lineitem, orders where l_orderkey = o_orderkey and l_quantity > 10";
+        let estimates = engine.estimate(query).unwrap();
+        let mut prices: Vec<f64> = engine
+            .get_connection()
+            .prepare("SELECT l_extendedprice FROM lineitem WHERE l_quantity > 10")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        prices.sort_by(f64::total_cmp);
+        let exact = prices[(0.25 * (prices.len() - 1) as f64) as usize];
+
+        let ground_truth = engine.ground_truth(query).unwrap().unwrap();
+        assert_eq!(ground_truth.value_of(&estimates[0]), Some(exact));
+        assert!((estimates[0].get_estimate() - exact).abs() / exact < 0.2);
+    }
+
+    #[test]
+    fn aggregates_of_a_select_list_share_the_selection_and_the_resamples() {
+        let selected = vec![1, 0, 1, 1, 0];
+        let values = vec![2.0, 5.0, 4.0, 6.0, 7.0];
+        let estimates = estimate_from(
+            "select count(*), sum(l_quantity), avg(l_quantity) from lineitem, orders where l_orderkey = o_orderkey",
+            &[
+                SampleResult::new(selected.clone(), Vec::new()),
+                SampleResult::new(selected.clone(), values.clone()),
+                SampleResult::new(selected, values),
+            ],
+            0.5,
+        )
+        .unwrap();
+        let aggregates: Vec<&str> = estimates.iter().map(Estimate::get_aggregate).collect();
+        assert_eq!(
+            aggregates,
+            vec!["count(*)", "sum(l_quantity)", "avg(l_quantity)"]
+        );
+        let values: Vec<f64> = estimates.iter().map(Estimate::get_estimate).collect();
+        assert_eq!(values, vec![6.0, 24.0, 4.0]);
+        for estimate in &estimates {
+            assert_eq!(estimate.get_matched_rows(), 3);
+            assert_eq!(estimate.get_bootstrap_size(), 200);
+            assert_eq!(
+                estimate.get_bootstrap_time(),
+                estimates[0].get_bootstrap_time()
+            );
+        }
+    }
+
+    #[test]
+    fn several_aggregates_give_the_answers_of_separate_queries() {
+        let engine = engine();
+        let conditions =
+            "from lineitem, orders where l_orderkey = o_orderkey and o_totalprice > 100000";
+        let together = engine
+            .estimate(&format!(
+                "select count(*), sum(l_quantity), avg(l_extendedprice) {}",
+                conditions
+            ))
+            .unwrap();
+        for (aggregate, estimate) in ["count(*)", "sum(l_quantity)", "avg(l_extendedprice)"]
+            .iter()
+            .zip(&together)
+        {
+            let alone = engine
+                .estimate(&format!("select {} {}", aggregate, conditions))
+                .unwrap();
+            assert_eq!(alone.len(), 1);
+            assert_eq!(alone[0].get_estimate(), estimate.get_estimate());
+            assert_eq!(alone[0].get_matched_rows(), estimate.get_matched_rows());
+        }
+    }
+
+    #[test]
+    fn arithmetic_of_aggregates_is_computed_on_paired_resamples() {
+        let engine = engine();
+        let estimates = engine
+            .estimate("select sum(l_quantity) - sum(l_quantity), sum(l_quantity) / count(*), avg(l_quantity), 100 * sum(l_extendedprice * l_discount) / sum(l_extendedprice) from lineitem, orders where l_orderkey = o_orderkey and l_tax > 0.02")
+            .unwrap();
+        // the two sums of a resample are the same, so their difference never moves
+        assert_eq!(values(&estimates)[0], (0.0, 0.0, 0.0));
+        assert!((estimates[1].get_estimate() - estimates[2].get_estimate()).abs() < 1e-9);
+        assert!(estimates[1].get_lower_bound() < estimates[1].get_estimate());
+
+        let (revenue, price): (f64, f64) = engine
+            .get_connection()
+            .query_row(
+                "SELECT SUM(l_extendedprice * l_discount), SUM(l_extendedprice) FROM s2_sample WHERE l_tax > 0.02",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert!((estimates[3].get_estimate() - 100.0 * revenue / price).abs() < 1e-9);
+        assert_eq!(
+            estimates[3].get_aggregate(),
+            "100 * sum(l_extendedprice * l_discount) / sum(l_extendedprice)"
+        );
+    }
+
+    #[test]
+    fn arithmetic_dividing_by_zero_on_the_sample_cannot_be_estimated() {
+        assert!(matches!(
+            engine().estimate("select count(*) / sum(l_quantity - l_quantity) from lineitem, orders where l_orderkey = o_orderkey"),
+            Err(AqpError::Statistical(message)) if message.contains("divides by 0")
+        ));
+    }
+
+    #[test]
+    fn every_group_gets_its_estimates_in_the_order_of_its_keys() {
+        let engine = engine();
+        let query = "select l_returnflag, count(*), avg(l_quantity) from lineitem, orders where l_orderkey = o_orderkey and l_tax > 0.02 group by l_returnflag";
+        let estimates = engine.estimate(query).unwrap();
+        let groups: Vec<(Option<&str>, &str)> = estimates
+            .iter()
+            .map(|estimate| (estimate.get_group(), estimate.get_aggregate()))
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                (Some("A"), "count(*)"),
+                (Some("A"), "avg(l_quantity)"),
+                (Some("N"), "count(*)"),
+                (Some("N"), "avg(l_quantity)"),
+                (Some("R"), "count(*)"),
+                (Some("R"), "avg(l_quantity)"),
+            ]
+        );
+        assert!(estimates
+            .iter()
+            .all(|estimate| estimate.get_membership_probability().is_none()));
+
+        let ground_truth = engine.ground_truth(query).unwrap().unwrap();
+        for estimate in &estimates {
+            let exact = ground_truth.value_of(estimate).unwrap();
+            assert!((estimate.get_estimate() - exact).abs() / exact < 0.3);
+        }
+        let matched: usize = estimates
+            .iter()
+            .step_by(2)
+            .map(Estimate::get_matched_rows)
+            .sum();
+        let total = engine
+            .estimate("select count(*) from lineitem, orders where l_orderkey = o_orderkey and l_tax > 0.02")
+            .unwrap();
+        assert_eq!(matched, total[0].get_matched_rows());
+    }
+
+    #[test]
+    fn ranked_groups_report_how_often_they_stay_in_the_result() {
+        let engine = engine();
+        let counts: Vec<(String, f64)> = engine
+            .estimate("select l_shipmode, count(*) from lineitem, orders where l_orderkey = o_orderkey group by l_shipmode")
+            .unwrap()
+            .iter()
+            .map(|estimate| {
+                (
+                    estimate.get_group().unwrap().to_string(),
+                    estimate.get_estimate(),
+                )
+            })
+            .collect();
+        let mut ranked = counts.clone();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        let top = engine
+            .estimate("select l_shipmode, count(*) as n from lineitem, orders where l_orderkey = o_orderkey group by l_shipmode order by n desc limit 2")
+            .unwrap();
+        let top: Vec<(String, f64)> = top
+            .iter()
+            .map(|estimate| {
+                let probability = estimate.get_membership_probability().unwrap();
+                assert!((0.0..=1.0).contains(&probability));
+                (
+                    estimate.get_group().unwrap().to_string(),
+                    estimate.get_estimate(),
+                )
+            })
+            .collect();
+        assert_eq!(top, ranked[..2].to_vec());
+
+        // a condition every resample satisfies keeps every group for sure
+        let kept = engine
+            .estimate("select l_shipmode, count(*) from lineitem, orders where l_orderkey = o_orderkey group by l_shipmode having avg(l_quantity) > 0")
+            .unwrap();
+        assert_eq!(kept.len(), counts.len());
+        assert!(kept
+            .iter()
+            .all(|estimate| estimate.get_membership_probability() == Some(1.0)));
+        let none = engine
+            .estimate(
+                "select l_shipmode, count(*) from lineitem, orders where l_orderkey = o_orderkey group by l_shipmode having count(*) < 0",
+            )
+            .unwrap();
+        assert!(none.is_empty());
+    }
+
+    #[test]
+    fn subqueries_select_the_sample_rows_by_their_exact_keys() {
+        let engine = engine();
+        let conn = engine.get_connection();
+        let sample_count = |condition: &str| -> f64 {
+            conn.query_row(
+                &format!("SELECT COUNT(*) FROM s2_sample WHERE {}", condition),
+                [],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        let count = |sql: &str| engine.estimate(sql).unwrap()[0].get_estimate();
+
+        let big = "select o_orderkey from orders where o_totalprice > 200000";
+        assert_eq!(
+            count(&format!(
+                "select count(*) from lineitem, orders where l_orderkey = o_orderkey and o_orderkey in ({})",
+                big
+            )),
+            sample_count(&format!("o_orderkey IN ({})", big)) / 0.1
+        );
+        assert_eq!(
+            count(&format!(
+                "select count(*) from lineitem, orders where l_orderkey = o_orderkey and o_orderkey not in ({})",
+                big
+            )),
+            sample_count(&format!("o_orderkey NOT IN ({})", big)) / 0.1
+        );
+        assert_eq!(
+            count("select count(*) from lineitem, orders where l_orderkey = o_orderkey and l_shipmode in ('MAIL', 'SHIP')"),
+            sample_count("l_shipmode IN ('MAIL', 'SHIP')") / 0.1
+        );
+
+        // exists is the same semi-join on the order key
+        let exists = count("select count(*) from lineitem l1, orders where l1.l_orderkey = o_orderkey and exists (select * from lineitem l2 where l2.l_orderkey = o_orderkey and l2.l_quantity > 45)");
+        let in_keys = count("select count(*) from lineitem, orders where l_orderkey = o_orderkey and o_orderkey in (select l_orderkey from lineitem where l_quantity > 45)");
+        assert_eq!(exists, in_keys);
+        assert!(exists > 0.0);
+    }
+
+    #[test]
+    fn wilson_interval_of_a_few_rows_is_scaled_to_the_table() {
+        // 5 of 100 sample rows match on a 10% sample
+        let (std_error, lower, upper) = wilson_interval(5, 100, 0.1, 1.96);
+        assert!((lower - 21.54).abs() < 0.01, "{}", lower);
+        assert!((upper - 111.75).abs() < 0.01, "{}", upper);
+        assert!((std_error - 4.75f64.sqrt() / 0.1).abs() < 1e-9);
+        // one row still gives an interval with room on both sides
+        let (_, lower, upper) = wilson_interval(1, 1000, 0.01, 1.96);
+        assert!(lower > 0.0 && lower < 100.0 && upper > 100.0);
+    }
+
+    #[test]
+    fn few_matching_rows_are_flagged_and_counted_with_wilson() {
+        let engine = engine();
+        let estimates = engine
+            .estimate("select count(*), avg(l_extendedprice) from lineitem, orders where l_orderkey = o_orderkey and l_quantity > 48")
+            .unwrap();
+        let matched = estimates[0].get_matched_rows();
+        assert!(matched > 0 && matched < MIN_MATCHED_ROWS);
+        assert!(estimates.iter().all(Estimate::is_low_selectivity));
+        let rows = estimates[0].get_sample_rows();
+        let (std_error, lower, upper) = wilson_interval(matched, rows, 0.1, 1.96);
+        assert_eq!(estimates[0].get_std_error(), Some(std_error));
+        assert_eq!(
+            (
+                estimates[0].get_lower_bound(),
+                estimates[0].get_upper_bound()
+            ),
+            (lower, upper)
+        );
+        // the other aggregates keep their bootstrap interval around the estimate
+        let avg = &estimates[1];
+        assert!(
+            (avg.get_estimate()
+                - avg.get_lower_bound()
+                - (avg.get_upper_bound() - avg.get_estimate()))
+            .abs()
+                < 1e-6
+        );
+
+        let common = engine
+            .estimate("select count(*) from lineitem, orders where l_orderkey = o_orderkey and l_quantity > 10")
+            .unwrap();
+        assert!(!common[0].is_low_selectivity());
+    }
+
+    #[test]
+    fn ladder_moves_past_samples_with_too_few_matching_rows() {
+        let mut engine = engine();
+        engine
+            .build_sample_ladder(&[0.05, 0.2, 1.0], Some(2))
+            .unwrap();
+        let engine = engine.with_error_target(Some(10.0));
+        let estimates = engine
+            .estimate("select count(*) from lineitem, orders where l_orderkey = o_orderkey and l_quantity > 48")
+            .unwrap();
+        assert!(estimates[0].get_sample_fraction() > 0.05);
+        assert!(!estimates[0].is_low_selectivity() || estimates[0].get_sample_fraction() == 1.0);
+    }
+
+    #[test]
+    fn repeated_query_is_read_from_the_cache() {
+        let engine = engine().with_cache(true);
+        let first = engine.estimate(QUERY).unwrap();
+        assert!(first.iter().all(|estimate| !estimate.is_cached()));
+
+        // the same query written differently is the same parsed query
+        let second = engine
+            .estimate("SELECT COUNT(*), AVG(l_quantity)\n FROM lineitem, orders -- big orders\n WHERE l_orderkey = o_orderkey AND o_totalprice > 100000;")
+            .unwrap();
+        assert!(second.iter().all(Estimate::is_cached));
+        assert_eq!(values(&first), values(&second));
+        let aggregates: Vec<&str> = second.iter().map(Estimate::get_aggregate).collect();
+        assert_eq!(aggregates, vec!["count(*)", "avg(l_quantity)"]);
+    }
+
+    #[test]
+    fn other_settings_or_queries_miss_the_cache() {
+        let engine = engine().with_cache(true);
+        engine.estimate(QUERY).unwrap();
+
+        let engine = engine.with_bootstrap_size(60);
+        assert!(!engine.estimate(QUERY).unwrap()[0].is_cached());
+        let other = QUERY.replace("100000", "200000");
+        assert!(!engine.estimate(&other).unwrap()[0].is_cached());
+        assert!(engine.estimate(&other).unwrap()[0].is_cached());
+    }
+
+    #[test]
+    fn answers_without_estimates_are_cached_and_latency_targets_are_not() {
+        let engine = engine().with_cache(true);
+        let none = "select l_shipmode, count(*) from lineitem, orders where l_orderkey = o_orderkey group by l_shipmode having count(*) < 0";
+        assert!(engine.estimate(none).unwrap().is_empty());
+        let key = engine.cache_key(none).unwrap();
+        let cached = cached_estimates(engine.get_connection(), &key.0, &key.1).unwrap();
+        assert_eq!(cached.map(|estimates| estimates.len()), Some(0));
+
+        let engine = engine.with_latency_target(Some(60.0));
+        assert_eq!(engine.cache_key(QUERY), None);
+        engine.estimate(QUERY).unwrap();
+        assert!(!engine.estimate(QUERY).unwrap()[0].is_cached());
+    }
+
+    #[test]
+    fn rebuilt_samples_bump_the_version_and_invalidate_the_cache() {
+        let mut engine = engine().with_cache(true);
+        let version = sample_version(engine.get_connection()).unwrap();
+        engine.estimate(QUERY).unwrap();
+
+        engine.build_samples(0.2, Some(4)).unwrap();
+        assert_eq!(
+            sample_version(engine.get_connection()).unwrap(),
+            version + 1
+        );
+        let estimates = engine.estimate(QUERY).unwrap();
+        assert!(!estimates[0].is_cached());
+        assert_eq!(estimates[0].get_sample_fraction(), 0.2);
+        assert!(engine.estimate(QUERY).unwrap()[0].is_cached());
+
+        // the entries of the previous samples are dropped
+        let versions: i64 = engine
+            .get_connection()
+            .query_row(
+                "SELECT COUNT(DISTINCT version) FROM query_cache",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(versions, 1);
+    }
+
+    #[test]
+    fn without_the_cache_nothing_is_stored() {
+        let engine = engine();
+        engine.estimate(QUERY).unwrap();
+        assert!(!engine.estimate(QUERY).unwrap()[0].is_cached());
+        let rows: i64 = engine
+            .get_connection()
+            .query_row("SELECT COUNT(*) FROM query_cache", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 0);
+    }
+
+    #[test]
+    fn ladder_stops_at_the_first_sample_meeting_the_error_target() {
+        let mut engine = engine();
+        engine
+            .build_sample_ladder(&[0.05, 0.2, 0.5], Some(2))
+            .unwrap();
+        let fraction = |engine: &Engine| engine.estimate(QUERY).unwrap()[0].get_sample_fraction();
+
+        let engine = engine.with_error_target(Some(10.0));
+        assert_eq!(fraction(&engine), 0.05);
+        // no sample meets it, the largest is the best there is
+        let engine = engine.with_error_target(Some(1e-9));
+        assert_eq!(fraction(&engine), 0.5);
+        // without targets the largest sample is used
+        let engine = engine.with_error_target(None);
+        assert_eq!(fraction(&engine), 0.5);
+    }
+
+    #[test]
+    fn invalid_sample_fractions_are_rejected_before_sampling() {
+        let mut engine = engine();
+        for fractions in [vec![f64::NAN], vec![0.1, 0.0], vec![2.0], Vec::new()] {
+            assert!(matches!(
+                engine.build_sample_ladder(&fractions, None),
+                Err(AqpError::InvalidInput(_))
+            ));
+        }
+        assert!(matches!(
+            engine.build_samples(f64::NAN, Some(1)),
+            Err(AqpError::InvalidInput(_))
+        ));
+        // the samples already built are kept
+        assert_eq!(engine.load_samples().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn failed_cache_write_leaves_no_transaction_open() {
+        let engine = engine();
+        let conn = engine.get_connection();
+        let estimates = engine.estimate(QUERY).unwrap();
+        // a table of another layout makes the insert fail after the delete
+        conn.execute_batch(
+            "DROP TABLE query_cache; CREATE TABLE query_cache (query, settings, position, version)",
+        )
+        .unwrap();
+        assert!(cache_estimates(conn, "q", "s", &estimates).is_err());
+        conn.execute_batch("BEGIN; COMMIT").unwrap();
+    }
+
+    #[test]
+    fn lineitem_alone_is_answered_from_the_first_sample() {
+        let query = "select count(*), sum(l_quantity) from lineitem where l_discount > 0.05 and l_quantity < 30";
+        let mut answers = Vec::new();
+        let mut engine = engine();
+        for evaluator in [Evaluator::Sql, Evaluator::Rust, Evaluator::Columnar] {
+            engine = engine.with_evaluator(evaluator);
+            let estimates = engine.estimate(query).unwrap();
+            assert_eq!(estimates[0].get_sample_table(), "s1_sample");
+            assert!(estimates[0].get_matched_rows() > 0);
+            answers.push(
+                estimates
+                    .iter()
+                    .map(Estimate::get_estimate)
+                    .collect::<Vec<f64>>(),
+            );
+        }
+        assert!(answers.iter().all(|answer| *answer == answers[0]));
+    }
+
+    #[test]
+    fn every_evaluator_answers_equalities_and_text_the_same() {
+        let query = "select count(*), sum(l_quantity) from lineitem, orders where l_orderkey = o_orderkey and l_shipmode = 'AIR' and l_quantity >= 10 and l_tax <> 0.02 and o_totalprice <= 200000";
+        let mut engine = engine();
+        let mut answers = Vec::new();
+        for evaluator in [
+            Evaluator::Sql,
+            Evaluator::Rust,
+            Evaluator::Columnar,
+            Evaluator::Bench,
+        ] {
+            engine = engine.with_evaluator(evaluator);
+            let estimates = engine.estimate(query).unwrap();
+            answers.push(
+                estimates
+                    .iter()
+                    .map(Estimate::get_estimate)
+                    .collect::<Vec<f64>>(),
+            );
+        }
+        assert!(answers[0][0] > 0.0);
+        assert!(answers.iter().all(|answer| *answer == answers[0]));
+    }
+
+    #[test]
+    fn exact_fallback_answers_what_the_samples_cannot() {
+        let query = "select min(o_totalprice), max(o_totalprice) from orders";
+        assert!(matches!(
+            engine().estimate(query),
+            Err(AqpError::Unsupported(message)) if message.contains("starts at lineitem")
+        ));
+
+        let engine = engine().with_exact_fallback(true);
+        let estimates = engine.estimate(query).unwrap();
+        let (min, max): (f64, f64) = engine
+            .get_connection()
+            .query_row(
+                "SELECT MIN(o_totalprice), MAX(o_totalprice) FROM orders",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(values(&estimates), vec![(min, min, min), (max, max, max)]);
+        for estimate in &estimates {
+            assert!(estimate.get_exact_reason().unwrap().contains("lineitem"));
+        }
+    }
+
+    #[test]
+    fn estimates_name_the_sample_of_their_join_level() {
+        let engine = engine();
+        for estimate in engine.estimate(QUERY).unwrap() {
+            assert_eq!(estimate.get_sample_table(), "s2_sample");
+        }
+        let estimates = engine
+            .estimate("select count(*) from lineitem l, orders o, customer c where l.l_orderkey = o.o_orderkey and o.o_custkey = c.c_custkey and c_acctbal > 0")
+            .unwrap();
+        assert_eq!(estimates[0].get_sample_table(), "s3_sample");
+    }
+}
@@ -0,0 +1,173 @@
+//every way answering a query can fail, so callers can tell a bad query from a missing
+//sample or a sqlite failure instead of getting a panic
+use std::fmt;
+
+use nom::error::Error as NomError;
+
+#[derive(Debug)]
+pub enum AqpError {
+    //the query text does not parse, position is the byte offset where parsing stopped
+    Parse { message: String, position: usize },
+    //the query parses but uses something the samples cannot answer
+    Unsupported(String),
+    //the query refers to a table, alias or column the samples do not have, or compares
+    //a column with a literal of the wrong type
+    Semantic(String),
+    //a base or sample table the query needs is not in the database
+    MissingTable(String),
+    //no sample has been built or loaded yet
+    MissingSamples(String),
+    //no estimate or confidence interval can be computed, e.g. from an empty sample
+    Statistical(String),
+    //a bad command-line argument or input file
+    InvalidInput(String),
+    Sqlite(rusqlite::Error),
+    Io(std::io::Error),
+    Csv(csv::Error),
+    Arrow(arrow::error::ArrowError),
+    Parquet(parquet::errors::ParquetError),
+}
+
+impl AqpError {
+    //parse error of a query from the nom error, with the offset of the unparsed input
+    pub fn from_nom(query: &str, error: nom::Err<NomError<&str>>) -> Self {
+        let (position, message) = match error {
+            nom::Err::Error(error) | nom::Err::Failure(error) => (
+                query.len() - error.input.len(),
+                format!("expected {:?}", error.code),
+            ),
+            nom::Err::Incomplete(_) => (query.len(), "unexpected end of query".to_string()),
+        };
+        AqpError::Parse { message, position }
+    }
+
+    //parse error for input left over after a complete query
+    pub fn trailing_input(query: &str, rest: &str) -> Self {
+        AqpError::Parse {
+            message: format!("unexpected {:?}", rest.trim()),
+            position: query.len() - rest.len(),
+        }
+    }
+}
+
+impl fmt::Display for AqpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AqpError::Parse { message, position } => {
+                write!(f, "parse error at position {}: {}", position, message)
+            }
+            AqpError::Unsupported(message) => write!(f, "unsupported query: {}", message),
+            AqpError::Semantic(message) => write!(f, "invalid query: {}", message),
+            AqpError::MissingTable(table) => write!(f, "missing table: {}", table),
+            AqpError::MissingSamples(message) => write!(f, "missing samples: {}", message),
+            AqpError::Statistical(message) => write!(f, "cannot estimate: {}", message),
+            AqpError::InvalidInput(message) => write!(f, "invalid input: {}", message),
+            AqpError::Sqlite(error) => write!(f, "sqlite error: {}", error),
+            AqpError::Io(error) => write!(f, "i/o error: {}", error),
+            AqpError::Csv(error) => write!(f, "csv error: {}", error),
+            AqpError::Arrow(error) => write!(f, "arrow error: {}", error),
+            AqpError::Parquet(error) => write!(f, "parquet error: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for AqpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AqpError::Sqlite(error) => Some(error),
+            AqpError::Io(error) => Some(error),
+            AqpError::Csv(error) => Some(error),
+            AqpError::Arrow(error) => Some(error),
+            AqpError::Parquet(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for AqpError {
+    fn from(error: rusqlite::Error) -> Self {
+        // sqlite reports a missing table as a plain error message
+        if let rusqlite::Error::SqliteFailure(_, Some(message)) = &error {
+            if let Some(table) = message.strip_prefix("no such table: ") {
+                return AqpError::MissingTable(table.to_string());
+            }
+        }
+        AqpError::Sqlite(error)
+    }
+}
+
+impl From<std::io::Error> for AqpError {
+    fn from(error: std::io::Error) -> Self {
+        AqpError::Io(error)
+    }
+}
+
+impl From<csv::Error> for AqpError {
+    fn from(error: csv::Error) -> Self {
+        AqpError::Csv(error)
+    }
+}
+
+impl From<arrow::error::ArrowError> for AqpError {
+    fn from(error: arrow::error::ArrowError) -> Self {
+        AqpError::Arrow(error)
+    }
+}
+
+impl From<parquet::errors::ParquetError> for AqpError {
+    fn from(error: parquet::errors::ParquetError) -> Self {
+        AqpError::Parquet(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{parse_query, Engine};
+    use rusqlite::Connection;
+    use std::error::Error;
+
+    #[test]
+    fn missing_sqlite_table_is_named() {
+        let conn = Connection::open_in_memory().unwrap();
+        let error = AqpError::from(conn.execute("DELETE FROM s1_sample", []).unwrap_err());
+        assert!(matches!(&error, AqpError::MissingTable(table) if table == "s1_sample"));
+        assert_eq!(error.to_string(), "missing table: s1_sample");
+
+        // other sqlite failures keep their source
+        let error = AqpError::from(conn.execute("SELEC 1", []).unwrap_err());
+        assert!(matches!(error, AqpError::Sqlite(_)));
+        assert!(error.to_string().starts_with("sqlite error: "));
+        assert!(error.source().is_some());
+    }
+
+    #[test]
+    fn parse_errors_give_the_position_where_parsing_stopped() {
+        let query = "select count(*) from lineitem where l_tax > 0.01 )";
+        match parse_query(query) {
+            Err(error @ AqpError::Parse { position, .. }) => {
+                assert_eq!(query[position..].trim_start(), ")");
+                assert_eq!(
+                    error.to_string(),
+                    format!("parse error at position {}: unexpected \")\"", position)
+                );
+                assert!(error.source().is_none());
+            }
+            other => panic!("expected a parse error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn estimating_without_samples_is_an_error_not_a_panic() {
+        let engine = Engine::new(Connection::open_in_memory().unwrap());
+        assert!(matches!(
+            engine.estimate("select count(*) from lineitem"),
+            Err(AqpError::MissingSamples(_))
+        ));
+        // without the base tables there is no ground truth to compare with
+        assert!(matches!(
+            engine.ground_truth("select count(*) from lineitem"),
+            Ok(None)
+        ));
+    }
+}
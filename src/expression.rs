@@ -0,0 +1,687 @@
+//expressions of aggregate arguments and conditions: columns, literals, arithmetic,
+//case when and date arithmetic, evaluated per sampled row
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::parser::{Subquery, Where};
+
+//unit of an interval literal, e.g. `interval '3' month`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IntervalUnit {
+    Day,
+    Month,
+    Year,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    //column with the table or alias it was qualified with, e.g. `l.l_discount`
+    Column(Option<String>, String),
+    Number(f64),
+    //string literal without its quotes
+    Text(String),
+    //date literal as days since 1970-01-01
+    Date(i64),
+    Interval(i64, IntervalUnit),
+    Negate(Box<Expr>),
+    //arithmetic with one of + - * /
+    Binary(Box<Expr>, char, Box<Expr>),
+    //`case when <conditions> then <expr> ... [else <expr>] end`, the conditions of a
+    //branch are joined by and
+    Case(Vec<(Vec<Where>, Expr)>, Option<Box<Expr>>),
+    //right side of [not] in: literal values, a subquery, or the keys (see Value::key) the
+    //subquery gave once it was answered over the base tables
+    List(Vec<Expr>),
+    Subquery(Box<Subquery>),
+    Keys(Arc<HashSet<String>>),
+}
+
+//value of an expression for one row
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Number(f64),
+    Text(String),
+    Date(i64),
+    Interval(i64, IntervalUnit),
+}
+
+impl Value {
+    //value of a sample column stored as text: a number, a yyyy-mm-dd date or text
+    pub fn parse(text: &str) -> Value {
+        if let Ok(number) = text.parse::<f64>() {
+            return Value::Number(number);
+        }
+        match parse_date(text) {
+            Some(days) => Value::Date(days),
+            None => Value::Text(text.to_string()),
+        }
+    }
+
+    //value added to a sum, nulls count as 0
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Value::Number(number) => *number,
+            _ => 0.0,
+        }
+    }
+
+    //value as a key of count(distinct ...) or of a group, None for null which is not counted;
+    //parsing the key back gives the value again, except for intervals
+    pub fn key(&self) -> Option<String> {
+        match self {
+            Value::Null => None,
+            Value::Number(number) => Some(number.to_string()),
+            Value::Text(text) => Some(text.clone()),
+            Value::Date(days) => {
+                let (year, month, day) = civil_from_days(*days);
+                Some(format!("{:04}-{:02}-{:02}", year, month, day))
+            }
+            Value::Interval(count, unit) => Some(format!("interval {} {:?}", count, unit)),
+        }
+    }
+
+    //order of two values of the same kind, text holding a date compares as that date
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+            (Value::Date(a), Value::Date(b)) => Some(a.cmp(b)),
+            (Value::Text(a), Value::Text(b)) => Some(a.cmp(b)),
+            (Value::Date(a), Value::Text(b)) => parse_date(b).map(|b| a.cmp(&b)),
+            (Value::Text(a), Value::Date(b)) => parse_date(a).map(|a| a.cmp(b)),
+            _ => None,
+        }
+    }
+}
+
+//whether a comparison holds, false when either side is null or they cannot be compared
+pub fn compare(left: &Value, operator: &str, right: &Value) -> bool {
+    let ordering = match left.compare(right) {
+        Some(ordering) => ordering,
+        None => return false,
+    };
+    match operator {
+        "=" => ordering == Ordering::Equal,
+        "<>" => ordering != Ordering::Equal,
+        "<" => ordering == Ordering::Less,
+        "<=" => ordering != Ordering::Greater,
+        ">" => ordering == Ordering::Greater,
+        ">=" => ordering != Ordering::Less,
+        _ => false,
+    }
+}
+
+impl Expr {
+    //name of the column when the expression is a bare column
+    pub fn get_column(&self) -> Option<&str> {
+        match self {
+            Expr::Column(_, name) => Some(name),
+            _ => None,
+        }
+    }
+
+    //whether two expressions are the same, a column matching with or without its qualifier,
+    //e.g. `l.l_returnflag` in order by and `l_returnflag` in group by
+    pub fn is_same(&self, other: &Expr) -> bool {
+        match (self, other) {
+            (Expr::Column(_, name), Expr::Column(_, other)) => name == other,
+            _ => self == other,
+        }
+    }
+
+    //every column the expression reads, with its qualifier
+    pub fn columns(&self) -> Vec<(Option<&str>, &str)> {
+        let mut columns = Vec::new();
+        self.collect_columns(&mut columns);
+        columns
+    }
+
+    fn collect_columns<'a>(&'a self, columns: &mut Vec<(Option<&'a str>, &'a str)>) {
+        match self {
+            Expr::Column(qualifier, name) => columns.push((qualifier.as_deref(), name)),
+            Expr::Negate(expr) => expr.collect_columns(columns),
+            Expr::Binary(left, _, right) => {
+                left.collect_columns(columns);
+                right.collect_columns(columns);
+            }
+            Expr::Case(branches, otherwise) => {
+                for (conditions, result) in branches {
+                    for condition in conditions {
+                        condition.get_left_expr().collect_columns(columns);
+                        condition.get_right_expr().collect_columns(columns);
+                    }
+                    result.collect_columns(columns);
+                }
+                if let Some(otherwise) = otherwise {
+                    otherwise.collect_columns(columns);
+                }
+            }
+            Expr::List(values) => {
+                for value in values {
+                    value.collect_columns(columns);
+                }
+            }
+            // the columns of a subquery are its own, not the query's
+            Expr::Number(_)
+            | Expr::Text(_)
+            | Expr::Date(_)
+            | Expr::Interval(..)
+            | Expr::Subquery(_)
+            | Expr::Keys(_) => {}
+        }
+    }
+
+    //whether the value is one of the values of the right side of an in, false for null
+    pub fn contains(&self, value: &Value) -> bool {
+        match self {
+            Expr::List(values) => values
+                .iter()
+                .any(|item| compare(value, "=", &item.evaluate(&|_: &str| Value::Null))),
+            Expr::Keys(keys) => value.key().is_some_and(|key| keys.contains(&key)),
+            _ => false,
+        }
+    }
+
+    //value of the expression for a row, `row` gives the value of a column
+    pub fn evaluate<F: Fn(&str) -> Value>(&self, row: &F) -> Value {
+        match self {
+            Expr::Column(_, name) => row(name),
+            Expr::Number(number) => Value::Number(*number),
+            Expr::Text(text) => Value::Text(text.clone()),
+            Expr::Date(days) => Value::Date(*days),
+            Expr::Interval(count, unit) => Value::Interval(*count, *unit),
+            Expr::Negate(expr) => match expr.evaluate(row) {
+                Value::Number(number) => Value::Number(-number),
+                Value::Interval(count, unit) => Value::Interval(-count, unit),
+                _ => Value::Null,
+            },
+            Expr::Binary(left, operator, right) => {
+                arithmetic(left.evaluate(row), *operator, right.evaluate(row))
+            }
+            Expr::Case(branches, otherwise) => {
+                for (conditions, result) in branches {
+                    if conditions.iter().all(|condition| condition.evaluate(row)) {
+                        return result.evaluate(row);
+                    }
+                }
+                otherwise
+                    .as_ref()
+                    .map_or(Value::Null, |otherwise| otherwise.evaluate(row))
+            }
+            // a set of values has no value of its own, see contains
+            Expr::List(_) | Expr::Subquery(_) | Expr::Keys(_) => Value::Null,
+        }
+    }
+}
+
+impl Expr {
+    //the expression in sqlite syntax, for the exact answer over the base tables: dates are
+    //yyyy-mm-dd text there, so date arithmetic goes through date() and julianday()
+    pub fn to_sql(&self, is_text_column: &dyn Fn(&str) -> bool) -> String {
+        match self {
+            Expr::Column(Some(qualifier), name) => format!("{}.{}", qualifier, name),
+            Expr::Column(None, name) => name.clone(),
+            // always with a decimal point, so sqlite does not divide integers
+            Expr::Number(number) => format!("{:?}", number),
+            Expr::Text(text) => format!("'{}'", text.replace('\'', "''")),
+            Expr::Date(days) => {
+                let (year, month, day) = civil_from_days(*days);
+                format!("'{:04}-{:02}-{:02}'", year, month, day)
+            }
+            Expr::Interval(count, unit) => format!("'{:+} {}'", count, unit),
+            Expr::Negate(expr) => format!("-({})", expr.to_sql(is_text_column)),
+            Expr::Binary(left, operator, right) => {
+                let sql = |expr: &Expr| expr.to_sql(is_text_column);
+                let is_date = |expr: &Expr| expr.is_date_like(is_text_column);
+                match (left.as_ref(), *operator, right.as_ref()) {
+                    (date, '+' | '-', Expr::Interval(count, unit)) => {
+                        let count = if *operator == '-' { -count } else { *count };
+                        add_interval_sql(&sql(date), count, *unit)
+                    }
+                    (Expr::Interval(count, unit), '+', date) => {
+                        add_interval_sql(&sql(date), *count, *unit)
+                    }
+                    (a, '-', b) if is_date(a) && is_date(b) => {
+                        format!("(julianday({}) - julianday({}))", sql(a), sql(b))
+                    }
+                    (date, '+' | '-', days) if is_date(date) => {
+                        format!("date(julianday({}) {} {})", sql(date), operator, sql(days))
+                    }
+                    _ => format!("({} {} {})", sql(left), operator, sql(right)),
+                }
+            }
+            Expr::Case(branches, otherwise) => {
+                let mut sql = "case".to_string();
+                for (conditions, result) in branches {
+                    let conditions: Vec<String> = conditions
+                        .iter()
+                        .map(|condition| condition.to_sql(is_text_column))
+                        .collect();
+                    sql += &format!(
+                        " when {} then {}",
+                        conditions.join(" and "),
+                        result.to_sql(is_text_column)
+                    );
+                }
+                if let Some(otherwise) = otherwise {
+                    sql += &format!(" else {}", otherwise.to_sql(is_text_column));
+                }
+                sql + " end"
+            }
+            Expr::List(values) => {
+                let values: Vec<String> = values
+                    .iter()
+                    .map(|value| value.to_sql(is_text_column))
+                    .collect();
+                format!("({})", values.join(", "))
+            }
+            Expr::Subquery(subquery) => format!("({})", subquery.to_sql(is_text_column)),
+            Expr::Keys(keys) => {
+                let keys: Vec<String> = keys
+                    .iter()
+                    .map(|key| match key.parse::<f64>() {
+                        Ok(number) => format!("{:?}", number),
+                        Err(_) => format!("'{}'", key.replace('\'', "''")),
+                    })
+                    .collect();
+                format!("({})", keys.join(", "))
+            }
+        }
+    }
+
+    //whether the expression gives a date: a date literal, a text column holding dates or
+    //date arithmetic
+    fn is_date_like(&self, is_text_column: &dyn Fn(&str) -> bool) -> bool {
+        match self {
+            Expr::Date(_) => true,
+            Expr::Text(text) => parse_date(text).is_some(),
+            Expr::Column(_, name) => is_text_column(name),
+            Expr::Binary(left, '+' | '-', right) => match (left.as_ref(), right.as_ref()) {
+                (_, Expr::Interval(..)) | (Expr::Interval(..), _) => true,
+                (left, right) => {
+                    left.is_date_like(is_text_column) && !right.is_date_like(is_text_column)
+                }
+            },
+            _ => false,
+        }
+    }
+}
+
+//a date, stored as text or not, as days since 1970-01-01
+fn as_date(value: &Value) -> Option<i64> {
+    match value {
+        Value::Date(days) => Some(*days),
+        Value::Text(text) => parse_date(text),
+        _ => None,
+    }
+}
+
+fn arithmetic(left: Value, operator: char, right: Value) -> Value {
+    match (&left, operator, &right) {
+        (Value::Number(a), '+', Value::Number(b)) => Value::Number(a + b),
+        (Value::Number(a), '-', Value::Number(b)) => Value::Number(a - b),
+        (Value::Number(a), '*', Value::Number(b)) => Value::Number(a * b),
+        (Value::Number(a), '/', Value::Number(b)) if *b != 0.0 => Value::Number(a / b),
+        (_, '+', Value::Interval(count, unit)) | (Value::Interval(count, unit), '+', _) => {
+            let date = if let Value::Interval(..) = left {
+                &right
+            } else {
+                &left
+            };
+            as_date(date).map_or(Value::Null, |days| {
+                Value::Date(add_interval(days, *count, *unit))
+            })
+        }
+        (_, '-', Value::Interval(count, unit)) => as_date(&left).map_or(Value::Null, |days| {
+            Value::Date(add_interval(days, -count, *unit))
+        }),
+        // dates plus or minus a number of days, and the days between two dates
+        (_, '+', Value::Number(days)) => {
+            as_date(&left).map_or(Value::Null, |date| Value::Date(date + *days as i64))
+        }
+        (_, '-', Value::Number(days)) => {
+            as_date(&left).map_or(Value::Null, |date| Value::Date(date - *days as i64))
+        }
+        (_, '-', _) => match (as_date(&left), as_date(&right)) {
+            (Some(a), Some(b)) => Value::Number((a - b) as f64),
+            _ => Value::Null,
+        },
+        _ => Value::Null,
+    }
+}
+
+//date moved by a number of days, months or years, the day of month is clamped to the
+//length of the month as sql does (2024-01-31 + 1 month = 2024-02-29)
+fn add_interval(days: i64, count: i64, unit: IntervalUnit) -> i64 {
+    let months = match unit {
+        IntervalUnit::Day => return days + count,
+        IntervalUnit::Month => count,
+        IntervalUnit::Year => count * 12,
+    };
+    let (year, month, day) = civil_from_days(days);
+    let total = year * 12 + (month as i64 - 1) + months;
+    let (year, month) = (total.div_euclid(12), total.rem_euclid(12) as u32 + 1);
+    days_from_civil(year, month, day.min(days_in_month(year, month)))
+}
+
+//add_interval in sqlite, whose date() carries the days past the end of the month over to the
+//next one instead (2024-01-31 + 1 month = 2024-03-02), so the result is clamped to the last
+//day of the target month
+fn add_interval_sql(date: &str, count: i64, unit: IntervalUnit) -> String {
+    let months = match unit {
+        IntervalUnit::Day => return format!("date({}, '{:+} day')", date, count),
+        IntervalUnit::Month => count,
+        IntervalUnit::Year => count * 12,
+    };
+    format!(
+        "min(date({}, '{:+} month'), date({}, 'start of month', '{:+} month', '-1 day'))",
+        date,
+        months,
+        date,
+        months + 1
+    )
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+//days since 1970-01-01 of a proleptic gregorian date
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+//year, month and day of a number of days since 1970-01-01
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+//days since 1970-01-01 of a yyyy-mm-dd date, None if the text is not a valid date
+pub fn parse_date(text: &str) -> Option<i64> {
+    let mut parts = text.splitn(3, '-');
+    let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+    let (year, month, day) = (
+        year.parse::<i64>().ok()?,
+        month.parse::<u32>().ok()?,
+        day.parse::<u32>().ok()?,
+    );
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+    Some(days_from_civil(year, month, day))
+}
+
+//binding strength of an arithmetic operator, * and / before + and -
+pub fn precedence(operator: char) -> u8 {
+    match operator {
+        '*' | '/' => 2,
+        _ => 1,
+    }
+}
+
+impl fmt::Display for IntervalUnit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IntervalUnit::Day => write!(f, "day"),
+            IntervalUnit::Month => write!(f, "month"),
+            IntervalUnit::Year => write!(f, "year"),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Column(Some(qualifier), name) => write!(f, "{}.{}", qualifier, name),
+            Expr::Column(None, name) => write!(f, "{}", name),
+            Expr::Number(number) => write!(f, "{}", number),
+            Expr::Text(text) => write!(f, "'{}'", text),
+            Expr::Date(days) => {
+                let (year, month, day) = civil_from_days(*days);
+                write!(f, "date '{:04}-{:02}-{:02}'", year, month, day)
+            }
+            Expr::Interval(count, unit) => write!(f, "interval '{}' {}", count, unit),
+            Expr::Negate(expr) => write!(f, "-{}", expr),
+            Expr::Binary(left, operator, right) => {
+                // parentheses only where the precedence needs them
+                let precedence = precedence(*operator);
+                match left.as_ref() {
+                    Expr::Binary(_, inner, _) if self::precedence(*inner) < precedence => {
+                        write!(f, "({})", left)?
+                    }
+                    _ => write!(f, "{}", left)?,
+                }
+                write!(f, " {} ", operator)?;
+                match right.as_ref() {
+                    Expr::Binary(_, inner, _)
+                        if self::precedence(*inner) < precedence
+                            || (self::precedence(*inner) == precedence
+                                && (*operator == '-' || *operator == '/')) =>
+                    {
+                        write!(f, "({})", right)
+                    }
+                    _ => write!(f, "{}", right),
+                }
+            }
+            Expr::Case(branches, otherwise) => {
+                write!(f, "case")?;
+                for (conditions, result) in branches {
+                    let conditions: Vec<String> = conditions
+                        .iter()
+                        .map(|condition| condition.to_string())
+                        .collect();
+                    write!(f, " when {} then {}", conditions.join(" and "), result)?;
+                }
+                if let Some(otherwise) = otherwise {
+                    write!(f, " else {}", otherwise)?;
+                }
+                write!(f, " end")
+            }
+            Expr::List(values) => {
+                let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+                write!(f, "({})", values.join(", "))
+            }
+            Expr::Subquery(subquery) => write!(f, "({})", subquery),
+            Expr::Keys(keys) => write!(f, "({} keys)", keys.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::parse_query;
+    use rusqlite::Connection;
+
+    //argument of sum(<expression>) as the parser builds it
+    fn expression(text: &str) -> Expr {
+        let query = parse_query(&format!("select sum({}) from lineitem", text)).unwrap();
+        query.get_select().get_functions()[0]
+            .get_argument()
+            .unwrap()
+            .clone()
+    }
+
+    fn date(text: &str) -> i64 {
+        parse_date(text).unwrap()
+    }
+
+    #[test]
+    fn dates_round_trip_through_days() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(date("1969-12-31"), -1);
+        for (year, month, day) in [(1992, 1, 1), (1996, 2, 29), (1998, 12, 31), (2000, 3, 1)] {
+            assert_eq!(
+                civil_from_days(days_from_civil(year, month, day)),
+                (year, month, day)
+            );
+        }
+        for text in [
+            "1995-02-29",
+            "1995-13-01",
+            "1995-00-10",
+            "95-01-01",
+            "1995-1-01",
+            "AIR",
+        ] {
+            assert_eq!(parse_date(text), None, "{}", text);
+        }
+        assert_eq!(Value::parse("1996-02-29"), Value::Date(date("1996-02-29")));
+    }
+
+    #[test]
+    fn intervals_clamp_the_day_to_the_month() {
+        let add = |text: &str, count: i64, unit: IntervalUnit| {
+            let (year, month, day) = civil_from_days(add_interval(date(text), count, unit));
+            format!("{:04}-{:02}-{:02}", year, month, day)
+        };
+        assert_eq!(add("2024-01-31", 1, IntervalUnit::Month), "2024-02-29");
+        assert_eq!(add("2023-01-31", 1, IntervalUnit::Month), "2023-02-28");
+        assert_eq!(add("2024-03-31", -1, IntervalUnit::Month), "2024-02-29");
+        assert_eq!(add("1995-11-15", 3, IntervalUnit::Month), "1996-02-15");
+        assert_eq!(add("2020-02-29", 1, IntervalUnit::Year), "2021-02-28");
+        assert_eq!(add("1998-12-01", -90, IntervalUnit::Day), "1998-09-02");
+    }
+
+    #[test]
+    fn expressions_evaluate_per_row() {
+        let row = |column: &str| match column {
+            "l_extendedprice" => Value::Number(1000.0),
+            "l_discount" => Value::Number(0.1),
+            "l_quantity" => Value::Number(0.0),
+            "l_shipdate" => Value::Text("1998-09-01".to_string()),
+            "l_shipmode" => Value::Text("AIR".to_string()),
+            _ => Value::Null,
+        };
+        let evaluate = |text: &str| expression(text).evaluate(&row);
+
+        assert_eq!(
+            evaluate("l_extendedprice * (1 - l_discount)"),
+            Value::Number(900.0)
+        );
+        assert_eq!(evaluate("-l_discount * 10"), Value::Number(-1.0));
+        assert_eq!(evaluate("l_extendedprice / l_quantity"), Value::Null);
+        assert_eq!(
+            evaluate("case when l_shipmode = 'AIR' and l_discount > 0.05 then 1 else 0 end"),
+            Value::Number(1.0)
+        );
+        assert_eq!(
+            evaluate("case when l_shipmode = 'MAIL' then 1 end"),
+            Value::Null
+        );
+        assert_eq!(
+            evaluate("date '1998-12-01' - interval '90' day"),
+            Value::Date(date("1998-09-02"))
+        );
+        assert_eq!(
+            evaluate("l_shipdate - date '1998-08-01'"),
+            Value::Number(31.0)
+        );
+        assert_eq!(evaluate("l_shipdate + 30"), Value::Date(date("1998-10-01")));
+        // a missing column is null and nulls add nothing to a sum
+        assert_eq!(evaluate("l_tax + 1").to_f64(), 0.0);
+    }
+
+    #[test]
+    fn display_keeps_only_the_parentheses_precedence_needs() {
+        for text in [
+            "l_extendedprice * (1 - l_discount)",
+            "(l_tax + 1) * 2",
+            "l_tax - (l_discount - 1)",
+            "l_tax / (l_discount * 2)",
+            "l_tax * l_discount + 1",
+        ] {
+            assert_eq!(expression(text).to_string(), text);
+        }
+        assert_eq!(
+            expression("(l_tax * l_discount) + 1").to_string(),
+            "l_tax * l_discount + 1"
+        );
+    }
+
+    #[test]
+    fn sqlite_gives_the_same_values_as_the_rust_evaluator() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE lineitem (l_extendedprice REAL, l_discount REAL, l_quantity INTEGER, l_shipdate TEXT, l_shipmode TEXT);
+             INSERT INTO lineitem VALUES (1000, 0.1, 7, '1998-09-01', 'AIR'), (250, 0.04, 3, '1996-01-31', 'MAIL');",
+        )
+        .unwrap();
+        let is_text_column = |column: &str| column == "l_shipdate" || column == "l_shipmode";
+        for text in [
+            "l_extendedprice * (1 - l_discount) / l_quantity",
+            "case when l_shipmode = 'AIR' then l_extendedprice else 0 end",
+            "l_shipdate - date '1995-01-01'",
+            "case when l_shipdate + interval '1' month <= date '1996-02-29' then 1 else 0 end",
+            "case when l_shipdate <= date '1998-12-01' - interval '90' day then 1 else 0 end",
+        ] {
+            let expr = expression(text);
+            let sql = format!(
+                "SELECT {}, l_extendedprice, l_discount, l_quantity, l_shipdate, l_shipmode FROM lineitem",
+                expr.to_sql(&is_text_column)
+            );
+            let mut stmt = conn.prepare(&sql).unwrap();
+            let mut rows = stmt.query([]).unwrap();
+            while let Some(row) = rows.next().unwrap() {
+                let expected: f64 = row.get(0).unwrap();
+                let values = [
+                    Value::Number(row.get(1).unwrap()),
+                    Value::Number(row.get(2).unwrap()),
+                    Value::Number(row.get::<_, i64>(3).unwrap() as f64),
+                    Value::Text(row.get(4).unwrap()),
+                    Value::Text(row.get(5).unwrap()),
+                ];
+                let columns = [
+                    "l_extendedprice",
+                    "l_discount",
+                    "l_quantity",
+                    "l_shipdate",
+                    "l_shipmode",
+                ];
+                let value = expr.evaluate(&|column: &str| {
+                    columns
+                        .iter()
+                        .position(|name| *name == column)
+                        .map_or(Value::Null, |i| values[i].clone())
+                });
+                assert!(
+                    (value.to_f64() - expected).abs() < 1e-9,
+                    "{}: {:?} vs {}",
+                    text,
+                    value,
+                    expected
+                );
+            }
+        }
+    }
+}
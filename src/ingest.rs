@@ -0,0 +1,559 @@
+use rand::prelude::*;
+use rusqlite::{params_from_iter, types::Value, Connection};
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use crate::error::AqpError;
+use crate::sampling::{join_sample_set, reset_catalog, SampleSet};
+
+//column name and sqlite type of the TPC-H tables, in dbgen column order
+const REGION: &[(&str, &str)] = &[
+    ("r_regionkey", "INTEGER"),
+    ("r_name", "TEXT"),
+    ("r_comment", "TEXT"),
+];
+
+const NATION: &[(&str, &str)] = &[
+    ("n_nationkey", "INTEGER"),
+    ("n_name", "TEXT"),
+    ("n_regionkey", "INTEGER"),
+    ("n_comment", "TEXT"),
+];
+
+const CUSTOMER: &[(&str, &str)] = &[
+    ("c_custkey", "INTEGER"),
+    ("c_name", "TEXT"),
+    ("c_address", "TEXT"),
+    ("c_nationkey", "INTEGER"),
+    ("c_phone", "TEXT"),
+    ("c_acctbal", "REAL"),
+    ("c_mktsegment", "TEXT"),
+    ("c_comment", "TEXT"),
+];
+
+const ORDERS: &[(&str, &str)] = &[
+    ("o_orderkey", "INTEGER"),
+    ("o_custkey", "INTEGER"),
+    ("o_orderstatus", "TEXT"),
+    ("o_totalprice", "REAL"),
+    ("o_orderdate", "TEXT"),
+    ("o_orderpriority", "TEXT"),
+    ("o_clerk", "TEXT"),
+    ("o_shippriority", "INTEGER"),
+    ("o_comment", "TEXT"),
+];
+
+const LINEITEM: &[(&str, &str)] = &[
+    ("l_orderkey", "INTEGER"),
+    ("l_partkey", "INTEGER"),
+    ("l_suppkey", "INTEGER"),
+    ("l_linenumber", "INTEGER"),
+    ("l_quantity", "REAL"),
+    ("l_extendedprice", "REAL"),
+    ("l_discount", "REAL"),
+    ("l_tax", "REAL"),
+    ("l_returnflag", "TEXT"),
+    ("l_linestatus", "TEXT"),
+    ("l_shipdate", "TEXT"),
+    ("l_commitdate", "TEXT"),
+    ("l_receiptdate", "TEXT"),
+    ("l_shipinstruct", "TEXT"),
+    ("l_shipmode", "TEXT"),
+    ("l_comment", "TEXT"),
+];
+
+const PART: &[(&str, &str)] = &[
+    ("p_partkey", "INTEGER"),
+    ("p_name", "TEXT"),
+    ("p_mfgr", "TEXT"),
+    ("p_brand", "TEXT"),
+    ("p_type", "TEXT"),
+    ("p_size", "INTEGER"),
+    ("p_container", "TEXT"),
+    ("p_retailprice", "REAL"),
+    ("p_comment", "TEXT"),
+];
+
+const SUPPLIER: &[(&str, &str)] = &[
+    ("s_suppkey", "INTEGER"),
+    ("s_name", "TEXT"),
+    ("s_address", "TEXT"),
+    ("s_nationkey", "INTEGER"),
+    ("s_phone", "TEXT"),
+    ("s_acctbal", "REAL"),
+    ("s_comment", "TEXT"),
+];
+
+const PARTSUPP: &[(&str, &str)] = &[
+    ("ps_partkey", "INTEGER"),
+    ("ps_suppkey", "INTEGER"),
+    ("ps_availqty", "INTEGER"),
+    ("ps_supplycost", "REAL"),
+    ("ps_comment", "TEXT"),
+];
+
+//built-in schema of a TPC-H table
+pub fn tpch_schema(table: &str) -> Option<Vec<(String, String)>> {
+    let columns = match table {
+        "region" => REGION,
+        "nation" => NATION,
+        "customer" => CUSTOMER,
+        "orders" => ORDERS,
+        "lineitem" => LINEITEM,
+        "part" => PART,
+        "supplier" => SUPPLIER,
+        "partsupp" => PARTSUPP,
+        _ => return None,
+    };
+
+    Some(
+        columns
+            .iter()
+            .map(|(name, column_type)| (name.to_string(), column_type.to_string()))
+            .collect(),
+    )
+}
+
+//a table source: a dbgen .tbl file with the built-in TPC-H schema,
+//or a csv file with a `<table>.schema` file listing one `name TYPE` per line
+pub struct TableSource {
+    table: String,
+    path: PathBuf,
+    delimiter: u8,
+    header: bool,
+    columns: Vec<(String, String)>,
+}
+
+impl TableSource {
+    //find `<table>.tbl` or `<table>.csv` in a directory
+    pub fn find(dir: &Path, table: &str) -> Result<Option<TableSource>, AqpError> {
+        let tbl = dir.join(format!("{}.tbl", table));
+        let csv = dir.join(format!("{}.csv", table));
+        let schema = dir.join(format!("{}.schema", table));
+
+        if csv.exists() {
+            let columns = if schema.exists() {
+                read_schema_file(&schema)?
+            } else {
+                tpch_schema(table).ok_or_else(|| {
+                    AqpError::InvalidInput(format!(
+                        "{} has no schema file {}",
+                        csv.display(),
+                        schema.display()
+                    ))
+                })?
+            };
+            return Ok(Some(TableSource {
+                table: table.to_string(),
+                path: csv,
+                delimiter: b',',
+                header: true,
+                columns,
+            }));
+        }
+
+        if tbl.exists() {
+            let columns = tpch_schema(table)
+                .ok_or_else(|| {
+                    AqpError::InvalidInput(format!("{} is not a TPC-H table", tbl.display()))
+                })?;
+            return Ok(Some(TableSource {
+                table: table.to_string(),
+                path: tbl,
+                delimiter: b'|',
+                header: false,
+                columns,
+            }));
+        }
+
+        Ok(None)
+    }
+
+    pub fn get_table(&self) -> &str {
+        &self.table
+    }
+
+    //index of a column in the source rows
+    fn column_index(&self, name: &str) -> Result<usize, AqpError> {
+        self.columns
+            .iter()
+            .position(|(column, _)| column == name)
+            .ok_or_else(|| {
+                AqpError::InvalidInput(format!("{} has no column {}", self.path.display(), name))
+            })
+    }
+
+    //CREATE TABLE statement for this source under the given table name
+    fn create_table_sql(&self, table: &str) -> String {
+        let columns: Vec<String> = self
+            .columns
+            .iter()
+            .map(|(name, column_type)| format!("{} {}", name, column_type))
+            .collect();
+        format!("CREATE TABLE {} ({})", table, columns.join(", "))
+    }
+
+    fn insert_sql(&self, table: &str) -> String {
+        let placeholders = vec!["?"; self.columns.len()].join(", ");
+        format!("INSERT INTO {} VALUES ({})", table, placeholders)
+    }
+
+    //stream the rows of the file, converted to sqlite values with the schema types
+    fn for_each_row<F>(&self, mut f: F) -> Result<u64, AqpError>
+    where
+        F: FnMut(&[Value]) -> Result<(), AqpError>,
+    {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .has_headers(self.header)
+            .flexible(true)
+            .from_reader(File::open(&self.path)?);
+
+        let mut values = Vec::with_capacity(self.columns.len());
+        let mut count = 0;
+        for record in reader.records() {
+            let record = record?;
+            values.clear();
+            // dbgen terminates every line with `|`, which reads as an extra empty field
+            for (i, (_, column_type)) in self.columns.iter().enumerate() {
+                values.push(to_value(record.get(i).unwrap_or(""), column_type));
+            }
+            f(&values)?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+
+//read a schema file with one `name TYPE` per line
+fn read_schema_file(path: &Path) -> Result<Vec<(String, String)>, AqpError> {
+    let mut columns = Vec::new();
+    for line in std::fs::read_to_string(path)?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.split_whitespace();
+        let name = parts.next().unwrap_or_default().to_lowercase();
+        let column_type = parts.next().unwrap_or("TEXT").to_uppercase();
+        columns.push((name, column_type));
+    }
+
+    Ok(columns)
+}
+
+//convert a field to a sqlite value following the column type affinity
+fn to_value(field: &str, column_type: &str) -> Value {
+    if field.is_empty() {
+        return Value::Null;
+    }
+    if column_type.contains("INT") {
+        if let Ok(i) = field.parse::<i64>() {
+            return Value::Integer(i);
+        }
+    } else if column_type.contains("REAL")
+        || column_type.contains("FLOA")
+        || column_type.contains("DOUB")
+        || column_type.contains("NUM")
+        || column_type.contains("DEC")
+    {
+        if let Ok(f) = field.parse::<f64>() {
+            return Value::Real(f);
+        }
+    }
+    Value::Text(field.to_string())
+}
+
+fn key_of(value: &Value) -> Option<i64> {
+    match value {
+        Value::Integer(i) => Some(*i),
+        _ => None,
+    }
+}
+
+//load every row of a source into a sqlite table, keeping only the rows whose key is in `keys`
+fn load_table(
+    conn: &Connection,
+    source: &TableSource,
+    table: &str,
+    key: Option<(&str, &HashSet<i64>)>,
+) -> Result<u64, AqpError> {
+    conn.execute(&format!("DROP TABLE IF EXISTS {}", table), [])?;
+    conn.execute(&source.create_table_sql(table), [])?;
+
+    let key_index = match key {
+        Some((column, _)) => Some(source.column_index(column)?),
+        None => None,
+    };
+
+    let mut stmt = conn.prepare(&source.insert_sql(table))?;
+    let mut loaded = 0;
+    source.for_each_row(|values| {
+        if let (Some(index), Some((_, keys))) = (key_index, key) {
+            if !key_of(&values[index]).is_some_and(|k| keys.contains(&k)) {
+                return Ok(());
+            }
+        }
+        stmt.execute(params_from_iter(values.iter()))?;
+        loaded += 1;
+        Ok(())
+    })?;
+
+    Ok(loaded)
+}
+
+//collect the distinct integer values of a column of a sqlite table
+fn column_keys(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+) -> Result<HashSet<i64>, AqpError> {
+    let mut stmt = conn.prepare(&format!("SELECT DISTINCT {} FROM {}", column, table))?;
+    let keys = stmt
+        .query_map([], |row| row.get::<_, i64>(0))?
+        .collect::<Result<HashSet<i64>, _>>()?;
+    Ok(keys)
+}
+
+//read dbgen .tbl (or csv) files from a directory and build the sample sets while streaming lineitem.
+//Each lineitem row draws one uniform number and belongs to every sample whose fraction exceeds it,
+//so the samples are nested Bernoulli samples. Only the dimension rows the samples join with are
+//kept unless `keep_base_tables` is set, in which case every base table is written to sqlite too.
+//The same seed draws the same samples from the same files, and a failure (e.g. a malformed row)
+//rolls everything back, previous samples included.
+pub fn ingest(
+    conn: &Connection,
+    dir: &Path,
+    sample_fractions: &[f64],
+    keep_base_tables: bool,
+    seed: Option<u64>,
+) -> Result<Vec<SampleSet>, AqpError> {
+    let source = |table: &str| -> Result<TableSource, AqpError> {
+        TableSource::find(dir, table)?.ok_or_else(|| {
+            AqpError::MissingTable(format!(
+                "{} (no {}.tbl or {}.csv in {})",
+                table,
+                table,
+                table,
+                dir.display()
+            ))
+        })
+    };
+
+    let mut fractions = sample_fractions.to_vec();
+    fractions.sort_by(f64::total_cmp);
+    fractions.dedup();
+
+    // rolled back when dropped on an error, so the connection stays usable
+    let transaction = conn.unchecked_transaction()?;
+    reset_catalog(conn)?;
+
+    let mut sets: Vec<SampleSet> = fractions
+        .iter()
+        .enumerate()
+        .map(|(rung, &fraction)| {
+            let suffix = if fractions.len() == 1 {
+                String::new()
+            } else {
+                format!("_r{}", rung)
+            };
+            SampleSet::new(suffix, fraction, 0)
+        })
+        .collect();
+
+    // Stream lineitem once, writing the sampled rows of every rung
+    let lineitem = source("lineitem")?;
+    if keep_base_tables {
+        conn.execute("DROP TABLE IF EXISTS lineitem", [])?;
+        conn.execute(&lineitem.create_table_sql("lineitem"), [])?;
+    }
+    for set in &sets {
+        conn.execute(&format!("DROP TABLE IF EXISTS {}", set.table(1)), [])?;
+        conn.execute(&lineitem.create_table_sql(&set.table(1)), [])?;
+    }
+    let mut base_insert = if keep_base_tables {
+        Some(conn.prepare(&lineitem.insert_sql("lineitem"))?)
+    } else {
+        None
+    };
+    let mut sample_inserts = sets
+        .iter()
+        .map(|set| conn.prepare(&lineitem.insert_sql(&set.table(1))))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut sample_rows = vec![0i64; sets.len()];
+
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let total_rows = lineitem.for_each_row(|values| {
+        if let Some(base_insert) = base_insert.as_mut() {
+            base_insert.execute(params_from_iter(values.iter()))?;
+        }
+        let u: f64 = rng.gen();
+        for (rung, fraction) in fractions.iter().enumerate() {
+            if u < *fraction {
+                sample_inserts[rung].execute(params_from_iter(values.iter()))?;
+                sample_rows[rung] += 1;
+            }
+        }
+        Ok(())
+    })?;
+    drop(base_insert);
+    drop(sample_inserts);
+    info!("lineitem: {} rows streamed", total_rows);
+
+    for (set, rows) in sets.iter_mut().zip(&sample_rows) {
+        *set = SampleSet::new(set.get_suffix().to_string(), set.get_fraction(), *rows);
+        info!("{} table created with sampled data.", set.table(1));
+    }
+
+    // Load the dimension tables, only keeping the rows reachable from the largest sample
+    // unless the base tables are kept. The restricted ones are temp tables, so they shadow
+    // nothing once the samples are built and dropped.
+    let largest = sets.last().map(|set| set.table(1)).unwrap_or_default();
+    let dimensions = [
+        ("orders", "o_orderkey", "l_orderkey", largest.clone()),
+        ("customer", "c_custkey", "o_custkey", "orders".to_string()),
+        (
+            "nation",
+            "n_nationkey",
+            "c_nationkey",
+            "customer".to_string(),
+        ),
+        ("region", "r_regionkey", "n_regionkey", "nation".to_string()),
+    ];
+    for (table, key, foreign_key, parent) in dimensions.iter() {
+        let source = source(table)?;
+        let loaded = if keep_base_tables {
+            load_table(conn, &source, table, None)?
+        } else {
+            let keys = column_keys(conn, parent, foreign_key)?;
+            load_table(
+                conn,
+                &source,
+                &format!("temp.{}", table),
+                Some((key, &keys)),
+            )?
+        };
+        info!("{}: {} rows loaded", table, loaded);
+    }
+
+    if keep_base_tables {
+        for table in ["part", "supplier", "partsupp"] {
+            if let Some(source) = TableSource::find(dir, table)? {
+                let loaded = load_table(conn, &source, source.get_table(), None)?;
+                info!("{}: {} rows loaded", table, loaded);
+            }
+        }
+    }
+
+    for set in &sets {
+        join_sample_set(conn, set)?;
+    }
+
+    if !keep_base_tables {
+        for (table, _, _, _) in dimensions.iter() {
+            conn.execute(&format!("DROP TABLE IF EXISTS temp.{}", table), [])?;
+        }
+    }
+    transaction.commit()?;
+
+    Ok(sets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    //a directory of dbgen-like .tbl files written from a generated database
+    fn tbl_dir(name: &str, tables: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("aqprius_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let conn = test_support::database(5);
+        for table in tables {
+            let columns: Vec<String> = tpch_schema(table)
+                .unwrap()
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect();
+            let mut stmt = conn
+                .prepare(&format!("SELECT {} FROM {}", columns.join(", "), table))
+                .unwrap();
+            let mut rows = stmt.query([]).unwrap();
+            let mut tbl = String::new();
+            while let Some(row) = rows.next().unwrap() {
+                for i in 0..columns.len() {
+                    match row.get::<_, Value>(i).unwrap() {
+                        Value::Integer(i) => tbl.push_str(&i.to_string()),
+                        Value::Real(f) => tbl.push_str(&f.to_string()),
+                        Value::Text(s) => tbl.push_str(&s),
+                        _ => {}
+                    }
+                    tbl.push('|');
+                }
+                tbl.push('\n');
+            }
+            std::fs::write(dir.join(format!("{}.tbl", table)), tbl).unwrap();
+        }
+        dir
+    }
+
+    const TABLES: [&str; 5] = ["lineitem", "orders", "customer", "nation", "region"];
+
+    fn rows(conn: &Connection, table: &str) -> Vec<(i64, i64)> {
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT l_orderkey, l_linenumber FROM {} ORDER BY 1, 2",
+                table
+            ))
+            .unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn seeded_ingest_is_reproducible_and_nested() {
+        let dir = tbl_dir("seeded", &TABLES);
+        let ingested = |seed| {
+            let conn = Connection::open_in_memory().unwrap();
+            let sets = ingest(&conn, &dir, &[0.5, 0.1], false, Some(seed)).unwrap();
+            assert_eq!(sets[0].get_fraction(), 0.1);
+            let small = rows(&conn, &sets[0].table(5));
+            let large = rows(&conn, &sets[1].table(5));
+            assert_eq!(small.len() as i64, sets[0].get_rows());
+            assert!(small.iter().all(|row| large.contains(row)));
+            (small, large)
+        };
+
+        let first = ingested(3);
+        assert!(!first.0.is_empty());
+        assert_eq!(ingested(3), first);
+        assert_ne!(ingested(4), first);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_ingest_keeps_the_previous_samples_and_a_usable_connection() {
+        let full = tbl_dir("full", &TABLES);
+        let conn = Connection::open_in_memory().unwrap();
+        let sets = ingest(&conn, &full, &[0.2], false, Some(1)).unwrap();
+        let sampled = rows(&conn, &sets[0].table(5));
+
+        // orders is missing, which is only found out after lineitem was streamed
+        let partial = tbl_dir("partial", &["lineitem"]);
+        let result = ingest(&conn, &partial, &[0.2], false, Some(2));
+        assert!(matches!(result, Err(AqpError::MissingTable(_))));
+
+        conn.execute_batch("BEGIN; COMMIT").unwrap();
+        assert_eq!(rows(&conn, &sets[0].table(5)), sampled);
+        assert_eq!(crate::sampling::sample_version(&conn).unwrap(), 1);
+        std::fs::remove_dir_all(&full).unwrap();
+        std::fs::remove_dir_all(&partial).unwrap();
+    }
+}
@@ -0,0 +1,30 @@
+//aqprius: approximate answers to aggregate queries over TPC-H tables in sqlite, with
+//bootstrap confidence intervals computed from samples of lineitem and its joins
+#[macro_use]
+pub mod output;
+pub mod benchmark;
+mod bootstrap;
+mod columnar;
+mod data_sampling;
+pub mod engine;
+mod error;
+mod expression;
+mod ingest;
+mod parquet_store;
+mod parser;
+mod pushdown;
+mod samples;
+pub mod sampling;
+mod tpch_gen;
+#[cfg(test)]
+mod test_support;
+mod validation;
+pub mod workload;
+
+pub use engine::{
+    Engine, Estimate, Evaluator, GroundTruth, DEFAULT_BOOTSTRAP_SIZE, MIN_MATCHED_ROWS,
+};
+pub use error::AqpError;
+pub use output::{OutputFormat, QueryReport};
+pub use sampling::SampleSet;
+pub use workload::{read_workload, WorkloadQuery};
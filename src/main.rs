@@ -1,40 +1,15 @@
-mod bootstrap;
-mod data_sampling;
-#[allow(dead_code)]
-mod parser;
-mod samples;
-mod sampling;
-
-use rayon::prelude::*;
-use rusqlite::Connection;
+use aqprius::benchmark::{run_benchmark, write_benchmark};
+use aqprius::output::{run_workload, set_output_format, write_reports};
+use aqprius::sampling::is_sample_fraction;
+use aqprius::{info, read_workload, AqpError, Engine, Evaluator, OutputFormat, WorkloadQuery};
 use std::env;
+use std::path::Path;
+use std::str::FromStr;
 use std::time::Instant;
 
-
-use crate::sampling::create_sample_tables;
-#[allow(unused_imports)]
-use crate::{
-    bootstrap::{
-        bootstrap_sums, calculate_mean, calculate_variance, random_sample_with_replacement,
-    },
-    data_sampling::{create_sample, groundtruth, s1_sample_hashmap, S1Sample},
-    parser::{parse_sql_query, Where},
-    samples::{
-        fetch_s2_sample, fetch_s3_sample, fetch_s4_sample, fetch_s5_sample, get_query_result,
-        s2_sample_to_hashmap, s3_sample_to_hashmap, s4_sample_to_hashmap, s5_sample_to_hashmap,
-        S2Sample, S3Sample,
-    },
-};
-
-//making the connection to the database
-fn db_connection(db_file: &str) -> Result<rusqlite::Connection, rusqlite::Error> {
-    let conn = rusqlite::Connection::open(db_file)?;
-    Ok(conn)
-}
-
-fn read_query(file_path: &str) -> String {
-    let query = std::fs::read_to_string(file_path).unwrap();
-    query
+fn read_query(file_path: &str) -> Result<String, AqpError> {
+    let query = std::fs::read_to_string(file_path)?;
+    Ok(query)
 }
 
 /// Retrieves the value associated with a specific command-line flag.
@@ -42,208 +17,281 @@ fn read_query(file_path: &str) -> String {
 fn get_argument_value<'a>(args: &'a [String], flag: &'a str) -> Option<&'a String> {
     args.iter()
         .position(|arg| arg == flag)
-        .map(|i| &args[i + 1])
+        .and_then(|i| args.get(i + 1))
 }
 
-//function to seperate join_condtion and selection condition
-fn separate_conditions(where_conditions: Vec<Where>) -> (Vec<Where>, Vec<Where>) {
-    let join_conditions: Vec<Where> = where_conditions
-        .clone()
-        .into_iter()
-        .filter(|condition| condition.get_operator() == "=")
-        .collect();
+/// Parses the value of a flag, `what` describes the expected value in the error.
+/// Returns `None` if the flag is not found.
+fn parse_argument<T: FromStr>(args: &[String], flag: &str, what: &str) -> Result<Option<T>, AqpError> {
+    get_argument_value(args, flag)
+        .map(|value| {
+            value
+                .trim()
+                .parse::<T>()
+                .map_err(|_| AqpError::InvalidInput(format!("{} {:?} must be {}", flag, value, what)))
+        })
+        .transpose()
+}
+
+/// Parses the comma-separated values of a flag, e.g. `-l 0.1,1,10`.
+/// Returns `None` if the flag is not found.
+fn parse_list_argument<T: FromStr>(
+    args: &[String],
+    flag: &str,
+    what: &str,
+) -> Result<Option<Vec<T>>, AqpError> {
+    get_argument_value(args, flag)
+        .map(|value| {
+            value
+                .split(',')
+                .map(|item| {
+                    item.trim().parse::<T>().map_err(|_| {
+                        AqpError::InvalidInput(format!("{} {:?} must be {}", flag, value, what))
+                    })
+                })
+                .collect()
+        })
+        .transpose()
+}
 
-    let selection_conditions: Vec<Where> = where_conditions
-        .into_iter()
-        .filter(|condition| condition.get_operator() == "<" || condition.get_operator() == ">")
-        .collect();
+/// Checks that the percentages of a flag, already divided by 100, are in (0, 100].
+fn check_percentages(flag: &str, fractions: &[f64]) -> Result<(), AqpError> {
+    let invalid = fractions
+        .iter()
+        .find(|&&fraction| !is_sample_fraction(fraction));
+    match invalid {
+        Some(fraction) => Err(AqpError::InvalidInput(format!(
+            "{} {} must be a percentage in (0, 100]",
+            flag,
+            fraction * 100.0
+        ))),
+        None => Ok(()),
+    }
+}
 
-    (join_conditions, selection_conditions)
+/// Returns `true` if a flag without a value is present.
+fn has_flag(args: &[String], flag: &str) -> bool {
+    args.iter().any(|arg| arg == flag)
 }
 
 fn main() {
+    if let Err(error) = run() {
+        eprintln!("error: {}", error);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), AqpError> {
     // Start timing
     let start = Instant::now();
     // Collect the command-line arguments into a vector of strings.
     let args: Vec<String> = env::args().collect();
 
     // Verify that the required number of arguments is provided.
-    if args.len() < 7 {
+    if args.len() < 5 {
         // Print the usage message and exit the program with an error code.
         eprintln!(
-            "Usage: {} -d <database> -s <sample_fraction> -b <bootstrap_num>",
+            "Usage: {} -d <database> -b <bootstrap_num> [-s <sample_fraction> | -l <fraction,fraction,...>] [-e <relative_error>] [-t <seconds>] [-p <sql|rust|columnar|bench>] [-o <parquet_dir>] [-i <parquet_dir>] [-g <data_dir> [-w]] [-f <scale_factor>] [-r <seed>] [-q <workload_file>] [-x <results.csv|results.json>] [--format <text|json|csv>] [--exact-fallback] [--cache]",
             args[0]
         );
         std::process::exit(1);
     }
 
     // Retrieve the value associated with the "-d" flag, which represents the database file.
-    let db_file = get_argument_value(&args, "-d").expect("Missing -d <database> argument");
+    let db_file = get_argument_value(&args, "-d")
+        .ok_or_else(|| AqpError::InvalidInput("missing -d <database> argument".to_string()))?;
 
     // Retrieve the value associated with the "-s" flag, which represents the sample fraction.
-    let sample_fraction = get_argument_value(&args, "-s")
-        .expect("Missing -s <sample_fraction> argument")
-        .parse::<f64>()
-        .expect("Sample fraction must be a valid floating-point number")
-        / 100.0;
-
-    // Retrieve the value associated with the "-b" flag, which represents the bootstrap size.
-    let bootstrap_size = get_argument_value(&args, "-b")
-        .expect("Missing -b <bootstrap_num> argument")
-        .parse::<usize>()
-        .expect("Bootstrap size must be a valid integer");
-
-    // argument verbal
-    
-
-    println!("sample_fraction {:?}%\nbootstrap iteration: {:?}\ndatabase: {:?}", sample_fraction*100.0, bootstrap_size, db_file.split('.').next().unwrap_or(""));
-    //Parsing the sql query and saving it in a struct
-    let query = read_query("query.txt");
-    println!("query: {:?}", query);
-
-    let (_, sql_query) = parse_sql_query(&query).unwrap();
-    let select = sql_query.get_select();
-    //println!("Tables: {:?}", select.get_table());
-
-    let where_conditions = select.get_where_clause().clone().unwrap();
-    // println!("Where Conditions: {:#?}", where_conditions);
-
-    //seperating join conditions
-    let (join_conditions, selection_conditions) = separate_conditions(where_conditions);
+    let sample_fraction = parse_argument::<f64>(&args, "-s", "a percentage")?
+        .map(|percent| percent / 100.0);
+
+    // Retrieve the value associated with the "-l" flag, which represents the sample ladder fractions.
+    let ladder_fractions = parse_list_argument::<f64>(&args, "-l", "comma-separated percentages")?
+        .map(|percents| percents.iter().map(|percent| percent / 100.0).collect::<Vec<f64>>());
+    if let Some(sample_fraction) = sample_fraction {
+        check_percentages("-s", &[sample_fraction])?;
+    }
+    if let Some(ladder_fractions) = &ladder_fractions {
+        check_percentages("-l", ladder_fractions)?;
+    }
 
-    println!("Join Condition: {:#?}", join_conditions);
-    println!("Selection Conditions: {:#?}", selection_conditions);
+    // Retrieve the value associated with the "-b" flag, which represents the bootstrap size,
+    // or a comma-separated list of bootstrap sizes to sweep with -x.
+    let bootstrap_sizes = parse_list_argument::<usize>(&args, "-b", "whole numbers")?
+        .ok_or_else(|| AqpError::InvalidInput("missing -b <bootstrap_num> argument".to_string()))?;
+
+    // Retrieve the value associated with the "-e" flag, which represents the relative error target.
+    let error_target = parse_argument::<f64>(&args, "-e", "a number")?;
+
+    // Retrieve the value associated with the "-t" flag, which represents the latency target in seconds.
+    let latency_target = parse_argument::<f64>(&args, "-t", "a number of seconds")?;
+
+    // Retrieve the value associated with the "-p" flag, which selects the predicate evaluator.
+    let evaluator = match get_argument_value(&args, "-p").map(|value| value.as_str()) {
+        None | Some("sql") => Evaluator::Sql,
+        Some("rust") => Evaluator::Rust,
+        Some("columnar") => Evaluator::Columnar,
+        Some("bench") => Evaluator::Bench,
+        Some(value) => {
+            return Err(AqpError::InvalidInput(format!(
+                "-p {:?} must be one of sql, rust, columnar or bench",
+                value
+            )))
+        }
+    };
 
-    // Connect to SQLite database (or create one if it doesn't exist)
-    let conn = db_connection(db_file).unwrap();
+    // Retrieve the value associated with the "-o" flag, a directory to persist the samples as parquet.
+    let parquet_output = get_argument_value(&args, "-o");
 
-    // Call the function to create the sample tables
-    create_sample_tables(&conn, sample_fraction).unwrap();
-    //running the query on the database to get the groundtruth
-    let database_ground_truth = groundtruth(&conn, &query).unwrap();
-    println!("Database Ground Truth: {}", database_ground_truth);
+    // Retrieve the value associated with the "-i" flag, a directory to load parquet samples from.
+    let parquet_input = get_argument_value(&args, "-i");
 
-    let query_result: Vec<i64> =
-        query_result(&conn, join_conditions, selection_conditions).unwrap();
+    // Retrieve the value associated with the "-g" flag, a directory of dbgen .tbl or csv files to ingest.
+    let ingest_dir = get_argument_value(&args, "-g");
 
-    // println!("Query result {:#?}", query_result);
+    // The "-w" flag also writes the ingested base tables to the database.
+    let keep_base_tables = has_flag(&args, "-w");
 
-    //calulating the sample ground truth
-    let sum: i64 = query_result.par_iter().sum();
-    let sample_ground_truth = sum as f64 / sample_fraction;
-    println!("Sample Ground Truth: {}", sample_ground_truth);
+    // Retrieve the value associated with the "-x" flag, a csv or json file for benchmark results.
+    // The benchmark samples are built as s*_sample_bench tables and dropped afterwards, so the
+    // samples already in the database are kept.
+    let benchmark_output = get_argument_value(&args, "-x");
 
-    //resampling the query result with replacement
-    let (bootstrap_sample, bootstrap_time_taken) =
-        bootstrap_sums(&query_result, bootstrap_size, sample_fraction);
-    // println!("Bootstrap Sample: {:#?}", bootstrap_sample);
-    println!("Bootstrap Time Taken: {:.2}s", bootstrap_time_taken);
+    // Retrieve the value associated with the "-q" flag, a workload file of named queries.
+    let workload_file = get_argument_value(&args, "-q");
 
-    let bootstrap_std_error = calculate_variance(&bootstrap_sample, bootstrap_size);
-    //println!("Mean: {}", mean);
-    println!("Standard Error: {:.2}", bootstrap_std_error);
+    // Retrieve the value associated with the "--format" flag: text, or json/csv on stdout.
+    let output_format = match get_argument_value(&args, "--format").map(|value| value.as_str()) {
+        None | Some("text") => OutputFormat::Text,
+        Some("json") => OutputFormat::Json,
+        Some("csv") => OutputFormat::Csv,
+        Some(value) => {
+            return Err(AqpError::InvalidInput(format!(
+                "--format {:?} must be one of text, json or csv",
+                value
+            )))
+        }
+    };
+    set_output_format(output_format);
 
-    // z-score for 95% confidence level
-    let z_score = 1.960;
-    let cib = z_score * bootstrap_std_error;
-    // println!("Margin of Error: {:.2}", ci);
+    // The "--exact-fallback" flag answers exactly the queries the samples cannot approximate.
+    let exact_fallback = has_flag(&args, "--exact-fallback");
 
-    let lower_bound = sample_ground_truth as f64 - cib;
-    let upper_bound = sample_ground_truth as f64 + cib;
+    // The "--cache" flag reuses the estimates of queries already answered over the same samples.
+    let cache = has_flag(&args, "--cache");
 
-    println!("CI: [{:.2}, {:.2}] (with 95% confidence level)", lower_bound, upper_bound);
-    // println!("CI: {:.2} ± {:.2} (with 95% confidence level)", sample_ground_truth, cib);
+    // Retrieve the value associated with the "-f" flag, a scale factor of TPC-H data to generate.
+    let scale_factor = parse_argument::<f64>(&args, "-f", "a number")?;
 
- 
+    // Retrieve the value associated with the "-r" flag, the seed of the data generator and of the sample.
+    let seed = parse_argument::<u64>(&args, "-r", "a whole number")?;
 
+    if benchmark_output.is_none() && bootstrap_sizes.len() > 1 {
+        return Err(AqpError::InvalidInput(
+            "several bootstrap sizes can only be swept with -x <results_file>".to_string(),
+        ));
+    }
+    let bootstrap_size = bootstrap_sizes[0];
 
-    if (database_ground_truth as f64) >= lower_bound
-        && (database_ground_truth as f64) <= upper_bound
-    {
-        println!(
-            "The database ground truth {} is within the confidence interval \u{1F44D}",
-            database_ground_truth
+    // argument verbal
+    if let Some(sample_fraction) = sample_fraction {
+        info!("sample_fraction {:?}%", sample_fraction * 100.0);
+    }
+    if let Some(ladder_fractions) = &ladder_fractions {
+        info!(
+            "sample ladder: {:?}%",
+            ladder_fractions.iter().map(|f| f * 100.0).collect::<Vec<f64>>()
         );
-    } else {
-        println!(
-            "The database ground truth {} is not within the confidence interval \u{1F44E}",
-            database_ground_truth
-        )
+    }
+    info!("bootstrap iteration: {:?}\ndatabase: {:?}", bootstrap_size, db_file.split('.').next().unwrap_or(""));
+
+    // Open the database (or create one if it doesn't exist) with the query settings
+    let mut engine = Engine::open(Path::new(db_file))?
+        .with_bootstrap_size(bootstrap_size)
+        .with_evaluator(evaluator)
+        .with_error_target(error_target)
+        .with_latency_target(latency_target)
+        .with_exact_fallback(exact_fallback)
+        .with_cache(cache);
+
+    // Fill the database with generated TPC-H data first, if asked to
+    if let Some(scale_factor) = scale_factor {
+        let seed = seed.unwrap_or(0);
+        engine.generate_tpch(scale_factor, seed)?;
+        info!("TPC-H data generated at scale factor {} with seed {}", scale_factor, seed);
     }
 
-    // End timing
-    let duration = start.elapsed().as_secs_f64();
-
-    // Print the elapsed time in seconds
-    println!("Execution time: {:.2}s", duration);
-}
+    // A workload file of named queries, or the single query in query.txt
+    let workload = match workload_file {
+        Some(path) => read_workload(Path::new(path))?,
+        None => vec![WorkloadQuery::new(
+            "query".to_string(),
+            read_query("query.txt")?.trim().to_string(),
+        )],
+    };
 
-//Selectiong sample based on join condition
-fn query_result(
-    conn: &Connection,
-    join_conditions: Vec<Where>,
-    selection_conditions: Vec<Where>,
-) -> Result<Vec<i64>, rusqlite::Error> {
-    let mut join_count = 0;
-
-    if join_conditions.iter().any(|condition| {
-        condition.get_left() == "l_orderkey"
-            && condition.get_right() == "o_orderkey"
-            && condition.get_operator() == "="
-    }) {
-        join_count += 1;
+    // Sweep the sample fractions and bootstrap sizes over the workload queries instead
+    if let Some(output) = benchmark_output {
+        let fractions = ladder_fractions
+            .or(sample_fraction.map(|fraction| vec![fraction]))
+            .ok_or_else(|| {
+                AqpError::InvalidInput(
+                    "-x <results_file> needs -s <sample_fraction> or -l <fractions>".to_string(),
+                )
+            })?;
+        let records = run_benchmark(
+            engine.get_connection(),
+            &workload,
+            &fractions,
+            &bootstrap_sizes,
+            evaluator,
+            seed,
+        )?;
+        write_benchmark(&records, Path::new(output))?;
+        info!("{} benchmark results written to {}", records.len(), output);
+        info!("Execution time: {:.2}s", start.elapsed().as_secs_f64());
+        return Ok(());
     }
 
-    if join_conditions.iter().any(|condition| {
-        condition.get_left() == "o_custkey"
-            && condition.get_right() == "c_custkey"
-            && condition.get_operator() == "="
-    }) {
-        join_count += 1;
+    // Build the sample tables, or reuse the ones already recorded in the sample catalog
+    match (sample_fraction, ladder_fractions, parquet_input, ingest_dir) {
+        (sample_fraction, ladder_fractions, _, Some(dir)) => {
+            let fractions = ladder_fractions
+                .or(sample_fraction.map(|fraction| vec![fraction]))
+                .ok_or_else(|| {
+                    AqpError::InvalidInput(
+                        "-g <data_dir> needs -s <sample_fraction> or -l <fractions>".to_string(),
+                    )
+                })?;
+            engine.ingest(Path::new(dir), &fractions, keep_base_tables, seed)?
+        }
+        (_, Some(ladder_fractions), _, None) => {
+            engine.build_sample_ladder(&ladder_fractions, seed)?
+        }
+        (Some(sample_fraction), None, _, None) => engine.build_samples(sample_fraction, seed)?,
+        (None, None, Some(dir), None) => engine.load_parquet_samples(Path::new(dir))?,
+        (None, None, None, None) => engine.load_samples()?,
+    };
+    if let Some(dir) = parquet_output {
+        engine.export_samples(Path::new(dir))?;
     }
-
-    if join_conditions.iter().any(|condition| {
-        condition.get_left() == "c_nationkey"
-            && condition.get_right() == "n_nationkey"
-            && condition.get_operator() == "="
-    }) {
-        join_count += 1;
+    if engine.get_samples().is_empty() {
+        return Err(AqpError::MissingSamples(
+            "no samples found in the sample catalog, use -s or -l to build them".to_string(),
+        ));
     }
 
-    if join_conditions.iter().any(|condition| {
-        condition.get_left() == "n_regionkey"
-            && condition.get_right() == "r_regionkey"
-            && condition.get_operator() == "="
-    }) {
-        join_count += 1;
+    // Answer every query of the workload over the same samples
+    if !engine.has_base_tables()? {
+        info!("Database Ground Truth: not available without the base tables (use -w)");
     }
+    let reports = run_workload(&engine, &workload);
+    write_reports(&reports, output_format)?;
 
-    let result = match join_count {
-        1 => {
-            let s2_sample = fetch_s2_sample(&conn)?;
-            let s2_hashmap = s2_sample_to_hashmap(&s2_sample);
-            get_query_result(&s2_hashmap, &selection_conditions)
-        }
-        2 => {
-            let s3_sample = fetch_s3_sample(&conn)?;
-            let s3_hashmap = s3_sample_to_hashmap(&s3_sample);
-            get_query_result(&s3_hashmap, &selection_conditions)
-        }
-        3 => {
-            let s4_sample = fetch_s4_sample(&conn)?;
-            let s4_hashmap = s4_sample_to_hashmap(&s4_sample);
-            get_query_result(&s4_hashmap, &selection_conditions)
-        }
-        4 => {
-            let s5_sample = fetch_s5_sample(&conn)?;
-            let s5_hashmap = s5_sample_to_hashmap(&s5_sample);
-            get_query_result(&s5_hashmap, &selection_conditions)
-        }
-        _ => {
-            println!("No matching join conditions found.");
-            Vec::new()
-        }
-    };
+    // End timing
+    let duration = start.elapsed().as_secs_f64();
 
-    Ok(result)
+    // Print the elapsed time in seconds
+    info!("Execution time: {:.2}s", duration);
+    Ok(())
 }
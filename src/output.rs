@@ -0,0 +1,618 @@
+//how the results are printed: free text by default, or a single json or csv document on
+//stdout for pipelines, in which case the progress messages are moved to stderr
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use crate::engine::{Engine, Estimate, MIN_MATCHED_ROWS};
+use crate::error::AqpError;
+use crate::workload::WorkloadQuery;
+
+//what one query of a workload produced
+pub struct QueryReport {
+    name: String,
+    query: String,
+    estimate: Estimate,
+    ground_truth: Option<f64>,
+    ground_truth_time: Option<f64>,
+    time: f64,
+}
+
+impl QueryReport {
+    pub fn new(
+        name: String,
+        query: String,
+        estimate: Estimate,
+        ground_truth: Option<f64>,
+        ground_truth_time: Option<f64>,
+        time: f64,
+    ) -> Self {
+        QueryReport {
+            name,
+            query,
+            estimate,
+            ground_truth,
+            ground_truth_time,
+            time,
+        }
+    }
+
+    //getter methods for the QueryReport struct
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn get_estimate(&self) -> &Estimate {
+        &self.estimate
+    }
+
+    pub fn get_ground_truth(&self) -> Option<f64> {
+        self.ground_truth
+    }
+
+    pub fn get_time(&self) -> f64 {
+        self.time
+    }
+
+    //error of the estimate relative to the ground truth
+    pub fn relative_error(&self) -> Option<f64> {
+        self.ground_truth.map(|ground_truth| {
+            (self.estimate.get_estimate() - ground_truth).abs() / ground_truth.abs()
+        })
+    }
+
+    //whether the confidence interval holds the ground truth
+    pub fn covered(&self) -> Option<bool> {
+        self.ground_truth
+            .map(|ground_truth| self.estimate.contains(ground_truth))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+static MACHINE_READABLE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_output_format(format: OutputFormat) {
+    MACHINE_READABLE.store(format != OutputFormat::Text, Ordering::Relaxed);
+}
+
+pub fn is_machine_readable() -> bool {
+    MACHINE_READABLE.load(Ordering::Relaxed)
+}
+
+//println! for progress and text results, sent to stderr when stdout carries json or csv
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::output::is_machine_readable() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+//json string literal with quotes, backslashes and control characters escaped
+pub fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+//json number, with null for a missing value or one json cannot represent (inf, NaN)
+pub fn json_number(value: Option<f64>) -> String {
+    match value {
+        Some(value) if value.is_finite() => value.to_string(),
+        _ => "null".to_string(),
+    }
+}
+
+//a json object with the fields in the given order
+pub fn json_object(fields: &[&str], values: Vec<String>) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .zip(values)
+        .map(|(name, value)| format!("{}: {}", json_string(name), value))
+        .collect();
+    format!("{{{}}}", fields.join(", "))
+}
+
+//one value of a report, written as json or as a csv cell (empty when missing)
+enum Field {
+    Text(String),
+    Label(Option<String>),
+    Number(Option<f64>),
+    Integer(usize),
+    Flag(Option<bool>),
+}
+
+impl Field {
+    fn to_json(&self) -> String {
+        match self {
+            Field::Text(text) => json_string(text),
+            Field::Label(label) => label.as_deref().map_or("null".to_string(), json_string),
+            Field::Number(number) => json_number(*number),
+            Field::Integer(integer) => integer.to_string(),
+            Field::Flag(flag) => flag.map_or("null".to_string(), |flag| flag.to_string()),
+        }
+    }
+
+    fn to_csv(&self) -> String {
+        match self {
+            Field::Text(text) => text.clone(),
+            Field::Label(label) => label.clone().unwrap_or_default(),
+            Field::Number(number) => number.map_or(String::new(), |number| number.to_string()),
+            Field::Integer(integer) => integer.to_string(),
+            Field::Flag(flag) => flag.map_or(String::new(), |flag| flag.to_string()),
+        }
+    }
+}
+
+const REPORT_FIELDS: [&str; 25] = [
+    "name",
+    "query",
+    "aggregate",
+    "group",
+    "estimate",
+    "std_error",
+    "lower_bound",
+    "upper_bound",
+    "confidence_level",
+    "membership_probability",
+    "exact_reason",
+    "cached",
+    "sample_table",
+    "sample_fraction",
+    "sample_rows",
+    "matched_rows",
+    "low_selectivity",
+    "bootstrap_size",
+    "evaluation_time",
+    "bootstrap_time",
+    "query_time",
+    "ground_truth",
+    "ground_truth_time",
+    "relative_error",
+    "covered",
+];
+
+fn report_values(report: &QueryReport) -> Vec<Field> {
+    let estimate = &report.estimate;
+    vec![
+        Field::Text(report.name.clone()),
+        Field::Text(report.query.clone()),
+        Field::Text(estimate.get_aggregate().to_string()),
+        Field::Label(estimate.get_group().map(str::to_string)),
+        Field::Number(Some(estimate.get_estimate())),
+        Field::Number(estimate.get_std_error()),
+        Field::Number(Some(estimate.get_lower_bound())),
+        Field::Number(Some(estimate.get_upper_bound())),
+        Field::Number(estimate.get_confidence_level()),
+        Field::Number(estimate.get_membership_probability()),
+        Field::Label(estimate.get_exact_reason().map(str::to_string)),
+        Field::Flag(Some(estimate.is_cached())),
+        Field::Text(estimate.get_sample_table().to_string()),
+        Field::Number(Some(estimate.get_sample_fraction())),
+        Field::Integer(estimate.get_sample_rows()),
+        Field::Integer(estimate.get_matched_rows()),
+        Field::Flag(Some(estimate.is_low_selectivity())),
+        Field::Integer(estimate.get_bootstrap_size()),
+        Field::Number(Some(estimate.get_evaluation_time())),
+        Field::Number(Some(estimate.get_bootstrap_time())),
+        Field::Number(Some(report.time)),
+        Field::Number(report.ground_truth),
+        Field::Number(report.ground_truth_time),
+        Field::Number(report.relative_error()),
+        Field::Flag(report.covered()),
+    ]
+}
+
+fn write_json(reports: &[QueryReport]) {
+    let objects: Vec<String> = reports
+        .iter()
+        .map(|report| {
+            let values = report_values(report).iter().map(Field::to_json).collect();
+            format!("  {}", json_object(&REPORT_FIELDS, values))
+        })
+        .collect();
+    println!("[\n{}\n]", objects.join(",\n"));
+}
+
+fn write_csv(reports: &[QueryReport]) -> Result<(), AqpError> {
+    let mut writer = csv::Writer::from_writer(std::io::stdout());
+    writer.write_record(REPORT_FIELDS)?;
+    for report in reports {
+        writer.write_record(report_values(report).iter().map(Field::to_csv))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+//print the reports of the workload on stdout: a summary table in text when there is more than
+//one, or every report in the chosen machine readable format
+pub fn write_reports(reports: &[QueryReport], format: OutputFormat) -> Result<(), AqpError> {
+    match format {
+        OutputFormat::Json => write_json(reports),
+        OutputFormat::Csv => write_csv(reports)?,
+        OutputFormat::Text if reports.len() > 1 => write_table(reports),
+        OutputFormat::Text => {}
+    }
+    Ok(())
+}
+
+//answer one query of the workload, one report per aggregate (and group) with its ground truth
+//when the base tables are there and the answer is not exact already
+pub fn query_reports(
+    engine: &Engine,
+    workload_query: &WorkloadQuery,
+) -> Result<Vec<QueryReport>, AqpError> {
+    let start = Instant::now();
+    let query = workload_query.get_sql();
+    let estimates = engine.estimate(query)?;
+    let time = start.elapsed().as_secs_f64();
+
+    // with --exact-fallback, a query the samples cannot approximate is answered exactly
+    let is_exact = estimates
+        .first()
+        .is_some_and(|estimate| estimate.get_exact_reason().is_some());
+    let ground_truth_start = Instant::now();
+    let ground_truths = match is_exact {
+        true => None,
+        false => engine.ground_truth(query)?,
+    };
+    let ground_truth_time = ground_truths
+        .as_ref()
+        .map(|_| ground_truth_start.elapsed().as_secs_f64());
+
+    Ok(estimates
+        .into_iter()
+        .map(|estimate| {
+            let ground_truth = ground_truths
+                .as_ref()
+                .and_then(|truths| truths.value_of(&estimate));
+            QueryReport::new(
+                workload_query.get_name().to_string(),
+                query.to_string(),
+                estimate,
+                ground_truth,
+                ground_truth_time,
+                time,
+            )
+        })
+        .collect())
+}
+
+//answer every query of the workload over the same samples, printing the reports of each as it
+//is answered; a query that fails is reported on stderr and skipped
+pub fn run_workload(engine: &Engine, workload: &[WorkloadQuery]) -> Vec<QueryReport> {
+    let mut reports = Vec::with_capacity(workload.len());
+    for workload_query in workload {
+        info!("\n== {} ==", workload_query.get_name());
+        info!("query: {:?}", workload_query.get_sql());
+        match query_reports(engine, workload_query) {
+            Ok(query_reports) => {
+                print_query_reports(&query_reports);
+                reports.extend(query_reports);
+            }
+            Err(error) => eprintln!("{}: {}", workload_query.get_name(), error),
+        }
+    }
+    reports
+}
+
+//print the estimate of every aggregate of one query next to its ground truth
+fn print_query_reports(reports: &[QueryReport]) {
+    let Some(first) = reports.first().map(QueryReport::get_estimate) else {
+        info!("The query returns no row on the sample");
+        return;
+    };
+    if first.is_cached() {
+        info!(
+            "Answered from the cache in {:.2}s (first computed in {:.2}s)",
+            reports[0].get_time(),
+            first.get_evaluation_time() + first.get_bootstrap_time()
+        );
+    }
+    let exact_reason = first.get_exact_reason();
+    match exact_reason {
+        Some(reason) => info!(
+            "Not approximated: {}\nAnswered exactly over {} in {:.2}s",
+            reason,
+            first.get_sample_table(),
+            first.get_evaluation_time()
+        ),
+        None => info!(
+            "Sample used: {} ({}% of lineitem)",
+            first.get_sample_table(),
+            first.get_sample_fraction() * 100.0
+        ),
+    }
+
+    let several = reports
+        .iter()
+        .filter(|report| report.get_estimate().get_group() == first.get_group())
+        .count()
+        > 1;
+    // the group and its probability to be in the result are shared by its aggregates
+    let mut previous_group = None;
+    for report in reports {
+        let estimate = report.get_estimate();
+        let ground_truth = report.get_ground_truth();
+        let group = estimate.get_group();
+        if previous_group != Some(group) {
+            match group {
+                Some(group) if exact_reason.is_some() => info!("Group: {}", group),
+                Some(group) => info!(
+                    "Group: {} ({} sample rows)",
+                    group,
+                    estimate.get_matched_rows()
+                ),
+                None if exact_reason.is_some() => {}
+                None => info!("Matching sample rows: {}", estimate.get_matched_rows()),
+            }
+            if estimate.is_low_selectivity() {
+                info!(
+                    "Warning: fewer than {} sample rows match, the intervals may be too narrow (count uses a Wilson score interval); a larger sample (-s, or -l with -e) helps",
+                    MIN_MATCHED_ROWS
+                );
+            }
+            if let Some(probability) = estimate.get_membership_probability() {
+                info!(
+                    "Probability in the result: {:.2} (share of the bootstrap resamples in which it passes having and ranks within the limit)",
+                    probability
+                );
+            }
+            previous_group = Some(group);
+        }
+        if several {
+            info!("-- {} --", estimate.get_aggregate());
+        }
+        if let Some(ground_truth) = ground_truth {
+            info!("Database Ground Truth: {}", ground_truth);
+        }
+
+        if exact_reason.is_some() {
+            info!("Exact Answer: {}", estimate.get_estimate());
+            continue;
+        }
+
+        info!("Sample Ground Truth: {}", estimate.get_estimate());
+        match (estimate.get_std_error(), estimate.get_confidence_level()) {
+            (Some(std_error), Some(confidence_level)) => {
+                info!("Bootstrap Time Taken: {:.2}s", estimate.get_bootstrap_time());
+                info!("Standard Error: {:.2}", std_error);
+                info!(
+                    "CI: [{:.2}, {:.2}] (with {}% confidence level)",
+                    estimate.get_lower_bound(),
+                    estimate.get_upper_bound(),
+                    confidence_level * 100.0
+                );
+            }
+            // distinct counts get the GEE bounds, the bootstrap is biased low for them
+            _ => info!(
+                "Bounds: [{:.2}, {:.2}] (GEE: within a factor of sqrt(1/q) of the estimate, not a confidence interval)",
+                estimate.get_lower_bound(),
+                estimate.get_upper_bound()
+            ),
+        }
+
+        match (ground_truth, report.covered()) {
+            (Some(ground_truth), Some(true)) => info!(
+                "The database ground truth {} is within the interval \u{1F44D}",
+                ground_truth
+            ),
+            (Some(ground_truth), _) => info!(
+                "The database ground truth {} is not within the interval \u{1F44E}",
+                ground_truth
+            ),
+            _ => {}
+        }
+    }
+}
+
+//label of a report in the summary table: the query name, its group and, for queries with
+//several aggregates, the aggregate
+fn table_label(reports: &[QueryReport], report: &QueryReport) -> String {
+    let estimate = report.get_estimate();
+    let aggregates = reports
+        .iter()
+        .filter(|other| {
+            other.get_name() == report.get_name()
+                && other.get_estimate().get_group() == estimate.get_group()
+        })
+        .count();
+    let mut label = report.get_name().to_string();
+    if let Some(group) = estimate.get_group() {
+        label += &format!(" [{}]", group);
+    }
+    if aggregates > 1 {
+        label += &format!(" {}", estimate.get_aggregate());
+    }
+    label
+}
+
+//one line per workload query, group and aggregate: estimate, interval, ground truth, error
+//and time
+fn write_table(reports: &[QueryReport]) {
+    let labels: Vec<String> = reports
+        .iter()
+        .map(|report| table_label(reports, report))
+        .collect();
+    let name_width = labels
+        .iter()
+        .map(String::len)
+        .max()
+        .unwrap_or(0)
+        .max("query".len());
+    println!(
+        "\n{:<name_width$} {:>8} {:>16} {:>34} {:>16} {:>10} {:>8} {:>9}",
+        "query", "sample", "estimate", "interval", "ground truth", "rel error", "covered", "time"
+    );
+    for (report, label) in reports.iter().zip(&labels) {
+        let estimate = report.get_estimate();
+        println!(
+            "{:<name_width$} {:>8} {:>16.2} {:>34} {:>16} {:>10} {:>8} {:>8.2}s",
+            label,
+            match estimate.get_exact_reason() {
+                Some(_) => "exact".to_string(),
+                None => format!("{}%", estimate.get_sample_fraction() * 100.0),
+            },
+            estimate.get_estimate(),
+            format!(
+                "[{:.2}, {:.2}]",
+                estimate.get_lower_bound(),
+                estimate.get_upper_bound()
+            ),
+            report
+                .get_ground_truth()
+                .map_or("-".to_string(), |truth| format!("{:.2}", truth)),
+            report
+                .relative_error()
+                .map_or("-".to_string(), |error| format!("{:.4}", error)),
+            report
+                .covered()
+                .map_or("-", |covered| if covered { "yes" } else { "no" }),
+            report.get_time()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn engine() -> Engine {
+        test_support::engine(0.1, 3)
+    }
+
+    fn workload_query(name: &str, sql: &str) -> WorkloadQuery {
+        WorkloadQuery::new(name.to_string(), sql.to_string())
+    }
+
+    #[test]
+    fn json_strings_escape_quotes_backslashes_and_control_characters() {
+        assert_eq!(
+            json_string("l_comment = 'a \"b\"'\\\n\t\u{1}é"),
+            "\"l_comment = 'a \\\"b\\\"'\\\\\\n\\t\\u0001é\""
+        );
+        assert_eq!(json_number(Some(0.25)), "0.25");
+        for value in [None, Some(f64::NAN), Some(f64::INFINITY)] {
+            assert_eq!(json_number(value), "null");
+        }
+        assert_eq!(
+            json_object(&["b", "a"], vec!["1".to_string(), json_string("x")]),
+            "{\"b\": 1, \"a\": \"x\"}"
+        );
+    }
+
+    #[test]
+    fn missing_values_are_null_in_json_and_empty_in_csv() {
+        let engine = engine().with_exact_fallback(true);
+        let query = workload_query("orders", "select count(*) from orders");
+        let report = &query_reports(&engine, &query).unwrap()[0];
+        let field = |name: &str| {
+            REPORT_FIELDS
+                .iter()
+                .position(|field| *field == name)
+                .unwrap()
+        };
+        let values = report_values(report);
+        assert_eq!(values.len(), REPORT_FIELDS.len());
+
+        let json: Vec<String> = values.iter().map(Field::to_json).collect();
+        let csv: Vec<String> = values.iter().map(Field::to_csv).collect();
+        for name in [
+            "group",
+            "std_error",
+            "ground_truth",
+            "relative_error",
+            "covered",
+        ] {
+            assert_eq!(json[field(name)], "null");
+            assert_eq!(csv[field(name)], "");
+        }
+        assert_eq!(json[field("name")], "\"orders\"");
+        assert_eq!(csv[field("estimate")], "1500");
+        assert_eq!(json[field("cached")], "false");
+        assert!(json[field("exact_reason")].starts_with('"'));
+    }
+
+    #[test]
+    fn every_aggregate_gets_a_report_with_its_ground_truth() {
+        let engine = engine();
+        let query = workload_query(
+            "revenue",
+            "select count(*), sum(l_extendedprice) from lineitem, orders where l_orderkey = o_orderkey and o_totalprice > 100000",
+        );
+        let reports = query_reports(&engine, &query).unwrap();
+        let aggregates: Vec<&str> = reports
+            .iter()
+            .map(|report| report.get_estimate().get_aggregate())
+            .collect();
+        assert_eq!(aggregates, vec!["count(*)", "sum(l_extendedprice)"]);
+
+        let exact: f64 = engine
+            .get_connection()
+            .query_row(
+                "SELECT COUNT(*) FROM lineitem, orders WHERE l_orderkey = o_orderkey AND o_totalprice > 100000",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(reports[0].get_name(), "revenue");
+        assert_eq!(reports[0].get_ground_truth(), Some(exact));
+        assert!(reports[0].relative_error().unwrap() < 0.2);
+        assert!(reports.iter().all(|report| report.covered().is_some()));
+    }
+
+    #[test]
+    fn exact_answers_have_no_separate_ground_truth() {
+        let engine = engine().with_exact_fallback(true);
+        let query = workload_query("orders", "select count(*) from orders");
+        let reports = query_reports(&engine, &query).unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].get_estimate().get_estimate(), 1500.0);
+        assert_eq!(reports[0].get_ground_truth(), None);
+        assert_eq!(reports[0].covered(), None);
+    }
+
+    #[test]
+    fn failing_queries_are_skipped_by_the_workload() {
+        let engine = engine();
+        let workload = [
+            workload_query("typo", "select count(*) from lineitem where l_quantit > 1"),
+            workload_query("flags", "select l_returnflag, count(*), avg(l_quantity) from lineitem group by l_returnflag"),
+        ];
+        let reports = run_workload(&engine, &workload);
+        assert_eq!(reports.len(), 6);
+        assert!(reports.iter().all(|report| report.get_name() == "flags"));
+
+        let labels: Vec<String> = reports
+            .iter()
+            .map(|report| table_label(&reports, report))
+            .collect();
+        assert_eq!(labels[0], "flags [A] count(*)");
+        assert_eq!(labels[1], "flags [A] avg(l_quantity)");
+    }
+}
@@ -0,0 +1,472 @@
+use arrow::array::{
+    Array, ArrayRef, Float64Array, Float64Builder, Int64Array, Int64Builder, StringArray,
+    StringBuilder,
+};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::{ArrowWriter, ProjectionMask};
+use parquet::file::properties::WriterProperties;
+use parquet::format::KeyValue;
+use rusqlite::{params, types::ValueRef, Connection};
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::columnar::{referenced_columns, ColumnarSample};
+use crate::error::AqpError;
+use crate::expression::{Expr, Value};
+use crate::parser::Where;
+use crate::sampling::{is_sample_fraction, SampleSet};
+
+const BATCH_SIZE: usize = 65536;
+
+//catalog metadata stored in the key-value metadata of every parquet sample file
+const SUFFIX_KEY: &str = "aqprius.suffix";
+const FRACTION_KEY: &str = "aqprius.fraction";
+const ROWS_KEY: &str = "aqprius.rows";
+
+//path of the parquet file holding the s*level sample of a sample set
+pub fn sample_file(dir: &Path, set: &SampleSet, level: usize) -> PathBuf {
+    dir.join(format!("{}.parquet", set.table(level)))
+}
+
+//arrow type for a sqlite declared type, following sqlite's affinity rules
+fn arrow_type(column_type: &str) -> DataType {
+    let column_type = column_type.to_uppercase();
+    if column_type.contains("INT") {
+        DataType::Int64
+    } else if column_type.contains("REAL")
+        || column_type.contains("FLOA")
+        || column_type.contains("DOUB")
+        || column_type.contains("NUM")
+        || column_type.contains("DEC")
+    {
+        DataType::Float64
+    } else {
+        DataType::Utf8
+    }
+}
+
+//sqlite declared type of an arrow type, the inverse of arrow_type
+fn declared_type(data_type: &DataType) -> &'static str {
+    match data_type {
+        DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::Int64 => "INTEGER",
+        DataType::Float32 | DataType::Float64 => "REAL",
+        _ => "TEXT",
+    }
+}
+
+//column name and declared type of every column in a parquet sample file, like
+//table_columns for a sqlite sample table
+pub fn parquet_columns(path: &Path) -> Result<HashMap<String, String>, AqpError> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+    Ok(builder
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| {
+            (
+                field.name().to_lowercase(),
+                declared_type(field.data_type()).to_string(),
+            )
+        })
+        .collect())
+}
+
+//column builders for one record batch
+enum ColumnBuilder {
+    Int(Int64Builder),
+    Float(Float64Builder),
+    Text(StringBuilder),
+}
+
+impl ColumnBuilder {
+    fn new(data_type: &DataType) -> Self {
+        match data_type {
+            DataType::Int64 => ColumnBuilder::Int(Int64Builder::with_capacity(BATCH_SIZE)),
+            DataType::Float64 => ColumnBuilder::Float(Float64Builder::with_capacity(BATCH_SIZE)),
+            _ => ColumnBuilder::Text(StringBuilder::new()),
+        }
+    }
+
+    fn append(&mut self, value: ValueRef) {
+        match (self, value) {
+            (ColumnBuilder::Int(b), ValueRef::Integer(i)) => b.append_value(i),
+            (ColumnBuilder::Int(b), ValueRef::Real(f)) => b.append_value(f as i64),
+            (ColumnBuilder::Float(b), ValueRef::Integer(i)) => b.append_value(i as f64),
+            (ColumnBuilder::Float(b), ValueRef::Real(f)) => b.append_value(f),
+            (ColumnBuilder::Text(b), ValueRef::Integer(i)) => b.append_value(i.to_string()),
+            (ColumnBuilder::Text(b), ValueRef::Real(f)) => b.append_value(f.to_string()),
+            (ColumnBuilder::Text(b), ValueRef::Text(t)) => {
+                b.append_value(String::from_utf8_lossy(t))
+            }
+            (ColumnBuilder::Int(b), ValueRef::Text(t)) => {
+                b.append_option(std::str::from_utf8(t).ok().and_then(|s| s.parse().ok()))
+            }
+            (ColumnBuilder::Float(b), ValueRef::Text(t)) => {
+                b.append_option(std::str::from_utf8(t).ok().and_then(|s| s.parse().ok()))
+            }
+            (builder, ValueRef::Null) | (builder, ValueRef::Blob(_)) => builder.append_null(),
+        }
+    }
+
+    fn append_null(&mut self) {
+        match self {
+            ColumnBuilder::Int(b) => b.append_null(),
+            ColumnBuilder::Float(b) => b.append_null(),
+            ColumnBuilder::Text(b) => b.append_null(),
+        }
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            ColumnBuilder::Int(b) => Arc::new(b.finish()),
+            ColumnBuilder::Float(b) => Arc::new(b.finish()),
+            ColumnBuilder::Text(b) => Arc::new(b.finish()),
+        }
+    }
+}
+
+//arrow schema of a sqlite sample table, in column order
+fn table_schema(conn: &Connection, table: &str) -> Result<Schema, rusqlite::Error> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let fields = stmt
+        .query_map(params![], |row| {
+            let name: String = row.get(1)?;
+            let column_type: String = row.get(2)?;
+            Ok(Field::new(
+                name.to_lowercase(),
+                arrow_type(&column_type),
+                true,
+            ))
+        })?
+        .collect::<Result<Vec<Field>, _>>()?;
+
+    Ok(Schema::new(fields))
+}
+
+//write one sqlite sample table to a parquet file, batch by batch
+fn export_table(
+    conn: &Connection,
+    table: &str,
+    set: &SampleSet,
+    path: &Path,
+) -> Result<(), AqpError> {
+    let schema = Arc::new(table_schema(conn, table)?);
+
+    let metadata = vec![
+        KeyValue::new(SUFFIX_KEY.to_string(), set.get_suffix().to_string()),
+        KeyValue::new(FRACTION_KEY.to_string(), set.get_fraction().to_string()),
+        KeyValue::new(ROWS_KEY.to_string(), set.get_rows().to_string()),
+    ];
+    let properties = WriterProperties::builder()
+        .set_key_value_metadata(Some(metadata))
+        .build();
+    let mut writer = ArrowWriter::try_new(File::create(path)?, schema.clone(), Some(properties))?;
+
+    let mut stmt = conn.prepare(&format!("SELECT * FROM {}", table))?;
+    let mut rows = stmt.query(params![])?;
+
+    let new_builders = || -> Vec<ColumnBuilder> {
+        schema
+            .fields()
+            .iter()
+            .map(|field| ColumnBuilder::new(field.data_type()))
+            .collect()
+    };
+    let mut builders = new_builders();
+    let mut batch_rows = 0;
+
+    loop {
+        let row = rows.next()?;
+        if let Some(row) = row {
+            for (i, builder) in builders.iter_mut().enumerate() {
+                builder.append(row.get_ref(i)?);
+            }
+            batch_rows += 1;
+        }
+
+        if batch_rows == BATCH_SIZE || (row.is_none() && batch_rows > 0) {
+            let columns = builders
+                .iter_mut()
+                .map(|builder| builder.finish())
+                .collect();
+            writer.write(&RecordBatch::try_new(schema.clone(), columns)?)?;
+            builders = new_builders();
+            batch_rows = 0;
+        }
+
+        if row.is_none() {
+            break;
+        }
+    }
+
+    writer.close()?;
+    Ok(())
+}
+
+//persist the s1..s5 tables of every sample set as parquet files in a directory
+pub fn export_samples(
+    conn: &Connection,
+    sets: &[SampleSet],
+    dir: &Path,
+) -> Result<(), AqpError> {
+    std::fs::create_dir_all(dir)?;
+
+    for set in sets {
+        for level in 1..=5 {
+            let path = sample_file(dir, set, level);
+            export_table(conn, &set.table(level), set, &path)?;
+            info!("{} written to {}", set.table(level), path.display());
+        }
+    }
+
+    Ok(())
+}
+
+fn metadata_value<'a>(metadata: &'a [KeyValue], key: &str) -> Option<&'a str> {
+    metadata
+        .iter()
+        .find(|kv| kv.key == key)
+        .and_then(|kv| kv.value.as_deref())
+}
+
+//read the catalog metadata of every sample set stored in a directory,
+//ordered from the smallest to the largest sample
+pub fn load_parquet_catalog(dir: &Path) -> Result<Vec<SampleSet>, AqpError> {
+    let mut sets = Vec::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_s1_file = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("s1_sample") && name.ends_with(".parquet"));
+        if !is_s1_file {
+            continue;
+        }
+
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(&path)?)?;
+        let metadata = builder
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()
+            .cloned()
+            .unwrap_or_default();
+
+        let invalid = |what: &str| {
+            AqpError::InvalidInput(format!("{} has no valid {}", path.display(), what))
+        };
+        let suffix = metadata_value(&metadata, SUFFIX_KEY)
+            .ok_or_else(|| invalid("sample catalog metadata"))?;
+        let fraction = metadata_value(&metadata, FRACTION_KEY)
+            .and_then(|value| value.parse::<f64>().ok())
+            .filter(|&fraction| is_sample_fraction(fraction))
+            .ok_or_else(|| invalid("sample fraction"))?;
+        let rows = metadata_value(&metadata, ROWS_KEY)
+            .and_then(|value| value.parse::<i64>().ok())
+            .ok_or_else(|| invalid("sample size"))?;
+
+        sets.push(SampleSet::new(suffix.to_string(), fraction, rows).with_parquet_dir(dir));
+    }
+
+    sets.sort_by(|a, b| a.get_fraction().total_cmp(&b.get_fraction()));
+    Ok(sets)
+}
+
+//same conversion as the hashmap path: numbers as they are, text parsed as f64 or 0.0
+fn array_to_f64(array: &dyn Array, values: &mut Vec<f64>) {
+    if let Some(array) = array.as_any().downcast_ref::<Int64Array>() {
+        values.extend((0..array.len()).map(|i| {
+            if array.is_null(i) {
+                0.0
+            } else {
+                array.value(i) as f64
+            }
+        }));
+    } else if let Some(array) = array.as_any().downcast_ref::<Float64Array>() {
+        values.extend((0..array.len()).map(|i| {
+            if array.is_null(i) {
+                0.0
+            } else {
+                array.value(i)
+            }
+        }));
+    } else if let Some(array) = array.as_any().downcast_ref::<StringArray>() {
+        values.extend((0..array.len()).map(|i| {
+            if array.is_null(i) {
+                0.0
+            } else {
+                array.value(i).parse::<f64>().unwrap_or(0.0)
+            }
+        }));
+    } else {
+        values.extend(std::iter::repeat_n(0.0, array.len()));
+    }
+}
+
+//text parsed as a number or date when it is one, like the values of the sqlite sample rows
+fn array_to_values(array: &StringArray, values: &mut Vec<Value>) {
+    values.extend((0..array.len()).map(|i| {
+        if array.is_null(i) {
+            Value::Null
+        } else {
+            Value::parse(array.value(i))
+        }
+    }));
+}
+
+//load the columns used by the selection conditions and the aggregates from a parquet sample
+//as arrow record batches, text columns keeping their values
+pub fn fetch_parquet_sample(
+    path: &Path,
+    conditions: &[Where],
+    arguments: &[Option<&Expr>],
+) -> Result<ColumnarSample, AqpError> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+
+    let names: Vec<String> = referenced_columns(conditions, arguments)
+        .into_iter()
+        .filter(|name| builder.schema().index_of(name).is_ok())
+        .collect();
+
+    let indices: Vec<usize> = names
+        .iter()
+        .map(|name| builder.schema().index_of(name))
+        .collect::<Result<_, _>>()?;
+    let schema = builder.schema().clone();
+    let mask = ProjectionMask::roots(builder.parquet_schema(), indices);
+    let rows = builder.metadata().file_metadata().num_rows() as usize;
+    let reader = builder
+        .with_projection(mask)
+        .with_batch_size(BATCH_SIZE)
+        .build()?;
+
+    let (text_names, names): (Vec<String>, Vec<String>) = names.into_iter().partition(|name| {
+        schema
+            .field_with_name(name)
+            .is_ok_and(|field| field.data_type() == &DataType::Utf8)
+    });
+    let mut columns: HashMap<String, Vec<f64>> = names
+        .iter()
+        .map(|name| (name.clone(), Vec::with_capacity(rows)))
+        .collect();
+    let mut text_columns: HashMap<String, Vec<Value>> = text_names
+        .iter()
+        .map(|name| (name.clone(), Vec::with_capacity(rows)))
+        .collect();
+    for batch in reader {
+        let batch = batch?;
+        for (name, values) in columns.iter_mut() {
+            if let Some(array) = batch.column_by_name(name) {
+                array_to_f64(array.as_ref(), values);
+            }
+        }
+        for (name, values) in text_columns.iter_mut() {
+            let array = batch.column_by_name(name);
+            if let Some(array) =
+                array.and_then(|array| array.as_any().downcast_ref::<StringArray>())
+            {
+                array_to_values(array, values);
+            }
+        }
+    }
+
+    Ok(ColumnarSample::new(rows, columns).with_text_columns(text_columns))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::test_support;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("aqprius_{}_{}", name, std::process::id()))
+    }
+
+    //an engine over a small generated database with a seeded ladder of two samples
+    fn engine() -> Engine {
+        let mut engine = test_support::engine(0.1, 3);
+        engine.build_sample_ladder(&[0.2, 0.1], Some(3)).unwrap();
+        engine
+    }
+
+    //estimate, matched rows and group of every answer
+    fn answers(engine: &Engine, query: &str) -> Vec<(f64, usize, Option<String>)> {
+        engine
+            .estimate(query)
+            .unwrap()
+            .iter()
+            .map(|estimate| {
+                (
+                    estimate.get_estimate(),
+                    estimate.get_matched_rows(),
+                    estimate.get_group().map(str::to_string),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn exported_catalog_is_read_back_in_fraction_order() {
+        let engine = engine();
+        let dir = temp_dir("catalog");
+        engine.export_samples(&dir).unwrap();
+
+        let sets = load_parquet_catalog(&dir).unwrap();
+        let fractions: Vec<f64> = sets.iter().map(SampleSet::get_fraction).collect();
+        assert_eq!(fractions, vec![0.1, 0.2]);
+        for (set, exported) in sets.iter().zip(engine.get_samples()) {
+            assert_eq!(set.get_suffix(), exported.get_suffix());
+            assert_eq!(set.get_rows(), exported.get_rows());
+            assert_eq!(set.get_parquet_dir(), Some(dir.as_path()));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn text_and_date_columns_give_the_same_answers_as_sqlite() {
+        let mut engine = engine();
+        let dir = temp_dir("text");
+        engine.export_samples(&dir).unwrap();
+        let queries = [
+            "select count(*), avg(l_quantity) from lineitem, orders where l_orderkey = o_orderkey and o_orderdate >= date '1995-01-01' and l_shipmode = 'AIR'",
+            "select l_returnflag, count(*) from lineitem, orders where l_orderkey = o_orderkey group by l_returnflag",
+            "select count(distinct o_orderpriority) from lineitem, orders where l_orderkey = o_orderkey and l_shipdate < o_orderdate + interval '30' day",
+        ];
+        let from_sqlite: Vec<_> = queries
+            .iter()
+            .map(|query| answers(&engine, query))
+            .collect();
+
+        engine.load_parquet_samples(&dir).unwrap();
+        let from_parquet: Vec<_> = queries
+            .iter()
+            .map(|query| answers(&engine, query))
+            .collect();
+        assert_eq!(from_parquet, from_sqlite);
+        assert_eq!(from_parquet[1].len(), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_fraction_metadata_is_rejected() {
+        let engine = engine();
+        let dir = temp_dir("metadata");
+        std::fs::create_dir_all(&dir).unwrap();
+        let set = &engine.get_samples()[0];
+        for fraction in [f64::NAN, 0.0, 2.0] {
+            let invalid = SampleSet::new(set.get_suffix().to_string(), fraction, set.get_rows());
+            let path = sample_file(&dir, &invalid, 1);
+            export_table(engine.get_connection(), &set.table(1), &invalid, &path).unwrap();
+            assert!(matches!(
+                load_parquet_catalog(&dir),
+                Err(AqpError::InvalidInput(message)) if message.contains("sample fraction")
+            ));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
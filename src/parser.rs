@@ -1,16 +1,45 @@
+//sql front-end: aggregates over a list of tables, possibly by group, written as
+//
+//  select <item> [[as] <name>] {, <item> [[as] <name>]} from <table> [[as] <alias>]
+//      {, <table> [[as] <alias>] | [inner] join <table> [[as] <alias>] on <condition> {and <condition>}}
+//      [where <condition> {and <condition>}] [group by <expr> {, <expr>}]
+//      [having <aggregate> <op> <aggregate> {and ...}] [order by <item> [asc|desc] {, ...}]
+//      [limit <n>] [;]
+//
+//where an item is an aggregate or, in a grouped query, one of the grouped expressions. The
+//aggregate is count(*), count(distinct <expr>), sum(<expr>), avg(<expr>),
+//percentile(<expr>, <fraction>), median(<expr>), min(<expr>), max(<expr>), or + - * / and
+//parentheses over those and numbers, e.g. sum(a) / sum(b); a condition compares two
+//expressions with = <> != < <= > >=, is `<expr> [not] in (<expr> {, <expr>})`,
+//`<expr> [not] in (select ...)` with an uncorrelated subquery of this grammar selecting one
+//item, answered exactly by sqlite, or
+//`[not] exists (select ... from lineitem [[as] <alias>] where l_orderkey = <expr> {and
+//<condition>})`; an expression is built from columns, numbers, 'text', date 'yyyy-mm-dd',
+//interval 'n' day|month|year, + - * /, parentheses and
+//case when <conditions> then <expr> ... [else <expr>] end.
+//having and order by may also name an item by its alias, and order by by its position.
+//keywords are case-insensitive, names are lowercased, -- and /* */ comments count as
+//whitespace, and the on conditions of a join are added to the where conditions
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_until},
-    character::complete::{char, multispace0, multispace1},
-    combinator::{map, opt},
-    multi::separated_list0,
-    sequence::{delimited, preceded, terminated},
+    bytes::complete::{tag, tag_no_case, take_until, take_while, take_while1},
+    character::complete::{char, multispace1, satisfy},
+    combinator::{cut, map, map_opt, not, opt, peek, recognize, value, verify},
+    multi::{many0, many1, separated_list1},
+    sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
     IResult,
 };
-#[allow(unused_imports)]
-use nom::{bytes::complete::take_till, multi::separated_list1};
 
-#[derive(Debug, Clone)]
+use crate::expression::{compare, parse_date, precedence, Expr, IntervalUnit, Value};
+
+//words that end a table list, an expression or a condition and so cannot be aliases or columns
+const RESERVED: [&str; 32] = [
+    "select", "from", "where", "and", "join", "inner", "on", "order", "by", "as", "asc", "desc",
+    "group", "having", "limit", "case", "when", "then", "else", "end", "date", "interval",
+    "distinct", "in", "not", "exists", "left", "right", "full", "outer", "cross", "natural",
+];
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct SQLQuery {
     pub select: Select,
 }
@@ -23,46 +52,536 @@ impl SQLQuery {
     pub fn get_select(&self) -> &Select {
         &self.select
     }
+
+    //the query in sqlite syntax, with the join ... on conditions moved to the where clause,
+    //`is_text_column` tells the columns holding dates as text
+    pub fn to_sql(&self, is_text_column: &dyn Fn(&str) -> bool) -> String {
+        self.aggregates_to_sql(&self.select.exact_functions(), is_text_column)
+    }
+
+    //the query in sqlite syntax computing the given aggregates instead of its select list
+    pub fn aggregates_to_sql(
+        &self,
+        functions: &[&Aggregate],
+        is_text_column: &dyn Fn(&str) -> bool,
+    ) -> String {
+        let select = &self.select;
+        let from = self.sql_from_clause(is_text_column);
+        let aggregates: Vec<String> = functions
+            .iter()
+            .map(|function| function.to_sql(&from, is_text_column))
+            .collect();
+        // one row per group, led by its keys; having, order by and limit are left to the caller
+        // which matches the rows with the estimated groups
+        if select.is_grouped() {
+            let keys: Vec<String> = select
+                .get_group_by()
+                .iter()
+                .map(|key| key.to_sql(is_text_column))
+                .collect();
+            let keys = keys.join(", ");
+            let items = if aggregates.is_empty() {
+                keys.clone()
+            } else {
+                format!("{}, {}", keys, aggregates.join(", "))
+            };
+            return format!("select {} {} group by {}", items, from, keys);
+        }
+        // the subqueries alone would be repeated for every row of the from clause
+        if functions
+            .iter()
+            .flat_map(|function| function.base_aggregates())
+            .all(|function| matches!(function, Aggregate::Percentile(..)))
+        {
+            return format!("select {}", aggregates.join(", "));
+        }
+        format!("select {} {}", aggregates.join(", "), from)
+    }
+
+    //`from <tables> [where <conditions>]` in sqlite syntax
+    fn sql_from_clause(&self, is_text_column: &dyn Fn(&str) -> bool) -> String {
+        let select = &self.select;
+        let tables: Vec<String> = select
+            .get_table()
+            .iter()
+            .map(|table| match table.get_alias() {
+                Some(alias) => format!("{} {}", table.get_name(), alias),
+                None => table.get_name().to_string(),
+            })
+            .collect();
+        let mut from = format!("from {}", tables.join(", "));
+        if let Some(conditions) = select.get_where_clause() {
+            let conditions: Vec<String> = conditions
+                .iter()
+                .map(|condition| condition.to_sql(is_text_column))
+                .collect();
+            from += &format!(" where {}", conditions.join(" and "));
+        }
+        from
+    }
+
+    //the query whole in sqlite syntax, with its having, order by and limit, for a subquery that
+    //sqlite answers exactly; the grouped expressions of the select list come first
+    pub fn subquery_sql(&self, is_text_column: &dyn Fn(&str) -> bool) -> String {
+        let select = &self.select;
+        let from = self.sql_from_clause(is_text_column);
+        let aggregate = |function: &Aggregate| function.to_sql(&from, is_text_column);
+        let items: Vec<String> = select
+            .keys
+            .iter()
+            .map(|key| key.to_sql(is_text_column))
+            .chain(select.functions.iter().map(aggregate))
+            .collect();
+        let mut sql = format!("select {} {}", items.join(", "), from);
+        if select.is_grouped() {
+            let keys: Vec<String> = select
+                .group_by
+                .iter()
+                .map(|key| key.to_sql(is_text_column))
+                .collect();
+            sql += &format!(" group by {}", keys.join(", "));
+        }
+        if !select.having.is_empty() {
+            let conditions: Vec<String> = select
+                .having
+                .iter()
+                .map(|condition| {
+                    format!(
+                        "{} {} {}",
+                        aggregate(&condition.left),
+                        condition.operator,
+                        aggregate(&condition.right)
+                    )
+                })
+                .collect();
+            sql += &format!(" having {}", conditions.join(" and "));
+        }
+        if !select.order_by.is_empty() {
+            let items: Vec<String> = select
+                .order_by
+                .iter()
+                .map(|order_by| {
+                    let item = match &order_by.item {
+                        OrderItem::Aggregate(function) => aggregate(function),
+                        OrderItem::Key(key) => key.to_sql(is_text_column),
+                    };
+                    if order_by.descending {
+                        item + " desc"
+                    } else {
+                        item
+                    }
+                })
+                .collect();
+            sql += &format!(" order by {}", items.join(", "));
+        }
+        if let Some(limit) = select.limit {
+            sql += &format!(" limit {}", limit);
+        }
+        sql
+    }
 }
 
-#[derive(Debug, Clone)]
+//the query as parsed, with its keywords in lowercase, single spaces and no comments, so that
+//queries written differently but parsed the same have the same text, see engine::Engine::estimate
+impl std::fmt::Display for SQLQuery {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let select = &self.select;
+        let items: Vec<String> = select
+            .keys
+            .iter()
+            .map(|key| key.to_string())
+            .chain(select.functions.iter().map(|function| function.to_string()))
+            .collect();
+        let tables: Vec<String> = select
+            .table
+            .iter()
+            .map(|table| match table.get_alias() {
+                Some(alias) => format!("{} {}", table.get_name(), alias),
+                None => table.get_name().to_string(),
+            })
+            .collect();
+        write!(f, "select {} from {}", items.join(", "), tables.join(", "))?;
+        if let Some(conditions) = &select.where_clause {
+            let conditions: Vec<String> = conditions.iter().map(Where::to_string).collect();
+            write!(f, " where {}", conditions.join(" and "))?;
+        }
+        if select.is_grouped() {
+            let keys: Vec<String> = select.group_by.iter().map(Expr::to_string).collect();
+            write!(f, " group by {}", keys.join(", "))?;
+        }
+        if !select.having.is_empty() {
+            let conditions: Vec<String> = select.having.iter().map(Having::to_string).collect();
+            write!(f, " having {}", conditions.join(" and "))?;
+        }
+        if !select.order_by.is_empty() {
+            let items: Vec<String> = select.order_by.iter().map(OrderBy::to_string).collect();
+            write!(f, " order by {}", items.join(", "))?;
+        }
+        if let Some(limit) = select.limit {
+            write!(f, " limit {}", limit)?;
+        }
+        Ok(())
+    }
+}
+
+//aggregate function of the select clause
+#[derive(Debug, Clone, PartialEq)]
+pub enum Aggregate {
+    Count,
+    Sum(Expr),
+    Avg(Expr),
+    CountDistinct(Expr),
+    //value below which the given fraction of the rows fall, the lower one when the rank
+    //falls between two rows; median is the fraction 0.5
+    Percentile(Expr, f64),
+    //smallest and largest value, parsed to be answered exactly: a sample rarely holds the
+    //extreme rows, so the samples cannot estimate them
+    Min(Expr),
+    Max(Expr),
+    //a number in arithmetic over aggregates, e.g. the 100 of 100 * sum(a) / sum(b)
+    Constant(f64),
+    //+ - * / of two aggregates, estimated from the same resamples as its operands
+    Arithmetic(Box<Aggregate>, char, Box<Aggregate>),
+}
+
+impl Aggregate {
+    //expression the aggregate is computed over, None for count(*) and arithmetic
+    pub fn get_argument(&self) -> Option<&Expr> {
+        match self {
+            Aggregate::Count | Aggregate::Constant(_) | Aggregate::Arithmetic(..) => None,
+            Aggregate::Sum(argument)
+            | Aggregate::Avg(argument)
+            | Aggregate::CountDistinct(argument)
+            | Aggregate::Percentile(argument, _)
+            | Aggregate::Min(argument)
+            | Aggregate::Max(argument) => Some(argument),
+        }
+    }
+
+    //column the aggregate is computed over when its argument is a bare column
+    pub fn get_column(&self) -> Option<&str> {
+        self.get_argument().and_then(Expr::get_column)
+    }
+
+    //count(*) or a sum or average of a bare column, which the sql and columnar evaluators
+    //answer without evaluating expressions
+    pub fn is_simple(&self) -> bool {
+        match self {
+            Aggregate::CountDistinct(_) => false,
+            Aggregate::Constant(_) | Aggregate::Arithmetic(..) => {
+                self.base_aggregates().iter().all(|base| base.is_simple())
+            }
+            _ => self.get_argument().is_none() || self.get_column().is_some(),
+        }
+    }
+
+    //aggregates computed over the sample rows, left to right: the aggregate itself, or the
+    //operands of arithmetic
+    pub fn base_aggregates(&self) -> Vec<&Aggregate> {
+        match self {
+            Aggregate::Constant(_) => Vec::new(),
+            Aggregate::Arithmetic(left, _, right) => {
+                let mut bases = left.base_aggregates();
+                bases.extend(right.base_aggregates());
+                bases
+            }
+            _ => vec![self],
+        }
+    }
+
+    //value of the aggregate from the values of its base aggregates in the same order,
+    //NaN when dividing by 0
+    pub fn evaluate<I: Iterator<Item = f64>>(&self, values: &mut I) -> f64 {
+        match self {
+            Aggregate::Constant(number) => *number,
+            Aggregate::Arithmetic(left, operator, right) => {
+                let left = left.evaluate(values);
+                let right = right.evaluate(values);
+                match operator {
+                    '+' => left + right,
+                    '-' => left - right,
+                    '*' => left * right,
+                    _ if right == 0.0 => f64::NAN,
+                    _ => left / right,
+                }
+            }
+            _ => values.next().unwrap_or(f64::NAN),
+        }
+    }
+
+    //the aggregate in sqlite syntax over the from and where clauses of the query
+    fn to_sql(&self, from: &str, is_text_column: &dyn Fn(&str) -> bool) -> String {
+        match self {
+            Aggregate::Count => "count(*)".to_string(),
+            Aggregate::Sum(argument) => format!("sum({})", argument.to_sql(is_text_column)),
+            Aggregate::Avg(argument) => format!("avg({})", argument.to_sql(is_text_column)),
+            Aggregate::CountDistinct(argument) => {
+                format!("count(distinct {})", argument.to_sql(is_text_column))
+            }
+            // sqlite has no percentile function, so a subquery looks up the value at the rank
+            Aggregate::Percentile(argument, fraction) => {
+                let argument = argument.to_sql(is_text_column);
+                format!(
+                    "(select {} {} order by {} limit 1 offset (select cast({:?} * (count(*) - 1) as integer) {}))",
+                    argument, from, argument, fraction, from
+                )
+            }
+            Aggregate::Min(argument) => format!("min({})", argument.to_sql(is_text_column)),
+            Aggregate::Max(argument) => format!("max({})", argument.to_sql(is_text_column)),
+            Aggregate::Constant(number) => format!("{:?}", number),
+            // counts are integers, which sqlite would divide without the fraction
+            Aggregate::Arithmetic(left, '/', right) => format!(
+                "(cast({} as real) / {})",
+                left.to_sql(from, is_text_column),
+                right.to_sql(from, is_text_column)
+            ),
+            Aggregate::Arithmetic(left, operator, right) => format!(
+                "({} {} {})",
+                left.to_sql(from, is_text_column),
+                operator,
+                right.to_sql(from, is_text_column)
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for Aggregate {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Aggregate::Count => write!(f, "count(*)"),
+            Aggregate::Sum(argument) => write!(f, "sum({})", argument),
+            Aggregate::Avg(argument) => write!(f, "avg({})", argument),
+            Aggregate::CountDistinct(argument) => write!(f, "count(distinct {})", argument),
+            Aggregate::Percentile(argument, fraction) if *fraction == 0.5 => {
+                write!(f, "median({})", argument)
+            }
+            Aggregate::Percentile(argument, fraction) => {
+                write!(f, "percentile({}, {})", argument, fraction)
+            }
+            Aggregate::Min(argument) => write!(f, "min({})", argument),
+            Aggregate::Max(argument) => write!(f, "max({})", argument),
+            Aggregate::Constant(number) => write!(f, "{}", number),
+            Aggregate::Arithmetic(left, operator, right) => {
+                // parentheses only where the precedence needs them, like for expressions
+                let precedence = precedence(*operator);
+                match left.as_ref() {
+                    Aggregate::Arithmetic(_, inner, _) if self::precedence(*inner) < precedence => {
+                        write!(f, "({})", left)?
+                    }
+                    _ => write!(f, "{}", left)?,
+                }
+                write!(f, " {} ", operator)?;
+                match right.as_ref() {
+                    Aggregate::Arithmetic(_, inner, _)
+                        if self::precedence(*inner) < precedence
+                            || (self::precedence(*inner) == precedence
+                                && (*operator == '-' || *operator == '/')) =>
+                    {
+                        write!(f, "({})", right)
+                    }
+                    _ => write!(f, "{}", right),
+                }
+            }
+        }
+    }
+}
+
+//table of the from clause with the alias it is referenced by
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableRef {
+    name: String,
+    alias: Option<String>,
+}
+
+impl TableRef {
+    pub fn new(name: String, alias: Option<String>) -> Self {
+        TableRef { name, alias }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_alias(&self) -> Option<&str> {
+        self.alias.as_deref()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Select {
-    function: String,
-    table: Vec<String>,
+    functions: Vec<Aggregate>,
+    keys: Vec<Expr>,
+    table: Vec<TableRef>,
     where_clause: Option<Vec<Where>>,
+    group_by: Vec<Expr>,
+    having: Vec<Having>,
+    order_by: Vec<OrderBy>,
+    limit: Option<usize>,
 }
 
 impl Select {
-    fn new(function: String, table: Vec<String>, where_clause: Option<Vec<Where>>) -> Self {
+    fn new(
+        functions: Vec<Aggregate>,
+        table: Vec<TableRef>,
+        where_clause: Option<Vec<Where>>,
+    ) -> Self {
         Select {
-            function,
+            functions,
+            keys: Vec::new(),
             table,
             where_clause,
+            group_by: Vec::new(),
+            having: Vec::new(),
+            order_by: Vec::new(),
+            limit: None,
         }
     }
     //getter methods for the Select struct
-    pub fn get_function(&self) -> &str {
-        &self.function
+    //the aggregates of the select list, in order
+    pub fn get_functions(&self) -> &[Aggregate] {
+        &self.functions
     }
 
-    pub fn get_table(&self) -> &Vec<String> {
+    //expressions of the select list that are not aggregated, which have to be grouped by
+    pub fn get_keys(&self) -> &[Expr] {
+        &self.keys
+    }
+
+    pub fn get_table(&self) -> &Vec<TableRef> {
         &self.table
     }
 
     pub fn get_where_clause(&self) -> &Option<Vec<Where>> {
         &self.where_clause
     }
+
+    pub fn get_group_by(&self) -> &[Expr] {
+        &self.group_by
+    }
+
+    pub fn get_having(&self) -> &[Having] {
+        &self.having
+    }
+
+    pub fn get_order_by(&self) -> &[OrderBy] {
+        &self.order_by
+    }
+
+    pub fn get_limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn is_grouped(&self) -> bool {
+        !self.group_by.is_empty()
+    }
+
+    //aggregates of the select list the exact query answers: all of them but the percentiles
+    //of a grouped query, since sqlite has no percentile function to apply per group
+    pub fn exact_functions(&self) -> Vec<&Aggregate> {
+        self.functions
+            .iter()
+            .filter(|function| {
+                !self.is_grouped()
+                    || !function
+                        .base_aggregates()
+                        .iter()
+                        .any(|base| matches!(base, Aggregate::Percentile(..)))
+            })
+            .collect()
+    }
 }
 
-#[derive(Debug, Clone)]
+//condition of the having clause, comparing two aggregates of a group, e.g. `count(*) > 100`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Having {
+    left: Aggregate,
+    operator: String,
+    right: Aggregate,
+}
+
+impl Having {
+    pub fn new(left: Aggregate, operator: String, right: Aggregate) -> Self {
+        Having {
+            left,
+            operator,
+            right,
+        }
+    }
+
+    pub fn get_left(&self) -> &Aggregate {
+        &self.left
+    }
+
+    pub fn get_operator(&self) -> &str {
+        &self.operator
+    }
+
+    pub fn get_right(&self) -> &Aggregate {
+        &self.right
+    }
+
+    //whether the condition holds for the values of its two sides, false for NaN
+    pub fn holds(&self, left: f64, right: f64) -> bool {
+        compare(&Value::Number(left), &self.operator, &Value::Number(right))
+    }
+}
+
+impl std::fmt::Display for Having {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.left, self.operator, self.right)
+    }
+}
+
+//what an order by item sorts the groups on
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderItem {
+    Aggregate(Aggregate),
+    //one of the group by expressions
+    Key(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderBy {
+    item: OrderItem,
+    descending: bool,
+}
+
+impl OrderBy {
+    pub fn new(item: OrderItem, descending: bool) -> Self {
+        OrderBy { item, descending }
+    }
+
+    pub fn get_item(&self) -> &OrderItem {
+        &self.item
+    }
+
+    pub fn is_descending(&self) -> bool {
+        self.descending
+    }
+}
+
+impl std::fmt::Display for OrderBy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.item {
+            OrderItem::Aggregate(aggregate) => write!(f, "{}", aggregate)?,
+            OrderItem::Key(key) => write!(f, "{}", key)?,
+        }
+        if self.descending {
+            write!(f, " desc")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Where {
-    left: String,
-    right: String,
+    left: Expr,
+    right: Expr,
     operator: String,
 }
 
 impl Where {
-    pub fn new(left: String, right: String, operator: String) -> Where {
+    pub fn new(left: Expr, right: Expr, operator: String) -> Where {
         Where {
             left,
             right,
@@ -70,104 +589,1079 @@ impl Where {
         }
     }
     //getter methods for the Where struct
-    pub fn get_left(&self) -> &str {
+    pub fn get_left_expr(&self) -> &Expr {
         &self.left
     }
 
-    pub fn get_right(&self) -> &str {
+    pub fn get_right_expr(&self) -> &Expr {
         &self.right
     }
 
     pub fn get_operator(&self) -> &str {
         &self.operator
     }
+
+    //column on the left, None when the left side is any other expression
+    pub fn get_left_column(&self) -> Option<&str> {
+        self.left.get_column()
+    }
+
+    //column on the right, None when the right side is any other expression
+    pub fn get_right_column(&self) -> Option<&str> {
+        self.right.get_column()
+    }
+
+    pub fn get_left_qualifier(&self) -> Option<&str> {
+        match &self.left {
+            Expr::Column(qualifier, _) => qualifier.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn get_right_qualifier(&self) -> Option<&str> {
+        match &self.right {
+            Expr::Column(qualifier, _) => qualifier.as_deref(),
+            _ => None,
+        }
+    }
+
+    //number on the right, e.g. 20 in `l_quantity > 20`
+    pub fn get_value(&self) -> Option<f64> {
+        match self.right {
+            Expr::Number(number) => Some(number),
+            _ => None,
+        }
+    }
+
+    //text on the right, e.g. AIR in `l_shipmode = 'AIR'`
+    pub fn get_text(&self) -> Option<&str> {
+        match &self.right {
+            Expr::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    //equality between two columns, e.g. `l_orderkey = o_orderkey`
+    pub fn is_join(&self) -> bool {
+        self.operator == "="
+            && self.get_left_column().is_some()
+            && self.get_right_column().is_some()
+    }
+
+    //a column compared with a number, or equal to text that is neither a number nor a date,
+    //which the sql and columnar evaluators answer without evaluating expressions
+    pub fn is_simple(&self) -> bool {
+        if self.get_left_column().is_none() {
+            return false;
+        }
+        match (self.get_value(), self.get_text()) {
+            (Some(_), _) => matches!(self.operator.as_str(), "=" | "<>" | "<" | "<=" | ">" | ">="),
+            // a date or a number kept as text compares differently in sqlite
+            (None, Some(text)) => {
+                self.operator == "=" && matches!(Value::parse(text), Value::Text(_))
+            }
+            (None, None) => false,
+        }
+    }
+
+    //the condition in sqlite syntax, see Expr::to_sql
+    pub fn to_sql(&self, is_text_column: &dyn Fn(&str) -> bool) -> String {
+        format!(
+            "{} {} {}",
+            self.left.to_sql(is_text_column),
+            self.operator,
+            self.right.to_sql(is_text_column)
+        )
+    }
+
+    //whether the condition holds for a row, `row` gives the value of a column
+    pub fn evaluate<F: Fn(&str) -> Value>(&self, row: &F) -> bool {
+        match self.operator.as_str() {
+            // null is neither in nor out of a set
+            "in" | "not in" => match self.left.evaluate(row) {
+                Value::Null => false,
+                value => self.right.contains(&value) == (self.operator == "in"),
+            },
+            _ => compare(
+                &self.left.evaluate(row),
+                &self.operator,
+                &self.right.evaluate(row),
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for Where {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.left, self.operator, self.right)
+    }
+}
+
+//subquery on the right of an in, answered exactly over the base tables before the samples
+//are read (see engine::resolve_subqueries)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Subquery {
+    //uncorrelated select of one column, parsed like the query and answered whole by sqlite
+    Select(Box<SQLQuery>),
+    //l_orderkey of the lineitem rows meeting the conditions: the orders that
+    //`exists (select * from lineitem where l_orderkey = <key> and <conditions>)` holds for
+    OrderKeys(TableRef, Vec<Where>),
+}
+
+impl Subquery {
+    //the subquery in sqlite syntax, see Expr::to_sql
+    pub fn to_sql(&self, is_text_column: &dyn Fn(&str) -> bool) -> String {
+        match self {
+            Subquery::Select(query) => query.subquery_sql(is_text_column),
+            Subquery::OrderKeys(table, conditions) => {
+                let conditions: Vec<String> = conditions
+                    .iter()
+                    .map(|condition| condition.to_sql(is_text_column))
+                    .collect();
+                order_keys_sql(table, &conditions)
+            }
+        }
+    }
+}
+
+//`select <table>.l_orderkey from lineitem [<alias>] [where <conditions>]`
+fn order_keys_sql(table: &TableRef, conditions: &[String]) -> String {
+    let mut sql = format!(
+        "select {}.l_orderkey from {}",
+        table.get_alias().unwrap_or(table.get_name()),
+        table.get_name()
+    );
+    if let Some(alias) = table.get_alias() {
+        sql += &format!(" {}", alias);
+    }
+    if !conditions.is_empty() {
+        sql += &format!(" where {}", conditions.join(" and "));
+    }
+    sql
+}
+
+impl std::fmt::Display for Subquery {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Subquery::Select(query) => write!(f, "{}", query),
+            Subquery::OrderKeys(table, conditions) => {
+                let conditions: Vec<String> = conditions
+                    .iter()
+                    .map(|condition| condition.to_string())
+                    .collect();
+                write!(f, "{}", order_keys_sql(table, &conditions))
+            }
+        }
+    }
+}
+
+//whether a column of an exists subquery reads the subquery's own lineitem rows: qualified by
+//its table or alias, or unqualified with the l_ prefix of every lineitem column
+pub fn is_subquery_column(table: &TableRef, qualifier: Option<&str>, column: &str) -> bool {
+    match qualifier {
+        Some(qualifier) => qualifier == table.get_name() || table.get_alias() == Some(qualifier),
+        None => column.starts_with("l_"),
+    }
 }
 
 pub fn parse_sql_query(input: &str) -> IResult<&str, SQLQuery> {
     let (input, select) = parse_select(input)?;
+    let (input, _) = opt(preceded(ws, char(';')))(input)?;
+    let (input, _) = ws(input)?;
     Ok((input, SQLQuery::new(select)))
 }
 
 pub fn parse_select(input: &str) -> IResult<&str, Select> {
-    let (input, _) = multispace0(input)?;
-    let (input, function) = parse_function(input)?;
-    let (input, _) = multispace0(input)?;
-    let (input, _) = tag("from")(input)?;
-    let (input, _) = multispace1(input)?;
-    let (input, table) = parse_table_list(input)?;
+    let (input, _) = ws(input)?;
+    let (input, _) = keyword("select")(input)?;
+    let (input, _) = ws(input)?;
+    let (input, items) = separated_list1(tuple((ws, char(','), ws)), parse_select_item)(input)?;
+    let (input, _) = ws(input)?;
+    let (input, _) = keyword("from")(input)?;
+    let (input, _) = ws(input)?;
+    let (input, (table, mut conditions)) = parse_table_list(input)?;
     let (input, where_clause) = opt(parse_where_clause)(input)?;
+    let (input, group_by) = opt(parse_group_by)(input)?;
+    let (input, having) = opt(|input| parse_having(input, &items))(input)?;
+    let (input, order_by) = opt(|input| parse_order_by(input, &items))(input)?;
+    let (input, limit) = opt(parse_limit)(input)?;
+
+    // join ... on conditions are answered like the same conditions written in the where clause
+    let where_clause = match where_clause {
+        Some(where_list) => {
+            conditions.extend(where_list);
+            Some(conditions)
+        }
+        None if conditions.is_empty() => None,
+        None => Some(conditions),
+    };
+
+    let mut functions = Vec::new();
+    let mut keys = Vec::new();
+    for (item, _) in items {
+        match item {
+            SelectItem::Aggregate(function) => functions.push(function),
+            SelectItem::Key(key) => keys.push(key),
+        }
+    }
+    let mut select = Select::new(functions, table, where_clause);
+    select.keys = keys;
+    select.group_by = group_by.unwrap_or_default();
+    select.having = having.unwrap_or_default();
+    select.order_by = order_by.unwrap_or_default();
+    select.limit = limit;
+    Ok((input, select))
+}
+
+//whitespace and comments, possibly none
+fn ws(input: &str) -> IResult<&str, ()> {
+    value(
+        (),
+        many0(alt((
+            multispace1,
+            recognize(pair(tag("--"), take_while(|c: char| c != '\n'))),
+            recognize(delimited(tag("/*"), take_until("*/"), tag("*/"))),
+        ))),
+    )(input)
+}
+
+//keyword in any casing, not followed by more letters of a longer name
+fn keyword<'a>(word: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    terminated(
+        tag_no_case(word),
+        not(peek(satisfy(|c: char| c.is_alphanumeric() || c == '_'))),
+    )
+}
+
+//table, column or alias name, lowercased
+fn parse_name(input: &str) -> IResult<&str, String> {
+    map(
+        recognize(pair(
+            satisfy(|c: char| c.is_alphabetic() || c == '_'),
+            take_while(|c: char| c.is_alphanumeric() || c == '_'),
+        )),
+        |name: &str| name.to_lowercase(),
+    )(input)
+}
+
+//name that is not a keyword, so `from lineitem where ...` does not take `where` as an alias
+fn parse_alias_name(input: &str) -> IResult<&str, String> {
+    verify(parse_name, |name: &String| {
+        !RESERVED.contains(&name.as_str())
+    })(input)
+}
+
+//column with an optional table or alias qualifier, e.g. `l.l_orderkey`
+fn parse_column(input: &str) -> IResult<&str, Expr> {
+    let (input, first) = parse_alias_name(input)?;
+    let (input, second) = opt(preceded(delimited(ws, char('.'), ws), parse_name))(input)?;
+    Ok((
+        input,
+        match second {
+            Some(column) => Expr::Column(Some(first), column),
+            None => Expr::Column(None, first),
+        },
+    ))
+}
+
+//item of the select list: an aggregate, or an expression the query groups by
+#[derive(Debug, Clone)]
+enum SelectItem {
+    Aggregate(Aggregate),
+    Key(Expr),
+}
+
+impl SelectItem {
+    //the item as something order by sorts on
+    fn order_item(&self) -> OrderItem {
+        match self {
+            SelectItem::Aggregate(function) => OrderItem::Aggregate(function.clone()),
+            SelectItem::Key(key) => OrderItem::Key(key.clone()),
+        }
+    }
+}
+
+//one item of the select list with the name given to it
+fn parse_select_item(input: &str) -> IResult<&str, (SelectItem, Option<String>)> {
+    let (input, item) = alt((
+        map(parse_aggregate_expr, SelectItem::Aggregate),
+        map(parse_expr, SelectItem::Key),
+    ))(input)?;
+    // the name given to the result does not change the estimate, having and order by may use it
+    let (input, name) = opt(preceded(
+        ws,
+        alt((
+            preceded(pair(keyword("as"), ws), parse_name),
+            parse_alias_name,
+        )),
+    ))(input)?;
+
+    Ok((input, (item, name)))
+}
+
+//select item named by an alias
+fn find_alias<'b>(items: &'b [(SelectItem, Option<String>)], name: &str) -> Option<&'b SelectItem> {
+    items
+        .iter()
+        .find(|(_, alias)| alias.as_deref() == Some(name))
+        .map(|(item, _)| item)
+}
 
-    Ok((input, Select::new(function, table, where_clause)))
+//sum or difference of aggregates, e.g. `sum(a) - sum(b)`
+fn parse_aggregate_expr(input: &str) -> IResult<&str, Aggregate> {
+    parse_binary(
+        input,
+        parse_aggregate_term,
+        &['+', '-'],
+        aggregate_arithmetic,
+    )
 }
 
-fn parse_function(input: &str) -> IResult<&str, String> {
-    let (input, _) = tag("select")(input)?;
-    let (input, _) = multispace1(input)?;
-    let (input, function) = alt((
+//product or quotient of aggregates, e.g. `100 * count(*) / 5`
+fn parse_aggregate_term(input: &str) -> IResult<&str, Aggregate> {
+    parse_binary(
+        input,
+        parse_aggregate_factor,
+        &['*', '/'],
+        aggregate_arithmetic,
+    )
+}
+
+fn aggregate_arithmetic(left: Aggregate, operator: char, right: Aggregate) -> Aggregate {
+    Aggregate::Arithmetic(Box::new(left), operator, Box::new(right))
+}
+
+fn parse_aggregate_factor(input: &str) -> IResult<&str, Aggregate> {
+    alt((
+        delimited(
+            pair(char('('), ws),
+            parse_aggregate_expr,
+            pair(ws, char(')')),
+        ),
+        map_opt(parse_number, |number| {
+            number.parse::<f64>().ok().map(Aggregate::Constant)
+        }),
+        parse_function,
+    ))(input)
+}
+
+//one aggregate function
+fn parse_function(input: &str) -> IResult<&str, Aggregate> {
+    alt((
+        map(
+            tuple((
+                keyword("count"),
+                ws,
+                char('('),
+                ws,
+                char('*'),
+                ws,
+                char(')'),
+            )),
+            |_| Aggregate::Count,
+        ),
         map(
             preceded(
-                tag("count"),
-                delimited(multispace0, tag("(*)"), multispace0),
+                keyword("count"),
+                preceded(
+                    ws,
+                    delimited(
+                        pair(char('('), ws),
+                        preceded(pair(keyword("distinct"), ws), cut(parse_expr)),
+                        pair(ws, char(')')),
+                    ),
+                ),
             ),
-            |inside_parens| format!("count{}", inside_parens),
+            Aggregate::CountDistinct,
+        ),
+        map(
+            preceded(keyword("sum"), preceded(ws, parse_argument)),
+            Aggregate::Sum,
+        ),
+        map(
+            preceded(keyword("avg"), preceded(ws, parse_argument)),
+            Aggregate::Avg,
+        ),
+        map(
+            preceded(
+                keyword("percentile"),
+                preceded(
+                    ws,
+                    cut(delimited(
+                        pair(char('('), ws),
+                        separated_pair(
+                            parse_expr,
+                            tuple((ws, char(','), ws)),
+                            map_opt(parse_number, |number| number.parse::<f64>().ok()),
+                        ),
+                        pair(ws, char(')')),
+                    )),
+                ),
+            ),
+            |(argument, fraction)| Aggregate::Percentile(argument, fraction),
+        ),
+        map(
+            preceded(keyword("median"), preceded(ws, cut(parse_argument))),
+            |argument| Aggregate::Percentile(argument, 0.5),
+        ),
+        map(
+            preceded(keyword("min"), preceded(ws, parse_argument)),
+            Aggregate::Min,
+        ),
+        map(
+            preceded(keyword("max"), preceded(ws, parse_argument)),
+            Aggregate::Max,
         ),
         // add other functions here
+    ))(input)
+}
+
+//expression between parentheses, e.g. `(l_extendedprice * (1 - l_discount))`
+fn parse_argument(input: &str) -> IResult<&str, Expr> {
+    delimited(char('('), delimited(ws, parse_expr, ws), char(')'))(input)
+}
+
+//table name with an optional alias, e.g. `lineitem l` or `lineitem as l`
+fn parse_table_ref(input: &str) -> IResult<&str, TableRef> {
+    let (input, name) = parse_name(input)?;
+    let (input, alias) = opt(preceded(
+        ws,
+        alt((
+            preceded(pair(keyword("as"), ws), parse_alias_name),
+            parse_alias_name,
+        )),
     ))(input)?;
+    Ok((input, TableRef::new(name, alias)))
+}
 
-    Ok((input, function.to_string()))
+//`[inner] join <table> on <conditions>`
+fn parse_join(input: &str) -> IResult<&str, (TableRef, Vec<Where>)> {
+    let (input, _) = opt(pair(keyword("inner"), ws))(input)?;
+    let (input, _) = keyword("join")(input)?;
+    let (input, _) = ws(input)?;
+    let (input, table) = parse_table_ref(input)?;
+    let (input, _) = delimited(ws, keyword("on"), ws)(input)?;
+    let (input, conditions) = cut(parse_conditions)(input)?;
+    Ok((input, (table, conditions)))
 }
 
-fn parse_table_list(input: &str) -> IResult<&str, Vec<String>> {
-    let (input, table_list) = separated_list1(
-        delimited(multispace0, char(','), multispace0),
-        map(
-            terminated(take_until("where"), opt(char(','))),
-            |s: &str| s.trim().to_string(),
-        ),
-    )(input)?;
+//`left|right|full [outer] join`, `cross join` or `natural [...] join`, which the samples of
+//inner joins cannot answer
+fn parse_unsupported_join(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((
+        alt((
+            keyword("left"),
+            keyword("right"),
+            keyword("full"),
+            keyword("cross"),
+            recognize(pair(
+                keyword("natural"),
+                opt(preceded(
+                    ws,
+                    alt((
+                        keyword("inner"),
+                        keyword("left"),
+                        keyword("right"),
+                        keyword("full"),
+                    )),
+                )),
+            )),
+        )),
+        opt(preceded(ws, keyword("outer"))),
+        ws,
+        keyword("join"),
+    )))(input)
+}
 
-    Ok((input, table_list))
+//the join keywords at the start of the input when they ask for a join other than an inner
+//one, e.g. `left outer join`: parse_sql_query fails there, and engine::parse_query reports it
+//as unsupported rather than as a parse error
+pub fn unsupported_join(input: &str) -> Option<String> {
+    parse_unsupported_join(input).ok().map(|(_, join)| {
+        join.split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ")
+            .to_lowercase()
+    })
+}
+
+//tables of the from clause with the conditions of their joins
+fn parse_table_list(input: &str) -> IResult<&str, (Vec<TableRef>, Vec<Where>)> {
+    let (mut input, first) = parse_table_ref(input)?;
+    let mut tables = vec![first];
+    let mut conditions = Vec::new();
+
+    loop {
+        if let Ok((rest, table)) = preceded(delimited(ws, char(','), ws), parse_table_ref)(input) {
+            tables.push(table);
+            input = rest;
+        } else if let Ok((rest, (table, on))) = preceded(ws, parse_join)(input) {
+            tables.push(table);
+            conditions.extend(on);
+            input = rest;
+        } else {
+            // an outer join would otherwise leave its keywords as trailing input
+            let (join, _) = ws(input)?;
+            if parse_unsupported_join(join).is_ok() {
+                return Err(nom::Err::Failure(nom::error::Error::new(
+                    join,
+                    nom::error::ErrorKind::Verify,
+                )));
+            }
+            break;
+        }
+    }
+
+    Ok((input, (tables, conditions)))
 }
 
 pub fn parse_where_clause(input: &str) -> IResult<&str, Vec<Where>> {
-    let (input, _) = multispace0(input)?;
-    let (input, _) = tag("where")(input)?;
-    let (input, _) = multispace1(input)?;
-    let (input, where_list) = separated_list0(
-        delimited(multispace0, tag("and"), multispace0),
-        parse_where_condition,
-    )(input)?;
+    let (input, _) = ws(input)?;
+    let (input, _) = keyword("where")(input)?;
+    let (input, _) = ws(input)?;
+    // after the keyword a malformed condition is reported where it is, not as trailing input
+    cut(parse_conditions)(input)
+}
 
-    Ok((input, where_list))
+//conditions joined by and
+fn parse_conditions(input: &str) -> IResult<&str, Vec<Where>> {
+    separated_list1(delimited(ws, keyword("and"), ws), parse_where_condition)(input)
 }
 
 pub fn parse_where_condition(input: &str) -> IResult<&str, Where> {
-    let (input, left) = take_till(|c: char| c == '=' || c == '<' || c == '>')(input)?;
+    alt((parse_exists, parse_comparison_condition))(input)
+}
+
+//`<expr> <op> <expr>` or `<expr> [not] in (...)`
+fn parse_comparison_condition(input: &str) -> IResult<&str, Where> {
+    let (input, left) = parse_expr(input)?;
+    let (input, _) = ws(input)?;
+    let (input, (operator, right)) = alt((
+        parse_in,
+        map(
+            separated_pair(parse_comparison, ws, parse_expr),
+            |(operator, right)| (operator.to_string(), right),
+        ),
+    ))(input)?;
 
-    let (input, operator) = alt((tag("="), tag("<"), tag(">")))(input)?;
-    let operator = operator.to_string();
+    Ok((input, Where::new(left, right, operator)))
+}
 
-    let (input, _) = multispace1(input)?;
-    let (input, right) = alt((map(take_till(|c: char| c.is_whitespace()), |s: &str| {
-        s.trim().to_string()
-    }),))(input)?;
+//`[not] in (<expr> {, <expr>})` or `[not] in (select ...)`
+fn parse_in(input: &str) -> IResult<&str, (String, Expr)> {
+    let (input, negated) = opt(terminated(keyword("not"), ws))(input)?;
+    let (input, _) = keyword("in")(input)?;
+    let (input, _) = ws(input)?;
+    let (input, right) = cut(delimited(
+        pair(char('('), ws),
+        alt((
+            map(parse_select, |select| {
+                Expr::Subquery(Box::new(Subquery::Select(Box::new(SQLQuery::new(select)))))
+            }),
+            map(
+                separated_list1(delimited(ws, char(','), ws), parse_expr),
+                Expr::List,
+            ),
+        )),
+        pair(ws, char(')')),
+    ))(input)?;
+    let operator = if negated.is_some() { "not in" } else { "in" };
+    Ok((input, (operator.to_string(), right)))
+}
 
-    let left = left.trim().to_string();
+//`[not] exists (select ... from lineitem [alias] where l_orderkey = <key> and <conditions>)`,
+//a semi-join on the order key answered as `<key> [not] in` the l_orderkey of the lineitem
+//rows meeting the conditions
+fn parse_exists(input: &str) -> IResult<&str, Where> {
+    let (input, negated) = opt(terminated(keyword("not"), ws))(input)?;
+    let (input, _) = keyword("exists")(input)?;
+    let start = input;
+    let (input, (table, conditions)) = cut(delimited(
+        tuple((ws, char('('), ws, keyword("select"), ws)),
+        preceded(
+            tuple((
+                alt((recognize(char('*')), recognize(parse_expr))),
+                ws,
+                keyword("from"),
+                ws,
+            )),
+            pair(parse_table_ref, parse_where_clause),
+        ),
+        pair(ws, char(')')),
+    ))(input)?;
 
-    Ok((input, Where::new(left, right, operator)))
+    let is_order_key = |expr: &Expr| match expr {
+        Expr::Column(qualifier, column) => {
+            column == "l_orderkey" && is_subquery_column(&table, qualifier.as_deref(), column)
+        }
+        _ => false,
+    };
+    let is_outer = |expr: &Expr| match expr {
+        Expr::Column(qualifier, column) => {
+            !is_subquery_column(&table, qualifier.as_deref(), column)
+        }
+        _ => false,
+    };
+    // the subquery has to be over lineitem and relate to the query by its order key
+    let correlation = conditions.iter().position(|condition| {
+        let (left, right) = (condition.get_left_expr(), condition.get_right_expr());
+        condition.is_join()
+            && ((is_order_key(left) && is_outer(right)) || (is_order_key(right) && is_outer(left)))
+    });
+    let correlation = match correlation {
+        Some(correlation) if table.get_name() == "lineitem" => correlation,
+        _ => {
+            return Err(nom::Err::Failure(nom::error::Error::new(
+                start,
+                nom::error::ErrorKind::Verify,
+            )))
+        }
+    };
+
+    let mut conditions = conditions;
+    let correlation = conditions.remove(correlation);
+    let key = if is_order_key(correlation.get_left_expr()) {
+        correlation.get_right_expr()
+    } else {
+        correlation.get_left_expr()
+    };
+    let operator = if negated.is_some() { "not in" } else { "in" };
+    Ok((
+        input,
+        Where::new(
+            key.clone(),
+            Expr::Subquery(Box::new(Subquery::OrderKeys(table, conditions))),
+            operator.to_string(),
+        ),
+    ))
+}
+
+//comparison operator, with != written as <>
+fn parse_comparison(input: &str) -> IResult<&str, &str> {
+    alt((
+        tag("<="),
+        tag(">="),
+        tag("<>"),
+        value("<>", tag("!=")),
+        tag("="),
+        tag("<"),
+        tag(">"),
+    ))(input)
+}
+
+//sum or difference of terms
+pub fn parse_expr(input: &str) -> IResult<&str, Expr> {
+    parse_binary(input, parse_term, &['+', '-'], binary)
+}
+
+//product or quotient of factors
+fn parse_term(input: &str) -> IResult<&str, Expr> {
+    parse_binary(input, parse_factor, &['*', '/'], binary)
+}
+
+//operands joined left to right by operators of the same precedence
+fn parse_binary<'a, T>(
+    input: &'a str,
+    operand: fn(&'a str) -> IResult<&'a str, T>,
+    operators: &'static [char],
+    combine: fn(T, char, T) -> T,
+) -> IResult<&'a str, T> {
+    let (mut input, mut expr) = operand(input)?;
+    while let Ok((rest, (operator, right))) = pair(
+        delimited(ws, satisfy(|c| operators.contains(&c)), ws),
+        operand,
+    )(input)
+    {
+        expr = combine(expr, operator, right);
+        input = rest;
+    }
+    Ok((input, expr))
+}
+
+fn binary(left: Expr, operator: char, right: Expr) -> Expr {
+    Expr::Binary(Box::new(left), operator, Box::new(right))
+}
+
+fn parse_factor(input: &str) -> IResult<&str, Expr> {
+    alt((
+        map(
+            preceded(pair(char('-'), ws), parse_factor),
+            |expr| match expr {
+                Expr::Number(number) => Expr::Number(-number),
+                expr => Expr::Negate(Box::new(expr)),
+            },
+        ),
+        delimited(pair(char('('), ws), parse_expr, pair(ws, char(')'))),
+        parse_case,
+        map(
+            preceded(pair(keyword("date"), ws), parse_date_string),
+            Expr::Date,
+        ),
+        parse_interval,
+        map(parse_string, |text: &str| Expr::Text(text.to_string())),
+        map_opt(parse_number, |number: &str| {
+            number.parse().ok().map(Expr::Number)
+        }),
+        parse_column,
+    ))(input)
+}
+
+//`case when <conditions> then <expr> ... [else <expr>] end`
+fn parse_case(input: &str) -> IResult<&str, Expr> {
+    let (input, _) = keyword("case")(input)?;
+    let (input, branches) = cut(many1(tuple((
+        delimited(ws, keyword("when"), ws),
+        parse_conditions,
+        delimited(ws, keyword("then"), ws),
+        parse_expr,
+    ))))(input)?;
+    let (input, otherwise) = opt(preceded(delimited(ws, keyword("else"), ws), parse_expr))(input)?;
+    let (input, _) = cut(preceded(ws, keyword("end")))(input)?;
+
+    let branches = branches
+        .into_iter()
+        .map(|(_, conditions, _, result)| (conditions, result))
+        .collect();
+    Ok((input, Expr::Case(branches, otherwise.map(Box::new))))
+}
+
+//`interval '90' day`, the count may also be written without quotes
+fn parse_interval(input: &str) -> IResult<&str, Expr> {
+    let (input, _) = keyword("interval")(input)?;
+    let (input, _) = ws(input)?;
+    let (input, count) = cut(map_opt(
+        alt((
+            delimited(char('\''), parse_number, char('\'')),
+            parse_number,
+        )),
+        |count: &str| count.parse::<i64>().ok(),
+    ))(input)?;
+    let (input, _) = ws(input)?;
+    let (input, unit) = cut(alt((
+        value(IntervalUnit::Day, alt((keyword("days"), keyword("day")))),
+        value(
+            IntervalUnit::Month,
+            alt((keyword("months"), keyword("month"))),
+        ),
+        value(IntervalUnit::Year, alt((keyword("years"), keyword("year")))),
+    )))(input)?;
+    Ok((input, Expr::Interval(count, unit)))
+}
+
+//quoted string, e.g. `'MAIL'`, without its quotes
+fn parse_string(input: &str) -> IResult<&str, &str> {
+    delimited(char('\''), take_while(|c| c != '\''), char('\''))(input)
+}
+
+//quoted yyyy-mm-dd date as days since 1970-01-01
+fn parse_date_string(input: &str) -> IResult<&str, i64> {
+    cut(map_opt(parse_string, parse_date))(input)
+}
+
+//integer or decimal number
+fn parse_number(input: &str) -> IResult<&str, &str> {
+    recognize(pair(
+        take_while1(|c: char| c.is_ascii_digit()),
+        opt(pair(char('.'), take_while(|c: char| c.is_ascii_digit()))),
+    ))(input)
+}
+
+//`group by <expr> {, <expr>}`
+fn parse_group_by(input: &str) -> IResult<&str, Vec<Expr>> {
+    let (input, _) = ws(input)?;
+    let (input, _) = keyword("group")(input)?;
+    let (input, _) = delimited(ws, keyword("by"), ws)(input)?;
+    cut(separated_list1(delimited(ws, char(','), ws), parse_expr))(input)
+}
+
+//`having <aggregate> <op> <aggregate> {and ...}`, where an aggregate may be named by its alias
+fn parse_having<'a>(
+    input: &'a str,
+    items: &[(SelectItem, Option<String>)],
+) -> IResult<&'a str, Vec<Having>> {
+    let operand = |input: &'a str| {
+        alt((
+            map_opt(parse_alias_name, |name| match find_alias(items, &name) {
+                Some(SelectItem::Aggregate(function)) => Some(function.clone()),
+                _ => None,
+            }),
+            parse_aggregate_expr,
+        ))(input)
+    };
+    let condition = map(
+        tuple((operand, delimited(ws, parse_comparison, ws), operand)),
+        |(left, operator, right)| Having::new(left, operator.to_string(), right),
+    );
+    let (input, _) = ws(input)?;
+    let (input, _) = keyword("having")(input)?;
+    let (input, _) = ws(input)?;
+    cut(separated_list1(
+        delimited(ws, keyword("and"), ws),
+        condition,
+    ))(input)
+}
+
+//`order by <item> [asc|desc] {, ...}`: an alias, the position of a select item, an aggregate,
+//or a grouped expression; without group by there is one row and the order has no effect
+fn parse_order_by<'a>(
+    input: &'a str,
+    items: &[(SelectItem, Option<String>)],
+) -> IResult<&'a str, Vec<OrderBy>> {
+    let item = alt((
+        map_opt(parse_alias_name, |name| {
+            find_alias(items, &name).map(SelectItem::order_item)
+        }),
+        map(parse_aggregate_expr, |function| match function {
+            Aggregate::Constant(position)
+                if position.fract() == 0.0
+                    && position >= 1.0
+                    && position as usize <= items.len() =>
+            {
+                items[position as usize - 1].0.order_item()
+            }
+            function => OrderItem::Aggregate(function),
+        }),
+        map(parse_expr, OrderItem::Key),
+    ));
+    let direction = opt(preceded(
+        ws,
+        alt((value(false, keyword("asc")), value(true, keyword("desc")))),
+    ));
+    let (input, _) = ws(input)?;
+    let (input, _) = keyword("order")(input)?;
+    let (input, _) = delimited(ws, keyword("by"), ws)(input)?;
+    cut(separated_list1(
+        delimited(ws, char(','), ws),
+        map(pair(item, direction), |(item, descending)| {
+            OrderBy::new(item, descending.unwrap_or(false))
+        }),
+    ))(input)
 }
-#[allow(dead_code)]
-//get the value of where clause
-pub fn get_join_conditions(conditions: &[Where]) -> Vec<String> {
-    conditions
+
+//`limit <n>`, the number of groups returned
+fn parse_limit(input: &str) -> IResult<&str, usize> {
+    let (input, _) = ws(input)?;
+    let (input, _) = keyword("limit")(input)?;
+    let (input, _) = ws(input)?;
+    cut(map_opt(parse_number, |number| number.parse::<usize>().ok()))(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::parse_query;
+    use crate::error::AqpError;
+
+    fn parse(sql: &str) -> SQLQuery {
+        parse_query(sql).unwrap()
+    }
+
+    #[test]
+    fn keywords_in_any_casing_with_comments_and_aliases() {
+        let query = parse(
+            "SELECT Count(*) -- rows\n FROM LineItem AS l, orders o /* joined */ WHERE l.l_orderkey = o.o_orderkey AND l_quantity > 10;",
+        );
+        let select = query.get_select();
+        assert_eq!(select.get_functions(), &[Aggregate::Count]);
+        let tables: Vec<(&str, Option<&str>)> = select
+            .get_table()
+            .iter()
+            .map(|table| (table.get_name(), table.get_alias()))
+            .collect();
+        assert_eq!(tables, vec![("lineitem", Some("l")), ("orders", Some("o"))]);
+        let conditions: Vec<String> = select
+            .get_where_clause()
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(Where::to_string)
+            .collect();
+        assert_eq!(
+            conditions,
+            vec!["l.l_orderkey = o.o_orderkey", "l_quantity > 10"]
+        );
+    }
+
+    #[test]
+    fn join_on_conditions_come_before_the_where_conditions() {
+        let query = parse(
+            "select sum(l_extendedprice) from lineitem inner join orders on o_orderkey = l_orderkey where o_totalprice > 1000",
+        );
+        let conditions = query.get_select().get_where_clause().clone().unwrap();
+        assert_eq!(conditions.len(), 2);
+        assert!(conditions[0].is_join());
+        assert_eq!(conditions[1].to_string(), "o_totalprice > 1000");
+    }
+
+    #[test]
+    fn where_is_not_taken_as_an_alias() {
+        let query = parse("select count(*) from lineitem where l_tax < 0.05");
+        assert_eq!(query.get_select().get_table()[0].get_alias(), None);
+    }
+
+    #[test]
+    fn malformed_query_reports_the_position() {
+        match parse_query("select count(*) from lineitem where l_tax <") {
+            Err(AqpError::Parse { position, .. }) => assert_eq!(position, 43),
+            other => panic!("expected a parse error, got {:?}", other.map(|_| ())),
+        }
+        match parse_query("select count(*) from lineitem limit 3 extra") {
+            Err(AqpError::Parse { message, .. }) => assert!(message.contains("extra")),
+            other => panic!("expected a parse error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn outer_cross_and_natural_joins_are_unsupported() {
+        for join in [
+            "left join",
+            "LEFT OUTER JOIN",
+            "right join",
+            "full outer join",
+            "cross join",
+            "natural join",
+            "natural left join",
+        ] {
+            let sql = format!(
+                "select count(*) from lineitem {} orders on l_orderkey = o_orderkey",
+                join
+            );
+            assert!(parse_sql_query(&sql).is_err(), "{}", sql);
+            match parse_query(&sql) {
+                Err(AqpError::Unsupported(message)) => {
+                    assert!(message.starts_with(&join.to_lowercase()), "{}", message)
+                }
+                other => panic!("{}: expected unsupported, got {:?}", sql, other.map(|_| ())),
+            }
+        }
+    }
+
+    //the single condition of a query's where clause
+    fn condition(sql: &str) -> Where {
+        let mut conditions = parse(sql).get_select().get_where_clause().clone().unwrap();
+        assert_eq!(conditions.len(), 1, "{}", sql);
+        conditions.remove(0)
+    }
+
+    #[test]
+    fn in_list_of_literals() {
+        let condition =
+            condition("select count(*) from lineitem where l_shipmode not in ('MAIL', 'SHIP')");
+        assert_eq!(condition.get_operator(), "not in");
+        assert_eq!(
+            condition.get_right_expr(),
+            &Expr::List(vec![
+                Expr::Text("MAIL".to_string()),
+                Expr::Text("SHIP".to_string())
+            ])
+        );
+    }
+
+    #[test]
+    fn in_subquery_is_parsed_and_translated_for_sqlite() {
+        let condition = condition(
+            "select count(*) from orders where o_orderkey in (SELECT o_orderkey FROM orders WHERE o_orderdate >= DATE '1995-01-01' + interval '1' month)",
+        );
+        let Expr::Subquery(subquery) = condition.get_right_expr() else {
+            panic!("expected a subquery, got {}", condition);
+        };
+        assert!(matches!(subquery.as_ref(), Subquery::Select(_)));
+        assert_eq!(
+            subquery.to_sql(&|column| column == "o_orderdate"),
+            "select o_orderkey from orders where o_orderdate >= min(date('1995-01-01', '+1 month'), date('1995-01-01', 'start of month', '+2 month', '-1 day'))"
+        );
+        assert_eq!(
+            subquery.to_string(),
+            "select o_orderkey from orders where o_orderdate >= date '1995-01-01' + interval '1' month"
+        );
+    }
+
+    #[test]
+    fn in_subquery_keeps_group_by_having_order_by_and_limit() {
+        let condition = condition(
+            "select count(*) from orders where o_orderkey in (select l_orderkey from lineitem group by l_orderkey having sum(l_quantity) > 300 order by sum(l_quantity) desc limit 10)",
+        );
+        let Expr::Subquery(subquery) = condition.get_right_expr() else {
+            panic!("expected a subquery, got {}", condition);
+        };
+        assert_eq!(
+            subquery.to_sql(&|_| false),
+            "select l_orderkey from lineitem group by l_orderkey having sum(l_quantity) > 300.0 order by sum(l_quantity) desc limit 10"
+        );
+    }
+
+    #[test]
+    fn malformed_in_subquery_is_a_parse_error() {
+        assert!(matches!(
+            parse_query("select count(*) from orders where o_orderkey in (select from lineitem)"),
+            Err(AqpError::Parse { .. })
+        ));
+    }
+
+    #[test]
+    fn exists_becomes_an_in_on_the_order_key() {
+        let condition = condition(
+            "select count(*) from orders where not exists (select * from lineitem l where l.l_orderkey = o_orderkey and l.l_receiptdate > l.l_commitdate)",
+        );
+        assert_eq!(condition.get_operator(), "not in");
+        assert_eq!(
+            condition.get_left_expr(),
+            &Expr::Column(None, "o_orderkey".to_string())
+        );
+        assert_eq!(
+            condition.get_right_expr().to_sql(&|_| false),
+            "(select l.l_orderkey from lineitem l where l.l_receiptdate > l.l_commitdate)"
+        );
+    }
+
+    #[test]
+    fn exists_without_order_key_correlation_is_rejected() {
+        for sql in [
+            "select count(*) from orders where exists (select * from lineitem where l_quantity > 3)",
+            "select count(*) from lineitem where exists (select * from orders where o_orderkey = l_orderkey)",
+        ] {
+            assert!(matches!(parse_query(sql), Err(AqpError::Parse { .. })), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn display_normalizes_casing_spacing_and_comments() {
+        let query = parse(
+            "SELECT o_orderpriority AS p, COUNT(*) c\n FROM lineitem JOIN orders ON l_orderkey = o_orderkey /* x */\n WHERE l_shipdate >= DATE '1994-01-01' -- y\n GROUP BY o_orderpriority HAVING c > 10 ORDER BY 2 DESC LIMIT 3;",
+        );
+        let normalized = "select o_orderpriority, count(*) from lineitem, orders where l_orderkey = o_orderkey and l_shipdate >= date '1994-01-01' group by o_orderpriority having count(*) > 10 order by count(*) desc limit 3";
+        assert_eq!(query.to_string(), normalized);
+        // the normalized text parses back to the same query
+        assert_eq!(parse(normalized), query);
+    }
+
+    #[test]
+    fn display_tells_different_queries_apart() {
+        let texts: Vec<String> = [
+            "select count(*) from lineitem where l_tax < 0.05",
+            "select count(*) from lineitem where l_tax <= 0.05",
+            "select sum(l_tax) from lineitem where l_tax < 0.05",
+            "select count(*) from lineitem l where l.l_tax < 0.05",
+            "select count(*) from lineitem where l_shipmode in ('MAIL')",
+            "select count(*) from lineitem where l_shipmode not in ('MAIL')",
+        ]
         .iter()
-        .map(|condition| {
-            format!(
-                "{} {} {}",
-                condition.get_left(),
-                condition.get_operator(),
-                condition.get_right()
-            )
-        })
-        .collect()
+        .map(|sql| parse(sql).to_string())
+        .collect();
+        for (i, text) in texts.iter().enumerate() {
+            assert!(!texts[..i].contains(text), "{}", text);
+        }
+    }
+
+    #[test]
+    fn join_keywords_are_not_aliases_or_columns() {
+        for word in ["left", "right", "full", "outer", "cross", "natural"] {
+            assert!(parse_alias_name(word).is_err(), "{}", word);
+        }
+        assert_eq!(unsupported_join("inner join orders"), None);
+        assert_eq!(
+            unsupported_join("Left  Outer\n join orders"),
+            Some("left outer join".to_string())
+        );
+    }
 }
@@ -0,0 +1,307 @@
+use crate::parser::Where;
+use crate::samples::SampleResult;
+use rusqlite::{params, Connection, Result};
+use std::collections::HashMap;
+
+//column name and declared type of every column in a sample table
+pub fn table_columns(conn: &Connection, table: &str) -> Result<HashMap<String, String>> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt
+        .query_map(params![], |row| {
+            let name: String = row.get(1)?;
+            let column_type: String = row.get(2)?;
+            Ok((name.to_lowercase(), column_type.to_uppercase()))
+        })?
+        .collect::<Result<HashMap<String, String>>>()?;
+
+    Ok(columns)
+}
+
+//declared types that sqlite gives numeric affinity (INT, REAL, NUM, ...)
+pub fn is_numeric_type(column_type: &str) -> bool {
+    column_type.contains("INT")
+        || column_type.contains("REAL")
+        || column_type.contains("FLOA")
+        || column_type.contains("DOUB")
+        || column_type.contains("NUM")
+        || column_type.contains("DEC")
+}
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+//declared types that sqlite gives text affinity (CHAR, CLOB, TEXT)
+pub fn is_text_type(column_type: &str) -> bool {
+    column_type.contains("CHAR") || column_type.contains("CLOB") || column_type.contains("TEXT")
+}
+
+//translate one selection condition into sql, or None when sqlite would not give
+//the same answer as the in-Rust evaluator: a number compared with a non-numeric column,
+//text compared with a non-text column, or an unknown column
+fn condition_to_sql(condition: &Where, columns: &HashMap<String, String>) -> Option<String> {
+    if !condition.is_simple() {
+        return None;
+    }
+    let column = condition.get_left_column()?;
+    let column_type = columns.get(column)?;
+    if !is_identifier(column) {
+        return None;
+    }
+
+    match (condition.get_value(), condition.get_text()) {
+        (Some(value), _) if is_numeric_type(column_type) && value.is_finite() => Some(format!(
+            "{} {} {:?}",
+            column,
+            condition.get_operator(),
+            value
+        )),
+        (None, Some(text)) if is_text_type(column_type) => Some(format!(
+            "{} {} '{}'",
+            column,
+            condition.get_operator(),
+            text.replace('\'', "''")
+        )),
+        _ => None,
+    }
+}
+
+//build `SELECT (<predicate>), <column>, ... FROM <table>` so only the per-row contributions
+//leave sqlite, with the aggregated column of every aggregate that has one
+pub fn pushdown_sql(
+    conn: &Connection,
+    table: &str,
+    conditions: &[Where],
+    aggregated: &[Option<&str>],
+) -> Result<Option<String>> {
+    let columns = table_columns(conn, table)?;
+
+    let mut value = String::new();
+    for column in aggregated.iter().flatten() {
+        match columns.get(*column) {
+            Some(column_type) if is_identifier(column) && is_numeric_type(column_type) => {
+                value += &format!(", {}", column)
+            }
+            _ => return Ok(None),
+        }
+    }
+
+    let mut predicates = Vec::with_capacity(conditions.len());
+    for condition in conditions {
+        match condition_to_sql(condition, &columns) {
+            Some(predicate) => predicates.push(predicate),
+            None => return Ok(None),
+        }
+    }
+
+    let contribution = if predicates.is_empty() {
+        "1".to_string()
+    } else {
+        format!("CASE WHEN {} THEN 1 ELSE 0 END", predicates.join(" AND "))
+    };
+
+    Ok(Some(format!("SELECT {}{} FROM {}", contribution, value, table)))
+}
+
+//evaluate the selection conditions inside sqlite and return 1 or 0 per sample row with the
+//aggregated column, one result per aggregate, or None when the query cannot be pushed down
+pub fn pushdown_query_result(
+    conn: &Connection,
+    table: &str,
+    conditions: &[Where],
+    aggregated: &[Option<&str>],
+) -> Result<Option<Vec<SampleResult>>> {
+    let sql = match pushdown_sql(conn, table, conditions, aggregated)? {
+        Some(sql) => sql,
+        None => return Ok(None),
+    };
+
+    let mut stmt = conn.prepare(&sql)?;
+    let mut rows = stmt.query(params![])?;
+    let mut selected = Vec::new();
+    let mut values: Vec<Vec<f64>> = vec![Vec::new(); aggregated.len()];
+    while let Some(row) = rows.next()? {
+        selected.push(row.get::<_, i64>(0)?);
+        let mut index = 1;
+        for (column, values) in aggregated.iter().zip(values.iter_mut()) {
+            if column.is_some() {
+                values.push(row.get::<_, Option<f64>>(index)?.unwrap_or(0.0));
+                index += 1;
+            }
+        }
+    }
+
+    Ok(Some(
+        values
+            .into_iter()
+            .map(|values| SampleResult::new(selected.clone(), values))
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::{hashmap_query_result, parse_query},
+        samples::get_query_result,
+        test_support,
+    };
+
+    //a few s2 rows with numeric and text columns
+    fn sample() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE s2_sample (l_quantity REAL, l_discount REAL, l_tax INTEGER, l_shipmode TEXT);
+             INSERT INTO s2_sample VALUES (5, 0.02, 1, 'AIR'), (12, 0.04, 0, 'MAIL'), (30, 0.07, 1, 'SHIP'), (11, 0.05, 2, 'AIR');",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn condition(left: &str, operator: &str, right: &str) -> Where {
+        let sql = format!(
+            "select count(*) from lineitem where {} {} {}",
+            left, operator, right
+        );
+        let query = parse_query(&sql).unwrap();
+        query
+            .get_select()
+            .get_where_clause()
+            .clone()
+            .unwrap()
+            .remove(0)
+    }
+
+    #[test]
+    fn numeric_comparisons_are_pushed_down() {
+        let conn = sample();
+        let conditions = [
+            condition("l_quantity", ">", "10"),
+            condition("l_discount", "<", "0.05"),
+        ];
+        assert_eq!(
+            pushdown_sql(&conn, "s2_sample", &conditions, &[None, Some("l_tax")]).unwrap().unwrap(),
+            "SELECT CASE WHEN l_quantity > 10.0 AND l_discount < 0.05 THEN 1 ELSE 0 END, l_tax FROM s2_sample"
+        );
+    }
+
+    #[test]
+    fn every_comparison_with_a_number_and_text_equality_are_pushed_down() {
+        let conn = sample();
+        let conditions = [
+            condition("l_quantity", ">=", "11"),
+            condition("l_discount", "<=", "0.05"),
+            condition("l_tax", "<>", "0"),
+            condition("l_shipmode", "=", "'AIR'"),
+        ];
+        assert_eq!(
+            pushdown_sql(&conn, "s2_sample", &conditions, &[None]).unwrap().unwrap(),
+            "SELECT CASE WHEN l_quantity >= 11.0 AND l_discount <= 0.05 AND l_tax <> 0.0 AND l_shipmode = 'AIR' THEN 1 ELSE 0 END FROM s2_sample"
+        );
+        let pushed = pushdown_query_result(&conn, "s2_sample", &conditions, &[None])
+            .unwrap()
+            .unwrap();
+        assert_eq!(pushed[0].selected, vec![0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn conditions_sqlite_would_answer_differently_are_not_pushed_down() {
+        let conn = sample();
+        for condition in [
+            condition("l_shipmode", "<", "10"),
+            condition("l_quantity", ">", "'10'"),
+            condition("l_unknown", ">", "10"),
+            condition("l_shipmode", "<>", "'AIR'"),
+            condition("l_shipmode", "=", "'1995-01-01'"),
+            condition("l_tax", "=", "'1'"),
+        ] {
+            assert_eq!(
+                pushdown_sql(&conn, "s2_sample", &[condition], &[None]).unwrap(),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn pushed_down_result_matches_the_rust_evaluator() {
+        let conn = sample();
+        let rows: Vec<HashMap<String, String>> = conn
+            .prepare("SELECT l_quantity, l_discount, l_tax, l_shipmode FROM s2_sample")
+            .unwrap()
+            .query_map(params![], |row| {
+                let (quantity, discount, tax): (f64, f64, i64) =
+                    (row.get(0)?, row.get(1)?, row.get(2)?);
+                Ok(HashMap::from([
+                    ("l_quantity".to_string(), quantity.to_string()),
+                    ("l_discount".to_string(), discount.to_string()),
+                    ("l_tax".to_string(), tax.to_string()),
+                    ("l_shipmode".to_string(), row.get(3)?),
+                ]))
+            })
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+
+        let conditions = [
+            condition("l_quantity", ">", "10"),
+            condition("l_discount", "<", "0.06"),
+        ];
+        let pushed = pushdown_query_result(&conn, "s2_sample", &conditions, &[Some("l_quantity")])
+            .unwrap()
+            .unwrap();
+        assert_eq!(pushed[0].selected, vec![0, 1, 0, 1]);
+        assert_eq!(pushed[0].selected, get_query_result(&rows, &conditions));
+        assert_eq!(pushed[0].values, vec![5.0, 12.0, 30.0, 11.0]);
+
+        // equalities are pushed down too, and select the same rows
+        for conditions in [
+            vec![condition("l_quantity", "=", "12")],
+            vec![
+                condition("l_tax", "=", "1"),
+                condition("l_quantity", "<>", "30"),
+            ],
+            vec![
+                condition("l_shipmode", "=", "'AIR'"),
+                condition("l_tax", ">=", "1"),
+            ],
+        ] {
+            let pushed = pushdown_query_result(&conn, "s2_sample", &conditions, &[None])
+                .unwrap()
+                .unwrap();
+            assert_eq!(pushed[0].selected, get_query_result(&rows, &conditions));
+            assert!(pushed[0].selected.contains(&1));
+        }
+    }
+
+    #[test]
+    fn pushed_down_generated_sample_matches_the_rust_evaluator() {
+        let (conn, sample_set) = test_support::sampled_database(0.1, 3);
+        let table = sample_set.table(2);
+        let query = parse_query("select count(*), sum(l_extendedprice) from lineitem, orders where l_orderkey = o_orderkey and l_quantity >= 10 and l_discount <> 0.05 and l_shipmode = 'AIR' and o_totalprice <= 200000").unwrap();
+        let select = query.get_select();
+        let conditions: Vec<Where> = select
+            .get_where_clause()
+            .clone()
+            .unwrap()
+            .into_iter()
+            .filter(|condition| !condition.is_join())
+            .collect();
+        let aggregates: Vec<&_> = select.get_functions().iter().collect();
+        let columns: Vec<Option<&str>> = aggregates
+            .iter()
+            .map(|aggregate| aggregate.get_column())
+            .collect();
+
+        let pushed = pushdown_query_result(&conn, &table, &conditions, &columns)
+            .unwrap()
+            .unwrap();
+        let evaluated =
+            hashmap_query_result(&conn, 2, &table, &conditions, &aggregates, &[]).unwrap();
+        for (pushed, evaluated) in pushed.iter().zip(&evaluated) {
+            assert_eq!(pushed.selected, evaluated.selected);
+            assert_eq!(pushed.values, evaluated.values);
+        }
+        assert!(pushed[0].selected.contains(&1) && pushed[0].selected.contains(&0));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::expression::{Expr, Value};
 use crate::parser::Where;
 use rusqlite::{Connection, Result, Row};
 use std::collections::HashMap;
@@ -11,6 +12,96 @@ macro_rules! insert_to_hashmap {
     };
 }
 
+//S1_sample data
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct S1Sample {
+    //LineItem fields
+    pub l_orderkey: i32,
+    pub l_partkey: i32,
+    pub l_suppkey: i32,
+    pub l_linenumber: i32,
+    pub l_quantity: f64,
+    pub l_extendedprice: f64,
+    pub l_discount: f64,
+    pub l_tax: f64,
+    pub l_returnflag: String,
+    pub l_linestatus: String,
+    pub l_shipdate: String,
+    pub l_commitdate: String,
+    pub l_receiptdate: String,
+    pub l_shipinstruct: String,
+    pub l_shipmode: String,
+    pub l_comment: String,
+}
+
+impl S1Sample {
+    fn from_row(row: &Row) -> Result<Self> {
+        Ok(S1Sample {
+            l_orderkey: row.get(0)?,
+            l_partkey: row.get(1)?,
+            l_suppkey: row.get(2)?,
+            l_linenumber: row.get(3)?,
+            l_quantity: row.get(4)?,
+            l_extendedprice: row.get(5)?,
+            l_discount: row.get(6)?,
+            l_tax: row.get(7)?,
+            l_returnflag: row.get(8)?,
+            l_linestatus: row.get(9)?,
+            l_shipdate: row.get(10)?,
+            l_commitdate: row.get(11)?,
+            l_receiptdate: row.get(12)?,
+            l_shipinstruct: row.get(13)?,
+            l_shipmode: row.get(14)?,
+            l_comment: row.get(15)?,
+        })
+    }
+}
+
+//fetch the data from database
+pub fn fetch_s1_sample(conn: &Connection, table: &str) -> Result<Vec<S1Sample>> {
+    let mut stmt = conn.prepare(&format!("SELECT * FROM {}", table))?;
+    let s1_samples_iter = stmt.query_map([], S1Sample::from_row)?;
+
+    let mut s1_samples = Vec::new();
+    for sample in s1_samples_iter {
+        s1_samples.push(sample?);
+    }
+
+    Ok(s1_samples)
+}
+
+//convert struct into hashmap for easier search
+pub fn s1_sample_to_hashmap(samples: &[S1Sample]) -> Vec<HashMap<String, String>> {
+    samples
+        .iter()
+        .map(|sample| {
+            let mut hashmap = HashMap::new();
+            insert_to_hashmap!(
+                hashmap,
+                sample,
+                l_orderkey,
+                l_partkey,
+                l_suppkey,
+                l_linenumber,
+                l_quantity,
+                l_extendedprice,
+                l_discount,
+                l_tax,
+                l_returnflag,
+                l_linestatus,
+                l_shipdate,
+                l_commitdate,
+                l_receiptdate,
+                l_shipinstruct,
+                l_shipmode,
+                l_comment
+            );
+            hashmap
+        })
+        .collect()
+}
+
 //S2_sample data
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
@@ -78,8 +169,8 @@ impl S2Sample {
 }
 
 //fetch the data from database
-pub fn fetch_s2_sample(conn: &Connection) -> Result<Vec<S2Sample>> {
-    let mut stmt = conn.prepare("SELECT * FROM s2_sample")?;
+pub fn fetch_s2_sample(conn: &Connection, table: &str) -> Result<Vec<S2Sample>> {
+    let mut stmt = conn.prepare(&format!("SELECT * FROM {}", table))?;
     let s2_samples_iter = stmt.query_map([], S2Sample::from_row)?;
 
     let mut s2_samples = Vec::new();
@@ -224,8 +315,8 @@ impl S3Sample {
 }
 
 //fetch sample data from database
-pub fn fetch_s3_sample(conn: &Connection) -> Result<Vec<S3Sample>> {
-    let mut stmt = conn.prepare("SELECT * FROM s3_sample")?;
+pub fn fetch_s3_sample(conn: &Connection, table: &str) -> Result<Vec<S3Sample>> {
+    let mut stmt = conn.prepare(&format!("SELECT * FROM {}", table))?;
     let s3_samples_iter = stmt.query_map([], S3Sample::from_row)?;
 
     let mut s3_samples = Vec::new();
@@ -374,8 +465,8 @@ impl S4Sample {
 }
 
 //fetch sample data from database
-pub fn fetch_s4_sample(conn: &Connection) -> Result<Vec<S4Sample>> {
-    let mut stmt = conn.prepare("SELECT * FROM s4_sample")?;
+pub fn fetch_s4_sample(conn: &Connection, table: &str) -> Result<Vec<S4Sample>> {
+    let mut stmt = conn.prepare(&format!("SELECT * FROM {}", table))?;
     let s4_samples_iter = stmt.query_map([], S4Sample::from_row)?;
 
     let mut s4_samples = Vec::new();
@@ -548,8 +639,8 @@ impl S5Sample {
 }
 
 //fetch sample data from database
-pub fn fetch_s5_sample(conn: &Connection) -> Result<Vec<S5Sample>> {
-    let mut stmt = conn.prepare("SELECT * FROM s5_sample")?;
+pub fn fetch_s5_sample(conn: &Connection, table: &str) -> Result<Vec<S5Sample>> {
+    let mut stmt = conn.prepare(&format!("SELECT * FROM {}", table))?;
     let s5_samples_iter = stmt.query_map([], S5Sample::from_row)?;
 
     let mut s5_samples = Vec::new();
@@ -626,27 +717,20 @@ pub fn s5_sample_to_hashmap(samples: &[S5Sample]) -> Vec<HashMap<String, String>
     hashmaps
 }
 
+//value of a column in a sample row, parsed as a number or date when it is one
+fn row_value(row: &HashMap<String, String>, column: &str) -> Value {
+    row.get(column).map_or(Value::Null, |value| Value::parse(value))
+}
+
 //fn to check for the where condition and return 1 if true or 0
-pub fn get_query_result(data: &Vec<HashMap<String, String>>, conditions: &Vec<Where>) -> Vec<i64> {
+pub fn get_query_result(data: &[HashMap<String, String>], conditions: &[Where]) -> Vec<i64> {
     let mut results = Vec::with_capacity(data.len());
 
     for row in data {
-        let mut all_conditions_passed = true;
-        for condition in conditions {
-            let column_value = match row.get(&condition.get_left().to_lowercase()) {
-                Some(value) => value.parse::<f64>().unwrap_or(0.0), // parsing string as f64 so that it works on both int and float data types
-                None => continue,
-            };
-            let condition_value = condition.get_right().parse::<f64>().unwrap_or(0.0);
-            //matching the comparator and returning true or false based on the condition values
-            let condition_result = match condition.get_operator() {
-                "<" => column_value < condition_value,
-                ">" => column_value > condition_value,
-                _ => false,
-            };
-
-            all_conditions_passed &= condition_result;
-        }
+        let lookup = |column: &str| row_value(row, column);
+        let all_conditions_passed = conditions
+            .iter()
+            .all(|condition| condition.evaluate(&lookup));
 
         //inserting 1 if all conditions are true else inserting 0
         let result = if all_conditions_passed { 1 } else { 0 };
@@ -655,3 +739,73 @@ pub fn get_query_result(data: &Vec<HashMap<String, String>>, conditions: &Vec<Wh
 
     results
 }
+
+//value of the aggregated expression for every sample row, nulls as 0
+pub fn get_expression_values(data: &[HashMap<String, String>], argument: &Expr) -> Vec<f64> {
+    data.iter()
+        .map(|row| {
+            argument
+                .evaluate(&|column: &str| row_value(row, column))
+                .to_f64()
+        })
+        .collect()
+}
+
+//value of the counted or grouped expression for every sample row as a key, None for null
+pub fn get_expression_keys(data: &[HashMap<String, String>], argument: &Expr) -> Vec<Option<String>> {
+    data.iter()
+        .map(|row| argument.evaluate(&|column: &str| row_value(row, column)).key())
+        .collect()
+}
+
+//per sample row: 1 if all selection conditions passed or 0, and the value of the
+//aggregated column (empty for count(*)), or its key for count(distinct ...) and group by
+#[derive(Debug, Clone, Default)]
+pub struct SampleResult {
+    pub selected: Vec<i64>,
+    pub values: Vec<f64>,
+    pub keys: Vec<Option<String>>,
+}
+
+impl SampleResult {
+    pub fn new(selected: Vec<i64>, values: Vec<f64>) -> Self {
+        SampleResult {
+            selected,
+            values,
+            keys: Vec::new(),
+        }
+    }
+
+    pub fn with_keys(selected: Vec<i64>, keys: Vec<Option<String>>) -> Self {
+        SampleResult {
+            selected,
+            values: Vec::new(),
+            keys,
+        }
+    }
+
+    //how often each key occurs among the selected rows of a group, `groups` giving the group
+    //of every row
+    pub fn key_frequencies(&self, groups: &[usize], group: usize) -> HashMap<&str, usize> {
+        let mut frequencies = HashMap::new();
+        for ((&selected, key), &row_group) in self.selected.iter().zip(&self.keys).zip(groups) {
+            if let (1, Some(key), true) = (selected, key, row_group == group) {
+                *frequencies.entry(key.as_str()).or_insert(0) += 1;
+            }
+        }
+        frequencies
+    }
+
+    //what every row adds to the aggregate: the selection flag for count(*),
+    //the selected value otherwise
+    pub fn contributions(&self) -> Vec<f64> {
+        if self.values.is_empty() {
+            return self.selected.iter().map(|&s| s as f64).collect();
+        }
+        self.selected
+            .iter()
+            .zip(&self.values)
+            .map(|(&s, &value)| s as f64 * value)
+            .collect()
+    }
+}
@@ -1,77 +1,439 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
 use rusqlite::{params, Connection, Result};
+use std::path::{Path, PathBuf};
 
-pub fn create_sample_tables(conn: &Connection, sample_fraction: f64) -> Result<()> {
-    // Drop existing sample tables if they exist
-    conn.execute("DROP TABLE IF EXISTS s1_sample", params![])?;
-    conn.execute("DROP TABLE IF EXISTS s2_sample", params![])?;
-    conn.execute("DROP TABLE IF EXISTS s3_sample", params![])?;
-    conn.execute("DROP TABLE IF EXISTS s4_sample", params![])?;
-    conn.execute("DROP TABLE IF EXISTS s5_sample", params![])?;
+//a set of s1..s5 sample tables built from the same lineitem sample
+#[derive(Debug, Clone)]
+pub struct SampleSet {
+    suffix: String,
+    fraction: f64,
+    rows: i64,
+    parquet_dir: Option<PathBuf>,
+}
 
-    // Create s1_sample table structure
+impl SampleSet {
+    pub fn new(suffix: String, fraction: f64, rows: i64) -> Self {
+        SampleSet {
+            suffix,
+            fraction,
+            rows,
+            parquet_dir: None,
+        }
+    }
+
+    //the same sample set persisted as parquet files in a directory
+    pub fn with_parquet_dir(mut self, dir: &Path) -> Self {
+        self.parquet_dir = Some(dir.to_path_buf());
+        self
+    }
+    //getter methods for the SampleSet struct
+    pub fn get_suffix(&self) -> &str {
+        &self.suffix
+    }
+
+    pub fn get_fraction(&self) -> f64 {
+        self.fraction
+    }
+
+    pub fn get_rows(&self) -> i64 {
+        self.rows
+    }
+
+    pub fn get_parquet_dir(&self) -> Option<&Path> {
+        self.parquet_dir.as_deref()
+    }
+
+    //name of the s*level sample table in this set, e.g. s2_sample_r1
+    pub fn table(&self, level: usize) -> String {
+        format!("s{}_sample{}", level, self.suffix)
+    }
+}
+
+//whether a sample fraction is a share of the rows, in (0, 1]; NaN is not
+pub fn is_sample_fraction(fraction: f64) -> bool {
+    fraction > 0.0 && fraction <= 1.0
+}
+
+//catalog of every sample set in the database with its sample fraction
+fn create_catalog(conn: &Connection) -> Result<()> {
     conn.execute(
-        "CREATE TABLE s1_sample AS SELECT * FROM lineitem WHERE 1=0",
+        "CREATE TABLE IF NOT EXISTS sample_catalog (
+            suffix TEXT PRIMARY KEY,
+            fraction REAL NOT NULL,
+            rows INTEGER NOT NULL
+         )",
         params![],
     )?;
-
-    // Generate shuffled row IDs
     conn.execute(
-        "CREATE TEMP TABLE ids AS SELECT rowid FROM lineitem ORDER BY RANDOM()",
+        "CREATE TABLE IF NOT EXISTS sample_version (version INTEGER NOT NULL)",
         params![],
     )?;
+    Ok(())
+}
 
-    // Calculate the number of rows to sample
-    let total_rows: i64 =
-        conn.query_row("SELECT COUNT(*) FROM lineitem", params![], |row| row.get(0))?;
-    let sample_size = (sample_fraction * total_rows as f64).round() as i64;
+//version of the samples in the catalog, bumped every time they are rebuilt so that what was
+//computed from the previous samples (see engine::Engine::with_cache) is not reused
+pub fn sample_version(conn: &Connection) -> Result<i64> {
+    create_catalog(conn)?;
+    conn.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM sample_version",
+        params![],
+        |row| row.get(0),
+    )
+}
+
+//drop the s1..s5 tables of every sample set recorded in the catalog
+fn drop_catalog_samples(conn: &Connection) -> Result<()> {
+    for set in load_sample_catalog(conn)? {
+        drop_sample_set(conn, &set)?;
+    }
+    conn.execute("DELETE FROM sample_catalog", params![])?;
+    Ok(())
+}
+
+//read the catalog ordered from the smallest to the largest sample
+pub fn load_sample_catalog(conn: &Connection) -> Result<Vec<SampleSet>> {
+    create_catalog(conn)?;
+    let mut stmt =
+        conn.prepare("SELECT suffix, fraction, rows FROM sample_catalog ORDER BY fraction")?;
+    let sets = stmt
+        .query_map([], |row| {
+            Ok(SampleSet::new(row.get(0)?, row.get(1)?, row.get(2)?))
+        })?
+        .collect::<Result<Vec<SampleSet>>>()?;
+
+    Ok(sets)
+}
+
+//shuffle the lineitem row IDs once so that every sample is a prefix of the same order,
+//with sqlite's RANDOM() or, to make the samples reproducible, a seeded generator
+fn shuffle_lineitem_ids(conn: &Connection, seed: Option<u64>) -> Result<i64> {
+    conn.execute("DROP TABLE IF EXISTS temp.ids", params![])?;
+
+    match seed {
+        None => {
+            conn.execute(
+                "CREATE TEMP TABLE ids AS SELECT rowid FROM lineitem ORDER BY RANDOM()",
+                params![],
+            )?;
+        }
+        Some(seed) => {
+            let mut stmt = conn.prepare("SELECT rowid FROM lineitem ORDER BY rowid")?;
+            let mut ids = stmt
+                .query_map(params![], |row| row.get::<_, i64>(0))?
+                .collect::<Result<Vec<i64>>>()?;
+            ids.shuffle(&mut StdRng::seed_from_u64(seed));
+
+            conn.execute("CREATE TEMP TABLE ids (rowid INTEGER)", params![])?;
+            for chunk in ids.chunks(500) {
+                let values: Vec<String> = chunk.iter().map(|id| format!("({})", id)).collect();
+                conn.execute(
+                    &format!("INSERT INTO ids VALUES {}", values.join(", ")),
+                    params![],
+                )?;
+            }
+        }
+    }
+
+    conn.query_row("SELECT COUNT(*) FROM lineitem", params![], |row| row.get(0))
+}
+
+//build s1..s5 tables for one sample set from the first sample_size shuffled IDs
+fn build_sample_set(conn: &Connection, set: &SampleSet) -> Result<()> {
+    build_sample_rows(conn, set)?;
+    join_sample_set(conn, set)
+}
+
+//build the s1 table of one sample set from the first sample_size shuffled IDs
+fn build_sample_rows(conn: &Connection, set: &SampleSet) -> Result<()> {
+    let s1 = set.table(1);
+
+    // Drop existing sample table if it exists
+    conn.execute(&format!("DROP TABLE IF EXISTS {}", s1), params![])?;
+
+    // Create s1_sample table structure
+    conn.execute(
+        &format!("CREATE TABLE {} AS SELECT * FROM lineitem WHERE 1=0", s1),
+        params![],
+    )?;
 
     // Take first N shuffled IDs as sample
     conn.execute(
-        "INSERT INTO s1_sample
-         SELECT * FROM lineitem
-         WHERE rowid IN (SELECT rowid FROM ids LIMIT ?)",
-        params![sample_size],
+        &format!(
+            "INSERT INTO {}
+             SELECT * FROM lineitem
+             WHERE rowid IN (SELECT rowid FROM ids LIMIT ?)",
+            s1
+        ),
+        params![set.get_rows()],
     )?;
-    println!("s1_sample table created with sampled data.");
+    info!("{} table created with sampled data.", s1);
+    Ok(())
+}
+
+//build s2..s5 tables by joining an existing s1 sample with the dimension tables,
+//and record the sample set in the catalog
+pub fn join_sample_set(conn: &Connection, set: &SampleSet) -> Result<()> {
+    join_sample_tables(conn, set)?;
+    conn.execute(
+        "INSERT OR REPLACE INTO sample_catalog (suffix, fraction, rows) VALUES (?, ?, ?)",
+        params![set.get_suffix(), set.get_fraction(), set.get_rows()],
+    )?;
+
+    Ok(())
+}
+
+//build s2..s5 tables by joining an existing s1 sample with the dimension tables
+fn join_sample_tables(conn: &Connection, set: &SampleSet) -> Result<()> {
+    let s1 = set.table(1);
+    let s2 = set.table(2);
+    let s3 = set.table(3);
+    let s4 = set.table(4);
+    let s5 = set.table(5);
+
+    // Drop existing sample tables if they exist
+    for table in [&s2, &s3, &s4, &s5] {
+        conn.execute(&format!("DROP TABLE IF EXISTS {}", table), params![])?;
+    }
+
     // Join s1_sample with orders table to create s2_sample
     conn.execute(
-        "CREATE TABLE IF NOT EXISTS s2_sample AS
-         SELECT s1.*, orders.*
-         FROM s1_sample AS s1
-         JOIN orders ON s1.l_orderkey = orders.o_orderkey",
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {} AS
+             SELECT s1.*, orders.*
+             FROM {} AS s1
+             JOIN orders ON s1.l_orderkey = orders.o_orderkey",
+            s2, s1
+        ),
         params![],
     )?;
-    println!("s2_sample table created with joined data.");
+    info!("{} table created with joined data.", s2);
 
     // Join s2_sample with customer table to create s3_sample
     conn.execute(
-        "CREATE TABLE IF NOT EXISTS s3_sample AS
-         SELECT s2.*, customer.*
-         FROM s2_sample AS s2
-         JOIN customer ON s2.o_custkey = customer.c_custkey",
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {} AS
+             SELECT s2.*, customer.*
+             FROM {} AS s2
+             JOIN customer ON s2.o_custkey = customer.c_custkey",
+            s3, s2
+        ),
         params![],
     )?;
-    println!("s3_sample table created with joined data.");
+    info!("{} table created with joined data.", s3);
 
     // Join s3_sample with nation table to create s4_sample
     conn.execute(
-        "CREATE TABLE IF NOT EXISTS s4_sample AS
-         SELECT s3.*, nation.*
-         FROM s3_sample AS s3
-         JOIN nation ON s3.c_nationkey = nation.n_nationkey",
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {} AS
+             SELECT s3.*, nation.*
+             FROM {} AS s3
+             JOIN nation ON s3.c_nationkey = nation.n_nationkey",
+            s4, s3
+        ),
         params![],
     )?;
-    println!("s4_sample table created with joined data.");
+    info!("{} table created with joined data.", s4);
 
     // Join s4_sample with region table to create s5_sample
     conn.execute(
-        "CREATE TABLE IF NOT EXISTS s5_sample AS
-         SELECT s4.*, region.*
-         FROM s4_sample AS s4
-         JOIN region ON s4.n_regionkey = region.r_regionkey",
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {} AS
+             SELECT s4.*, region.*
+             FROM {} AS s4
+             JOIN region ON s4.n_regionkey = region.r_regionkey",
+            s5, s4
+        ),
         params![],
     )?;
-    println!("s5_sample table created with joined data.");
+    info!("{} table created with joined data.", s5);
+
+    Ok(())
+}
 
+//drop the s1..s5 tables of one sample set
+pub fn drop_sample_set(conn: &Connection, set: &SampleSet) -> Result<()> {
+    for level in 1..=5 {
+        conn.execute(
+            &format!("DROP TABLE IF EXISTS {}", set.table(level)),
+            params![],
+        )?;
+    }
     Ok(())
 }
+
+//drop every sample set recorded in the catalog before building new ones
+pub fn reset_catalog(conn: &Connection) -> Result<()> {
+    let version = sample_version(conn)?;
+    drop_catalog_samples(conn)?;
+    conn.execute("DELETE FROM sample_version", params![])?;
+    conn.execute(
+        "INSERT INTO sample_version (version) VALUES (?)",
+        params![version + 1],
+    )?;
+    Ok(())
+}
+
+pub fn create_sample_tables(conn: &Connection, sample_fraction: f64) -> Result<SampleSet> {
+    build_sample_tables(conn, sample_fraction, None)
+}
+
+//same as create_sample_tables, but the same seed always draws the same sample
+pub fn create_seeded_sample_tables(
+    conn: &Connection,
+    sample_fraction: f64,
+    seed: u64,
+) -> Result<SampleSet> {
+    build_sample_tables(conn, sample_fraction, Some(seed))
+}
+
+fn build_sample_tables(
+    conn: &Connection,
+    sample_fraction: f64,
+    seed: Option<u64>,
+) -> Result<SampleSet> {
+    reset_catalog(conn)?;
+
+    // Calculate the number of rows to sample
+    let total_rows = shuffle_lineitem_ids(conn, seed)?;
+    let sample_size = (sample_fraction * total_rows as f64).round() as i64;
+
+    let set = SampleSet::new(String::new(), sample_fraction, sample_size);
+    build_sample_set(conn, &set)?;
+
+    Ok(set)
+}
+
+//build a sample set under its own suffix, e.g. s1_sample_bench, without touching the
+//catalog or the samples recorded in it; drop it with drop_sample_set once done
+pub fn create_scratch_sample_tables(
+    conn: &Connection,
+    suffix: &str,
+    sample_fraction: f64,
+    seed: Option<u64>,
+) -> Result<SampleSet> {
+    let total_rows = shuffle_lineitem_ids(conn, seed)?;
+    let sample_size = (sample_fraction * total_rows as f64).round() as i64;
+
+    let set = SampleSet::new(suffix.to_string(), sample_fraction, sample_size);
+    build_sample_rows(conn, &set)?;
+    join_sample_tables(conn, &set)?;
+
+    Ok(set)
+}
+
+//build a ladder of nested samples (e.g. 0.1%, 1%, 10%) from a single shuffle of lineitem,
+//so every smaller rung is a subset of the larger ones; the same seed draws the same ladder
+pub fn create_sample_ladder(
+    conn: &Connection,
+    sample_fractions: &[f64],
+    seed: Option<u64>,
+) -> Result<Vec<SampleSet>> {
+    reset_catalog(conn)?;
+
+    let mut fractions = sample_fractions.to_vec();
+    fractions.sort_by(f64::total_cmp);
+    fractions.dedup();
+
+    let total_rows = shuffle_lineitem_ids(conn, seed)?;
+
+    let mut ladder = Vec::with_capacity(fractions.len());
+    for (rung, fraction) in fractions.into_iter().enumerate() {
+        let sample_size = (fraction * total_rows as f64).round() as i64;
+        let set = SampleSet::new(format!("_r{}", rung), fraction, sample_size);
+        build_sample_set(conn, &set)?;
+        ladder.push(set);
+    }
+
+    Ok(ladder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn database() -> Connection {
+        test_support::database(5)
+    }
+
+    //l_orderkey and l_linenumber of the rows of a sample table, which identify them
+    fn rows(conn: &Connection, table: &str) -> Vec<(i64, i64)> {
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT l_orderkey, l_linenumber FROM {} ORDER BY 1, 2",
+                table
+            ))
+            .unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn ladder_rungs_are_nested_and_sorted() {
+        let conn = database();
+        let ladder = create_sample_ladder(&conn, &[0.5, 0.1, 0.5, 0.25], Some(1)).unwrap();
+        let fractions: Vec<f64> = ladder.iter().map(SampleSet::get_fraction).collect();
+        assert_eq!(fractions, vec![0.1, 0.25, 0.5]);
+
+        for pair in ladder.windows(2) {
+            let larger = rows(&conn, &pair[1].table(1));
+            let smaller = rows(&conn, &pair[0].table(1));
+            assert_eq!(smaller.len() as i64, pair[0].get_rows());
+            assert!(smaller.iter().all(|row| larger.contains(row)));
+        }
+        let catalog: Vec<f64> = load_sample_catalog(&conn)
+            .unwrap()
+            .iter()
+            .map(SampleSet::get_fraction)
+            .collect();
+        assert_eq!(catalog, fractions);
+    }
+
+    #[test]
+    fn seeded_ladder_is_reproducible() {
+        let conn = database();
+        let first = create_sample_ladder(&conn, &[0.05, 0.2], Some(11)).unwrap();
+        let first_rows = rows(&conn, &first[0].table(1));
+        let second = create_sample_ladder(&conn, &[0.05, 0.2], Some(11)).unwrap();
+        assert_eq!(rows(&conn, &second[0].table(1)), first_rows);
+        create_sample_ladder(&conn, &[0.05, 0.2], Some(12)).unwrap();
+        assert_ne!(rows(&conn, &second[0].table(1)), first_rows);
+    }
+
+    #[test]
+    fn rebuilding_drops_the_previous_samples_and_bumps_the_version() {
+        let conn = database();
+        assert_eq!(sample_version(&conn).unwrap(), 0);
+        create_sample_ladder(&conn, &[0.1, 0.2], None).unwrap();
+        assert_eq!(sample_version(&conn).unwrap(), 1);
+        create_seeded_sample_tables(&conn, 0.1, 3).unwrap();
+        assert_eq!(sample_version(&conn).unwrap(), 2);
+
+        let catalog = load_sample_catalog(&conn).unwrap();
+        assert_eq!(catalog.len(), 1);
+        assert_eq!(catalog[0].get_suffix(), "");
+        let rungs: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE name LIKE 's%_sample_r%'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(rungs, 0);
+    }
+
+    #[test]
+    fn sample_fractions_are_shares_of_the_rows() {
+        for fraction in [0.001, 0.5, 1.0] {
+            assert!(is_sample_fraction(fraction), "{}", fraction);
+        }
+        for fraction in [0.0, -0.1, 1.5, f64::NAN, f64::INFINITY] {
+            assert!(!is_sample_fraction(fraction), "{}", fraction);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//fixtures of the unit tests: a small TPC-H database generated in memory from a seed, and
+//seeded samples of it, so every test module draws its data the same way
+use rusqlite::Connection;
+
+use crate::{
+    engine::Engine,
+    sampling::{create_seeded_sample_tables, SampleSet},
+    tpch_gen::generate_tpch,
+};
+
+//about 6000 lineitem rows, enough for every join level to have matching sample rows
+pub const SCALE_FACTOR: f64 = 0.001;
+
+//resamples of the test engines, few enough to keep the tests fast
+pub const BOOTSTRAP_SIZE: usize = 50;
+
+//an in-memory database with the TPC-H tables generated from a seed
+pub fn database(seed: u64) -> Connection {
+    let conn = Connection::open_in_memory().unwrap();
+    generate_tpch(&conn, SCALE_FACTOR, seed).unwrap();
+    conn
+}
+
+//the database of a seed with one sample set of a fraction drawn with the same seed
+pub fn sampled_database(sample_fraction: f64, seed: u64) -> (Connection, SampleSet) {
+    let conn = database(seed);
+    let sample_set = create_seeded_sample_tables(&conn, sample_fraction, seed).unwrap();
+    (conn, sample_set)
+}
+
+//an engine over the database of a seed with one sample set of a fraction drawn with the
+//same seed
+pub fn engine(sample_fraction: f64, seed: u64) -> Engine {
+    let mut engine =
+        Engine::new(Connection::open_in_memory().unwrap()).with_bootstrap_size(BOOTSTRAP_SIZE);
+    engine.generate_tpch(SCALE_FACTOR, seed).unwrap();
+    engine.build_samples(sample_fraction, Some(seed)).unwrap();
+    engine
+}
@@ -0,0 +1,435 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rusqlite::{params, Connection, Result};
+
+use crate::ingest::tpch_schema;
+
+//TPC-H row counts at scale factor 1
+const SUPPLIERS_PER_SF: f64 = 10_000.0;
+const PARTS_PER_SF: f64 = 200_000.0;
+const CUSTOMERS_PER_SF: f64 = 150_000.0;
+const ORDERS_PER_SF: f64 = 1_500_000.0;
+const CLERKS_PER_SF: f64 = 1_000.0;
+
+const REGIONS: [&str; 5] = ["AFRICA", "AMERICA", "ASIA", "EUROPE", "MIDDLE EAST"];
+
+//nation name and region key
+const NATIONS: [(&str, i64); 25] = [
+    ("ALGERIA", 0),
+    ("ARGENTINA", 1),
+    ("BRAZIL", 1),
+    ("CANADA", 1),
+    ("EGYPT", 4),
+    ("ETHIOPIA", 0),
+    ("FRANCE", 3),
+    ("GERMANY", 3),
+    ("INDIA", 2),
+    ("INDONESIA", 2),
+    ("IRAN", 4),
+    ("IRAQ", 4),
+    ("JAPAN", 2),
+    ("JORDAN", 4),
+    ("KENYA", 0),
+    ("MOROCCO", 0),
+    ("MOZAMBIQUE", 0),
+    ("PERU", 1),
+    ("CHINA", 2),
+    ("ROMANIA", 3),
+    ("SAUDI ARABIA", 4),
+    ("VIETNAM", 2),
+    ("RUSSIA", 3),
+    ("UNITED KINGDOM", 3),
+    ("UNITED STATES", 1),
+];
+
+const SEGMENTS: [&str; 5] = [
+    "AUTOMOBILE",
+    "BUILDING",
+    "FURNITURE",
+    "HOUSEHOLD",
+    "MACHINERY",
+];
+const PRIORITIES: [&str; 5] = ["1-URGENT", "2-HIGH", "3-MEDIUM", "4-NOT SPECIFIED", "5-LOW"];
+const INSTRUCTIONS: [&str; 4] = [
+    "DELIVER IN PERSON",
+    "COLLECT COD",
+    "NONE",
+    "TAKE BACK RETURN",
+];
+const MODES: [&str; 7] = ["REG AIR", "AIR", "RAIL", "SHIP", "TRUCK", "MAIL", "FOB"];
+const TYPE_SIZES: [&str; 6] = ["STANDARD", "SMALL", "MEDIUM", "LARGE", "ECONOMY", "PROMO"];
+const TYPE_FINISHES: [&str; 5] = ["ANODIZED", "BURNISHED", "PLATED", "POLISHED", "BRUSHED"];
+const TYPE_MATERIALS: [&str; 5] = ["TIN", "NICKEL", "BRASS", "STEEL", "COPPER"];
+const CONTAINER_SIZES: [&str; 5] = ["SM", "LG", "MED", "JUMBO", "WRAP"];
+const CONTAINER_TYPES: [&str; 8] = ["CASE", "BOX", "BAG", "JAR", "PKG", "PACK", "CAN", "DRUM"];
+const COLORS: [&str; 16] = [
+    "almond",
+    "antique",
+    "aquamarine",
+    "azure",
+    "beige",
+    "bisque",
+    "black",
+    "blanched",
+    "blue",
+    "blush",
+    "brown",
+    "burlywood",
+    "chartreuse",
+    "chiffon",
+    "chocolate",
+    "coral",
+];
+const WORDS: [&str; 16] = [
+    "furiously",
+    "quickly",
+    "carefully",
+    "blithely",
+    "slyly",
+    "regular",
+    "final",
+    "special",
+    "pending",
+    "ironic",
+    "express",
+    "deposits",
+    "requests",
+    "packages",
+    "accounts",
+    "instructions",
+];
+
+//1992-01-01, 1998-12-31 and the 1995-06-17 "current date" of the spec, as days since 1970-01-01
+const START_DATE: i64 = 8035;
+const END_DATE: i64 = 10591;
+const CURRENT_DATE: i64 = 9298;
+
+//civil date (yyyy-mm-dd) of a day number counted from 1970-01-01
+fn format_date(days: i64) -> String {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn scaled(rows_per_sf: f64, scale_factor: f64) -> i64 {
+    ((rows_per_sf * scale_factor).round() as i64).max(1)
+}
+
+fn comment(rng: &mut StdRng) -> String {
+    let words = rng.gen_range(3..8);
+    (0..words)
+        .map(|_| *WORDS.choose(rng).unwrap())
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn address(rng: &mut StdRng) -> String {
+    let length = rng.gen_range(10..26);
+    (0..length)
+        .map(|_| {
+            let c = rng.gen_range(0..36);
+            if c < 10 {
+                (b'0' + c) as char
+            } else {
+                (b'a' + c - 10) as char
+            }
+        })
+        .collect()
+}
+
+fn phone(rng: &mut StdRng, nationkey: i64) -> String {
+    format!(
+        "{}-{}-{}-{}",
+        nationkey + 10,
+        rng.gen_range(100..1000),
+        rng.gen_range(100..1000),
+        rng.gen_range(1000..10000)
+    )
+}
+
+fn money(rng: &mut StdRng, low: i64, high: i64) -> f64 {
+    rng.gen_range(low..=high) as f64 / 100.0
+}
+
+//retail price of a part, as defined by the spec
+fn retail_price(partkey: i64) -> f64 {
+    (90_000 + ((partkey / 10) % 20_001) + 100 * (partkey % 1_000)) as f64 / 100.0
+}
+
+fn create_table(conn: &Connection, table: &str) -> Result<String> {
+    let columns = tpch_schema(table).unwrap();
+    let definitions: Vec<String> = columns
+        .iter()
+        .map(|(name, column_type)| format!("{} {}", name, column_type))
+        .collect();
+
+    conn.execute(&format!("DROP TABLE IF EXISTS {}", table), params![])?;
+    conn.execute(
+        &format!("CREATE TABLE {} ({})", table, definitions.join(", ")),
+        params![],
+    )?;
+
+    Ok(format!(
+        "INSERT INTO {} VALUES ({})",
+        table,
+        vec!["?"; columns.len()].join(", ")
+    ))
+}
+
+//generate TPC-H shaped region, nation, supplier, part, customer, orders and lineitem tables
+//at a (small) scale factor. The same seed always produces the same database.
+pub fn generate_tpch(conn: &Connection, scale_factor: f64, seed: u64) -> Result<()> {
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let suppliers = scaled(SUPPLIERS_PER_SF, scale_factor);
+    let parts = scaled(PARTS_PER_SF, scale_factor);
+    let customers = scaled(CUSTOMERS_PER_SF, scale_factor);
+    let orders = scaled(ORDERS_PER_SF, scale_factor);
+    let clerks = scaled(CLERKS_PER_SF, scale_factor);
+
+    conn.execute_batch("BEGIN")?;
+
+    let insert = create_table(conn, "region")?;
+    let mut stmt = conn.prepare(&insert)?;
+    for (regionkey, name) in REGIONS.iter().enumerate() {
+        stmt.execute(params![regionkey as i64, name, comment(&mut rng)])?;
+    }
+    drop(stmt);
+
+    let insert = create_table(conn, "nation")?;
+    let mut stmt = conn.prepare(&insert)?;
+    for (nationkey, (name, regionkey)) in NATIONS.iter().enumerate() {
+        stmt.execute(params![
+            nationkey as i64,
+            name,
+            regionkey,
+            comment(&mut rng)
+        ])?;
+    }
+    drop(stmt);
+
+    let insert = create_table(conn, "supplier")?;
+    let mut stmt = conn.prepare(&insert)?;
+    for suppkey in 1..=suppliers {
+        let nationkey = rng.gen_range(0..25);
+        stmt.execute(params![
+            suppkey,
+            format!("Supplier#{:09}", suppkey),
+            address(&mut rng),
+            nationkey,
+            phone(&mut rng, nationkey),
+            money(&mut rng, -99_999, 999_999),
+            comment(&mut rng),
+        ])?;
+    }
+    drop(stmt);
+
+    let insert = create_table(conn, "part")?;
+    let mut stmt = conn.prepare(&insert)?;
+    for partkey in 1..=parts {
+        let name = COLORS
+            .choose_multiple(&mut rng, 5)
+            .cloned()
+            .collect::<Vec<&str>>()
+            .join(" ");
+        let manufacturer = rng.gen_range(1..=5);
+        stmt.execute(params![
+            partkey,
+            name,
+            format!("Manufacturer#{}", manufacturer),
+            format!("Brand#{}{}", manufacturer, rng.gen_range(1..=5)),
+            format!(
+                "{} {} {}",
+                TYPE_SIZES.choose(&mut rng).unwrap(),
+                TYPE_FINISHES.choose(&mut rng).unwrap(),
+                TYPE_MATERIALS.choose(&mut rng).unwrap()
+            ),
+            rng.gen_range(1..=50),
+            format!(
+                "{} {}",
+                CONTAINER_SIZES.choose(&mut rng).unwrap(),
+                CONTAINER_TYPES.choose(&mut rng).unwrap()
+            ),
+            retail_price(partkey),
+            comment(&mut rng),
+        ])?;
+    }
+    drop(stmt);
+
+    let insert = create_table(conn, "customer")?;
+    let mut stmt = conn.prepare(&insert)?;
+    for custkey in 1..=customers {
+        let nationkey = rng.gen_range(0..25);
+        stmt.execute(params![
+            custkey,
+            format!("Customer#{:09}", custkey),
+            address(&mut rng),
+            nationkey,
+            phone(&mut rng, nationkey),
+            money(&mut rng, -99_999, 999_999),
+            SEGMENTS.choose(&mut rng).unwrap(),
+            comment(&mut rng),
+        ])?;
+    }
+    drop(stmt);
+
+    let insert = create_table(conn, "orders")?;
+    let mut orders_stmt = conn.prepare(&insert)?;
+    let insert = create_table(conn, "lineitem")?;
+    let mut lineitem_stmt = conn.prepare(&insert)?;
+    for orderkey in 1..=orders {
+        // as in dbgen, a third of the customers never place an order
+        let mut custkey = rng.gen_range(1..=customers);
+        if customers > 2 {
+            while custkey % 3 == 0 {
+                custkey = rng.gen_range(1..=customers);
+            }
+        }
+        let orderdate = rng.gen_range(START_DATE..=END_DATE - 151);
+
+        let mut totalprice = 0.0;
+        let mut shipped = 0;
+        let lines = rng.gen_range(1..=7);
+        for linenumber in 1..=lines {
+            let partkey = rng.gen_range(1..=parts);
+            let suppkey = rng.gen_range(1..=suppliers);
+            let quantity = rng.gen_range(1..=50) as f64;
+            let extendedprice = (quantity * retail_price(partkey) * 100.0).round() / 100.0;
+            let discount = rng.gen_range(0..=10) as f64 / 100.0;
+            let tax = rng.gen_range(0..=8) as f64 / 100.0;
+            let shipdate = orderdate + rng.gen_range(1..=121);
+            let commitdate = orderdate + rng.gen_range(30..=90);
+            let receiptdate = shipdate + rng.gen_range(1..=30);
+            let returnflag = if receiptdate <= CURRENT_DATE {
+                if rng.gen_bool(0.5) {
+                    "R"
+                } else {
+                    "A"
+                }
+            } else {
+                "N"
+            };
+            let linestatus = if shipdate > CURRENT_DATE {
+                "O"
+            } else {
+                shipped += 1;
+                "F"
+            };
+            totalprice += extendedprice * (1.0 + tax) * (1.0 - discount);
+
+            lineitem_stmt.execute(params![
+                orderkey,
+                partkey,
+                suppkey,
+                linenumber,
+                quantity,
+                extendedprice,
+                discount,
+                tax,
+                returnflag,
+                linestatus,
+                format_date(shipdate),
+                format_date(commitdate),
+                format_date(receiptdate),
+                INSTRUCTIONS.choose(&mut rng).unwrap(),
+                MODES.choose(&mut rng).unwrap(),
+                comment(&mut rng),
+            ])?;
+        }
+
+        let orderstatus = if shipped == lines {
+            "F"
+        } else if shipped == 0 {
+            "O"
+        } else {
+            "P"
+        };
+        orders_stmt.execute(params![
+            orderkey,
+            custkey,
+            orderstatus,
+            (totalprice * 100.0).round() / 100.0,
+            format_date(orderdate),
+            PRIORITIES.choose(&mut rng).unwrap(),
+            format!("Clerk#{:09}", rng.gen_range(1..=clerks)),
+            0,
+            comment(&mut rng),
+        ])?;
+    }
+    drop(orders_stmt);
+    drop(lineitem_stmt);
+
+    conn.execute_batch("COMMIT")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database(scale_factor: f64, seed: u64) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        generate_tpch(&conn, scale_factor, seed).unwrap();
+        conn
+    }
+
+    fn count(conn: &Connection, sql: &str) -> i64 {
+        conn.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    //a digest of the lineitem rows, equal for equal tables
+    fn lineitem_digest(conn: &Connection) -> (i64, f64, String) {
+        conn.query_row(
+            "SELECT COUNT(*), SUM(l_extendedprice * l_discount), MAX(l_shipdate || l_comment) FROM lineitem",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_database() {
+        let first = lineitem_digest(&database(0.001, 1));
+        assert_eq!(lineitem_digest(&database(0.001, 1)), first);
+        assert_ne!(lineitem_digest(&database(0.001, 2)), first);
+    }
+
+    #[test]
+    fn tables_scale_and_join_on_their_keys() {
+        let conn = database(0.002, 1);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM orders"), 3000);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM customer"), 300);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM nation"), 25);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM region"), 5);
+        let lineitems = count(&conn, "SELECT COUNT(*) FROM lineitem");
+        assert!((3000..=21000).contains(&lineitems));
+
+        // every row of the chain the samples follow finds its parent, and ships after its order
+        assert_eq!(
+            count(
+                &conn,
+                "SELECT COUNT(*) FROM lineitem, orders, customer, nation, region
+                 WHERE l_orderkey = o_orderkey AND o_custkey = c_custkey
+                 AND c_nationkey = n_nationkey AND n_regionkey = r_regionkey"
+            ),
+            lineitems
+        );
+        assert_eq!(
+            count(
+                &conn,
+                "SELECT COUNT(*) FROM lineitem, orders WHERE l_orderkey = o_orderkey
+                 AND (l_shipdate <= o_orderdate OR l_receiptdate <= l_shipdate)"
+            ),
+            0
+        );
+    }
+}
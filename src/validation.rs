@@ -0,0 +1,846 @@
+//semantic analysis of a parsed query against the schema of the samples, before anything is
+//evaluated: every table has to be covered by the samples, every column has to resolve to
+//one of the tables of the query and every literal has to fit the type of its column, so a
+//typo is reported instead of silently dropping a condition from the estimate
+use rusqlite::Connection;
+use std::collections::HashMap;
+
+use crate::{
+    engine::{is_sample_join, join_level, JOIN_KEYS},
+    error::AqpError,
+    expression::Expr,
+    parquet_store::{parquet_columns, sample_file},
+    parser::{
+        is_subquery_column, Aggregate, OrderItem, SQLQuery, Select, Subquery, TableRef, Where,
+    },
+    pushdown::{is_numeric_type, table_columns},
+    sampling::SampleSet,
+};
+
+//tables added by the s1..s5 samples, in order
+pub const SAMPLE_TABLES: [&str; 5] = ["lineitem", "orders", "customer", "nation", "region"];
+
+//largest edit distance at which a column is suggested for an unknown name
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+
+//base table and declared type of every column in the samples of a sample set
+#[derive(Debug, Clone)]
+pub struct SampleSchema {
+    columns: HashMap<String, (String, String)>,
+    levels: usize,
+}
+
+impl SampleSchema {
+    //table and declared type of a column
+    pub fn get_column(&self, column: &str) -> Option<(&str, &str)> {
+        self.columns
+            .get(column)
+            .map(|(table, column_type)| (table.as_str(), column_type.as_str()))
+    }
+
+    //number of sample levels, the tables of the first `levels` joins have samples
+    pub fn get_levels(&self) -> usize {
+        self.levels
+    }
+
+    //tables covered by the samples
+    pub fn tables(&self) -> &[&str] {
+        &SAMPLE_TABLES[..self.levels]
+    }
+
+    //closest column name to a misspelled one
+    fn suggestion(&self, column: &str) -> Option<&str> {
+        self.columns
+            .keys()
+            .map(|name| (edit_distance(column, name), name))
+            .filter(|&(distance, _)| distance <= MAX_SUGGESTION_DISTANCE)
+            .min()
+            .map(|(_, name)| name.as_str())
+    }
+}
+
+//read the schema of the s1..s5 samples, a column belongs to the table of the first level
+//whose sample has it (s1 has the lineitem columns, s2 adds the orders columns, ...)
+pub fn sample_schema(conn: &Connection, sample_set: &SampleSet) -> Result<SampleSchema, AqpError> {
+    let mut columns = HashMap::new();
+    let mut levels = 0;
+    for (level, table) in (1..=SAMPLE_TABLES.len()).zip(SAMPLE_TABLES) {
+        let level_columns = match sample_set.get_parquet_dir() {
+            Some(dir) => {
+                let path = sample_file(dir, sample_set, level);
+                if !path.exists() {
+                    break;
+                }
+                parquet_columns(&path)?
+            }
+            None => table_columns(conn, &sample_set.table(level))?,
+        };
+        // sqlite reports no columns for a table that does not exist
+        if level_columns.is_empty() {
+            break;
+        }
+        for (column, column_type) in level_columns {
+            columns
+                .entry(column)
+                .or_insert_with(|| (table.to_string(), column_type));
+        }
+        levels = level;
+    }
+
+    if levels == 0 {
+        return Err(AqpError::MissingTable(sample_set.table(1)));
+    }
+    Ok(SampleSchema { columns, levels })
+}
+
+//check a query against the sample schema, with a message saying what to change
+pub fn validate_query(sql_query: &SQLQuery, schema: &SampleSchema) -> Result<(), AqpError> {
+    let select = sql_query.get_select();
+    let tables = select.get_table();
+    let conditions = select.get_where_clause().clone().unwrap_or_default();
+
+    for (i, table) in tables.iter().enumerate() {
+        if !schema.tables().contains(&table.get_name()) {
+            return Err(AqpError::Unsupported(format!(
+                "table {} has no sample, the samples cover {}",
+                table.get_name(),
+                schema.tables().join(", ")
+            )));
+        }
+        if tables[..i]
+            .iter()
+            .any(|other| other.get_name() == table.get_name())
+        {
+            return Err(AqpError::Unsupported(format!(
+                "table {} appears twice in the from clause",
+                table.get_name()
+            )));
+        }
+    }
+
+    // the joins have to connect exactly the tables of the from clause
+    let join_conditions: Vec<Where> = conditions
+        .iter()
+        .filter(|condition| is_sample_join(condition))
+        .cloned()
+        .collect();
+    let join_count = join_level(&join_conditions)?;
+    if join_count >= schema.get_levels() {
+        return Err(AqpError::MissingSamples(format!(
+            "the query joins {} tables but the samples only go up to {}",
+            join_count + 1,
+            SAMPLE_TABLES[schema.get_levels() - 1]
+        )));
+    }
+    let has_lineitem = tables
+        .iter()
+        .any(|from| from.get_name() == SAMPLE_TABLES[0]);
+    if join_count == 0 && !has_lineitem {
+        return Err(AqpError::Unsupported(format!(
+            "every sample starts at lineitem, join {} with it on {} = {}",
+            tables[0].get_name(),
+            JOIN_KEYS[0].0,
+            JOIN_KEYS[0].1
+        )));
+    }
+    for table in &SAMPLE_TABLES[..=join_count] {
+        if !tables.iter().any(|from| from.get_name() == *table) {
+            return Err(AqpError::Semantic(format!(
+                "the joins use {}, which is not in the from clause",
+                table
+            )));
+        }
+    }
+    if let Some(table) = tables
+        .iter()
+        .find(|from| !SAMPLE_TABLES[..=join_count].contains(&from.get_name()))
+    {
+        let (left, right) = JOIN_KEYS[join_count];
+        return Err(AqpError::Unsupported(format!(
+            "table {} is not joined, add the condition {} = {}",
+            table.get_name(),
+            left,
+            right
+        )));
+    }
+
+    for function in select.get_functions() {
+        validate_aggregate(schema, tables, function)?;
+    }
+
+    for condition in &conditions {
+        validate_condition(schema, tables, condition)?;
+    }
+
+    // a grouped query returns the grouped expressions and aggregates only
+    for key in select.get_group_by() {
+        expression_type(schema, tables, key)?;
+    }
+    for key in select.get_keys() {
+        expression_type(schema, tables, key)?;
+        if !is_grouped_by(select, key) {
+            return Err(AqpError::Semantic(format!(
+                "{} is neither aggregated nor in group by",
+                key
+            )));
+        }
+    }
+    for condition in select.get_having() {
+        // numbers such as the 100 of `count(*) > 100` are fine on their own there
+        for side in [condition.get_left(), condition.get_right()] {
+            if !side.base_aggregates().is_empty() {
+                validate_ranking(schema, tables, side, "having")?;
+            }
+        }
+    }
+    // without group by there is one row, whatever order by says
+    if select.is_grouped() {
+        for order_by in select.get_order_by() {
+            match order_by.get_item() {
+                OrderItem::Aggregate(aggregate) => {
+                    validate_ranking(schema, tables, aggregate, "order by")?
+                }
+                OrderItem::Key(key) if !is_grouped_by(select, key) => {
+                    return Err(AqpError::Semantic(format!(
+                        "order by {} is neither an aggregate nor in group by",
+                        key
+                    )));
+                }
+                OrderItem::Key(_) => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_grouped_by(select: &Select, key: &Expr) -> bool {
+    select
+        .get_group_by()
+        .iter()
+        .any(|group_by| group_by.is_same(key))
+}
+
+//an aggregate of having or order by, which decide what the query returns in every bootstrap
+//resample
+fn validate_ranking(
+    schema: &SampleSchema,
+    tables: &[TableRef],
+    aggregate: &Aggregate,
+    clause: &str,
+) -> Result<(), AqpError> {
+    let bases = aggregate.base_aggregates();
+    if let Some(distinct) = bases
+        .iter()
+        .find(|base| matches!(base, Aggregate::CountDistinct(_)))
+    {
+        return Err(AqpError::Unsupported(format!(
+            "{} has no bootstrap resamples, so it cannot be used in {}",
+            distinct, clause
+        )));
+    }
+    validate_aggregate(schema, tables, aggregate)
+}
+
+//an aggregate of the select list: a fraction between 0 and 1 for percentile, a number to
+//aggregate unless distinct values are counted, and arithmetic over bootstrapped aggregates
+fn validate_aggregate(
+    schema: &SampleSchema,
+    tables: &[TableRef],
+    function: &Aggregate,
+) -> Result<(), AqpError> {
+    if let Aggregate::Constant(_) | Aggregate::Arithmetic(..) = function {
+        let bases = function.base_aggregates();
+        if bases.is_empty() {
+            return Err(AqpError::Semantic(format!(
+                "{} has no aggregate to estimate",
+                function
+            )));
+        }
+        if let Some(distinct) = bases
+            .iter()
+            .find(|base| matches!(base, Aggregate::CountDistinct(_)))
+        {
+            return Err(AqpError::Unsupported(format!(
+                "{} has no bootstrap resamples, so it cannot be combined in {}",
+                distinct, function
+            )));
+        }
+        for base in bases {
+            validate_aggregate(schema, tables, base)?;
+        }
+        return Ok(());
+    }
+    if let Aggregate::Min(_) | Aggregate::Max(_) = function {
+        return Err(AqpError::Unsupported(format!(
+            "{} cannot be estimated, a sample rarely holds the extreme rows",
+            function
+        )));
+    }
+    if let Aggregate::Percentile(_, fraction) = function {
+        if !(0.0..=1.0).contains(fraction) {
+            return Err(AqpError::Semantic(format!(
+                "{} asks for the fraction {} of the rows, which has to be between 0 and 1",
+                function, fraction
+            )));
+        }
+    }
+    if let Some(argument) = function.get_argument() {
+        // distinct values of any type can be counted
+        let argument_type = expression_type(schema, tables, argument)?;
+        let counted = matches!(function, Aggregate::CountDistinct(_));
+        if argument_type != Type::Number && !counted {
+            return Err(AqpError::Semantic(format!(
+                "{} aggregates {}, only numbers can be summed, averaged or ranked",
+                function, argument_type
+            )));
+        }
+    }
+    Ok(())
+}
+
+//type of an expression, text columns may hold yyyy-mm-dd dates
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Number,
+    Text,
+    Date,
+    Interval,
+}
+
+impl Type {
+    fn is_date_like(self) -> bool {
+        self == Type::Date || self == Type::Text
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Type::Number => write!(f, "a number"),
+            Type::Text => write!(f, "text"),
+            Type::Date => write!(f, "a date"),
+            Type::Interval => write!(f, "an interval"),
+        }
+    }
+}
+
+fn validate_condition(
+    schema: &SampleSchema,
+    tables: &[TableRef],
+    condition: &Where,
+) -> Result<(), AqpError> {
+    let left = expression_type(schema, tables, condition.get_left_expr())?;
+    match condition.get_right_expr() {
+        Expr::List(values) => {
+            for value in values {
+                let value_type = expression_type(schema, tables, value)?;
+                if !is_comparable(left, value_type) {
+                    return Err(AqpError::Semantic(format!(
+                        "{}: cannot compare {} with {}",
+                        condition, left, value_type
+                    )));
+                }
+            }
+            return Ok(());
+        }
+        Expr::Subquery(subquery) => return validate_subquery(schema, condition, subquery, left),
+        _ => {}
+    }
+    let right = expression_type(schema, tables, condition.get_right_expr())?;
+
+    // an equality of columns of two tables has to be one of the sample joins
+    if condition.is_join() && !is_sample_join(condition) {
+        let (left_table, _) = resolve_column(
+            schema,
+            tables,
+            condition.get_left_qualifier(),
+            condition.get_left_column().unwrap_or_default(),
+        )?;
+        let (right_table, _) = resolve_column(
+            schema,
+            tables,
+            condition.get_right_qualifier(),
+            condition.get_right_column().unwrap_or_default(),
+        )?;
+        if left_table != right_table {
+            return Err(AqpError::Unsupported(format!(
+                "{} is not one of the joins the samples are built on",
+                condition
+            )));
+        }
+    }
+
+    if !is_comparable(left, right) {
+        return Err(AqpError::Semantic(format!(
+            "{}: cannot compare {} with {}",
+            condition, left, right
+        )));
+    }
+    Ok(())
+}
+
+fn is_comparable(left: Type, right: Type) -> bool {
+    (left == Type::Number && right == Type::Number) || (left.is_date_like() && right.is_date_like())
+}
+
+//the subquery of an in: an uncorrelated one has to select one item comparable with the left
+//side, the conditions of an exists have to read lineitem only, the correlation on l_orderkey
+//being the one way the subquery relates to its query
+fn validate_subquery(
+    schema: &SampleSchema,
+    condition: &Where,
+    subquery: &Subquery,
+    left: Type,
+) -> Result<(), AqpError> {
+    let (table, conditions) = match subquery {
+        Subquery::Select(query) => return validate_in_select(schema, condition, query, left),
+        Subquery::OrderKeys(table, conditions) => (table, conditions),
+    };
+    if left != Type::Number {
+        return Err(AqpError::Semantic(format!(
+            "{}: l_orderkey cannot be compared with {}",
+            condition, left
+        )));
+    }
+
+    let tables = [table.clone()];
+    for inner in conditions {
+        let outer = inner
+            .get_left_expr()
+            .columns()
+            .into_iter()
+            .chain(inner.get_right_expr().columns())
+            .find(|&(qualifier, column)| !is_subquery_column(table, qualifier, column));
+        if let Some((qualifier, column)) = outer {
+            let column = qualifier.map_or(column.to_string(), |qualifier| {
+                format!("{}.{}", qualifier, column)
+            });
+            return Err(AqpError::Unsupported(format!(
+                "{} reads {} of the outer query, exists may only relate to it by l_orderkey",
+                inner, column
+            )));
+        }
+        validate_condition(schema, &tables, inner)?;
+    }
+    Ok(())
+}
+
+//the select of an in is answered over the base tables, whose columns sqlite checks when it
+//answers it, so its item is typed when it reads the tables of the samples or is an aggregate
+//and trusted otherwise, e.g. p_partkey of part
+fn validate_in_select(
+    schema: &SampleSchema,
+    condition: &Where,
+    query: &SQLQuery,
+    left: Type,
+) -> Result<(), AqpError> {
+    let select = query.get_select();
+    let items = select.get_keys().len() + select.get_functions().len();
+    if items != 1 {
+        return Err(AqpError::Semantic(format!(
+            "{}: the subquery selects {} items, in compares with one",
+            condition, items
+        )));
+    }
+
+    let item_type = |expr: &Expr| expression_type(schema, select.get_table(), expr).ok();
+    let item_type = match (select.get_keys().first(), select.get_functions().first()) {
+        (Some(key), _) => item_type(key),
+        (None, Some(Aggregate::Min(argument) | Aggregate::Max(argument))) => item_type(argument),
+        _ => Some(Type::Number),
+    };
+    match item_type {
+        Some(item_type) if !is_comparable(left, item_type) => Err(AqpError::Semantic(format!(
+            "{}: cannot compare {} with {}",
+            condition, left, item_type
+        ))),
+        _ => Ok(()),
+    }
+}
+
+//type of an expression, checking every column it reads and the operands of its operators
+fn expression_type(
+    schema: &SampleSchema,
+    tables: &[TableRef],
+    expr: &Expr,
+) -> Result<Type, AqpError> {
+    let expr_type = match expr {
+        Expr::Column(qualifier, column) => {
+            let (_, column_type) = resolve_column(schema, tables, qualifier.as_deref(), column)?;
+            if is_numeric_type(column_type) {
+                Type::Number
+            } else {
+                Type::Text
+            }
+        }
+        Expr::Number(_) => Type::Number,
+        Expr::Text(_) => Type::Text,
+        Expr::Date(_) => Type::Date,
+        Expr::Interval(..) => Type::Interval,
+        Expr::Negate(inner) => match expression_type(schema, tables, inner)? {
+            Type::Number => Type::Number,
+            Type::Interval => Type::Interval,
+            inner_type => {
+                return Err(AqpError::Semantic(format!(
+                    "{}: cannot negate {}",
+                    expr, inner_type
+                )))
+            }
+        },
+        Expr::Binary(left, operator, right) => {
+            let left_type = expression_type(schema, tables, left)?;
+            let right_type = expression_type(schema, tables, right)?;
+            match (left_type, *operator, right_type) {
+                (Type::Number, _, Type::Number) => Type::Number,
+                (date, '+' | '-', Type::Interval | Type::Number) if date.is_date_like() => {
+                    Type::Date
+                }
+                (Type::Interval, '+', date) if date.is_date_like() => Type::Date,
+                (a, '-', b) if a.is_date_like() && b.is_date_like() => Type::Number,
+                _ => {
+                    return Err(AqpError::Semantic(format!(
+                        "{}: cannot apply {} to {} and {}",
+                        expr, operator, left_type, right_type
+                    )))
+                }
+            }
+        }
+        Expr::List(_) | Expr::Subquery(_) | Expr::Keys(_) => {
+            return Err(AqpError::Semantic(format!(
+                "{}: a set of values is only allowed after in",
+                expr
+            )))
+        }
+        Expr::Case(branches, otherwise) => {
+            let mut result_type = None;
+            for (conditions, result) in branches {
+                for condition in conditions {
+                    if let Expr::Subquery(_) = condition.get_right_expr() {
+                        return Err(AqpError::Unsupported(format!(
+                            "{}: subqueries are answered in the where clause only",
+                            condition
+                        )));
+                    }
+                    validate_condition(schema, tables, condition)?;
+                }
+                let branch_type = expression_type(schema, tables, result)?;
+                if result_type.is_some_and(|result_type| result_type != branch_type) {
+                    return Err(AqpError::Semantic(format!(
+                        "{}: the branches give both {} and {}",
+                        expr,
+                        result_type.unwrap_or(branch_type),
+                        branch_type
+                    )));
+                }
+                result_type = Some(branch_type);
+            }
+            if let Some(otherwise) = otherwise {
+                let otherwise_type = expression_type(schema, tables, otherwise)?;
+                if result_type.is_some_and(|result_type| result_type != otherwise_type) {
+                    return Err(AqpError::Semantic(format!(
+                        "{}: else gives {} but the branches give {}",
+                        expr,
+                        otherwise_type,
+                        result_type.unwrap_or(otherwise_type)
+                    )));
+                }
+            }
+            result_type.unwrap_or(Type::Number)
+        }
+    };
+    Ok(expr_type)
+}
+
+//table and declared type of a column of the query, checking its qualifier names a table
+//of the from clause (by alias or name) that has the column
+fn resolve_column<'a>(
+    schema: &'a SampleSchema,
+    tables: &[TableRef],
+    qualifier: Option<&str>,
+    column: &str,
+) -> Result<(&'a str, &'a str), AqpError> {
+    let (table, column_type) = match schema.get_column(column) {
+        Some(found) => found,
+        None => {
+            let hint = schema
+                .suggestion(column)
+                .map_or(String::new(), |name| format!(", did you mean {}?", name));
+            return Err(AqpError::Semantic(format!(
+                "unknown column {}{}",
+                column, hint
+            )));
+        }
+    };
+
+    if let Some(qualifier) = qualifier {
+        let from = tables
+            .iter()
+            .find(|from| from.get_alias().unwrap_or(from.get_name()) == qualifier)
+            .or_else(|| tables.iter().find(|from| from.get_name() == qualifier));
+        match from {
+            None => {
+                return Err(AqpError::Semantic(format!(
+                    "{}.{}: no table or alias {} in the from clause",
+                    qualifier, column, qualifier
+                )))
+            }
+            Some(from) if from.get_name() != table => {
+                return Err(AqpError::Semantic(format!(
+                    "{}.{}: {} is a column of {}, not of {}",
+                    qualifier,
+                    column,
+                    column,
+                    table,
+                    from.get_name()
+                )))
+            }
+            Some(_) => {}
+        }
+    } else if !tables.iter().any(|from| from.get_name() == table) {
+        return Err(AqpError::Semantic(format!(
+            "{} is a column of {}, which is not in the from clause",
+            column, table
+        )));
+    }
+
+    Ok((table, column_type))
+}
+
+//levenshtein distance between two names
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, &b) in b.iter().enumerate() {
+            current[j + 1] = (previous[j] + (a != b) as usize)
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::parse_query;
+    use crate::test_support;
+
+    //lineitem and orders columns of s2, as sqlite declares them
+    fn schema() -> SampleSchema {
+        let columns = [
+            ("l_orderkey", "lineitem", "INTEGER"),
+            ("l_quantity", "lineitem", "REAL"),
+            ("l_suppkey", "lineitem", "INTEGER"),
+            ("l_shipmode", "lineitem", "TEXT"),
+            ("l_shipdate", "lineitem", "TEXT"),
+            ("o_orderkey", "orders", "INTEGER"),
+            ("o_orderdate", "orders", "TEXT"),
+            ("o_totalprice", "orders", "REAL"),
+        ];
+        SampleSchema {
+            columns: columns
+                .iter()
+                .map(|&(column, table, column_type)| {
+                    (
+                        column.to_string(),
+                        (table.to_string(), column_type.to_string()),
+                    )
+                })
+                .collect(),
+            levels: 2,
+        }
+    }
+
+    fn validate(sql: &str) -> Result<(), AqpError> {
+        validate_query(&parse_query(sql)?, &schema())
+    }
+
+    const JOIN: &str = "select count(*) from lineitem, orders where l_orderkey = o_orderkey";
+
+    fn semantic_error(sql: &str) -> String {
+        match validate(sql) {
+            Err(AqpError::Semantic(message)) => message,
+            other => panic!("{}: expected an invalid query, got {:?}", sql, other),
+        }
+    }
+
+    #[test]
+    fn unknown_columns_get_the_closest_name_suggested() {
+        assert_eq!(
+            semantic_error(&format!("{} and l_quantiy > 10", JOIN)),
+            "unknown column l_quantiy, did you mean l_quantity?"
+        );
+        assert_eq!(
+            semantic_error(&format!("{} and l_price > 10", JOIN)),
+            "unknown column l_price"
+        );
+        assert_eq!(edit_distance("o_orderky", "o_orderkey"), 1);
+        assert_eq!(edit_distance("l_tax", "l_tax"), 0);
+    }
+
+    #[test]
+    fn qualifiers_have_to_name_the_table_of_the_column() {
+        let query = "select count(*) from lineitem l, orders o where l.l_orderkey = o.o_orderkey";
+        validate(&format!("{} and orders.o_totalprice > 10", query)).unwrap();
+        assert_eq!(
+            semantic_error(&format!("{} and l.o_totalprice > 10", query)),
+            "l.o_totalprice: o_totalprice is a column of orders, not of lineitem"
+        );
+        assert_eq!(
+            semantic_error(&format!("{} and x.l_quantity > 10", query)),
+            "x.l_quantity: no table or alias x in the from clause"
+        );
+    }
+
+    #[test]
+    fn literals_have_to_fit_their_column() {
+        validate(&format!("{} and o_orderdate < date '1995-01-01'", JOIN)).unwrap();
+        assert_eq!(
+            semantic_error(&format!("{} and l_quantity > '10'", JOIN)),
+            "l_quantity > '10': cannot compare a number with text"
+        );
+        assert!(semantic_error(
+            "select sum(l_shipmode) from lineitem, orders where l_orderkey = o_orderkey"
+        )
+        .contains("only numbers"));
+    }
+
+    #[test]
+    fn tables_have_to_be_sampled_once() {
+        match validate("select count(*) from lineitem, part where l_partkey = p_partkey") {
+            Err(AqpError::Unsupported(message)) => {
+                assert_eq!(
+                    message,
+                    "table part has no sample, the samples cover lineitem, orders"
+                )
+            }
+            other => panic!("expected a table without sample, got {:?}", other),
+        }
+        assert!(matches!(
+            validate("select count(*) from lineitem, lineitem l2"),
+            Err(AqpError::Unsupported(message)) if message.contains("appears twice")
+        ));
+    }
+
+    #[test]
+    fn schema_of_generated_samples_covers_every_level() {
+        let engine = test_support::engine(0.1, 3);
+        let schema = sample_schema(engine.get_connection(), &engine.get_samples()[0]).unwrap();
+        assert_eq!(schema.tables(), SAMPLE_TABLES);
+        assert_eq!(schema.get_column("l_quantity").unwrap().0, "lineitem");
+        assert_eq!(schema.get_column("o_orderdate").unwrap().0, "orders");
+        assert_eq!(schema.get_column("r_name").unwrap().0, "region");
+        assert_eq!(schema.get_column("p_partkey"), None);
+    }
+
+    #[test]
+    fn in_subquery_over_tables_without_sample_is_trusted() {
+        validate(&format!(
+            "{} and l_suppkey in (select s_suppkey from supplier where s_acctbal > 0)",
+            JOIN
+        ))
+        .unwrap();
+        validate(&format!(
+            "{} and o_orderkey in (select l_orderkey from lineitem group by l_orderkey having sum(l_quantity) > 300)",
+            JOIN
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn left_side_of_in_subquery_is_resolved() {
+        match validate(&format!(
+            "{} and o_orderky in (select l_orderkey from lineitem)",
+            JOIN
+        )) {
+            Err(AqpError::Semantic(message)) => {
+                assert!(message.contains("did you mean o_orderkey"))
+            }
+            other => panic!("expected an unknown column, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn left_side_of_in_subquery_is_type_checked() {
+        for condition in [
+            "o_orderdate in (select l_orderkey from lineitem)",
+            "o_orderkey in (select l_shipmode from lineitem)",
+            "l_shipmode in (select count(*) from lineitem)",
+        ] {
+            match validate(&format!("{} and {}", JOIN, condition)) {
+                Err(AqpError::Semantic(message)) => {
+                    assert!(message.contains("cannot compare"), "{}", message)
+                }
+                other => panic!("{}: expected a type error, got {:?}", condition, other),
+            }
+        }
+        validate(&format!(
+            "{} and o_orderdate in (select max(l_shipdate) from lineitem)",
+            JOIN
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn in_subquery_selects_one_item() {
+        match validate(&format!(
+            "{} and o_orderkey in (select l_orderkey, l_suppkey from lineitem)",
+            JOIN
+        )) {
+            Err(AqpError::Semantic(message)) => assert!(message.contains("selects 2 items")),
+            other => panic!("expected two items to be rejected, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn in_list_is_type_checked() {
+        validate(&format!("{} and l_shipmode in ('MAIL', 'SHIP')", JOIN)).unwrap();
+        assert!(matches!(
+            validate(&format!("{} and l_shipmode in (1, 2)", JOIN)),
+            Err(AqpError::Semantic(_))
+        ));
+    }
+
+    #[test]
+    fn exists_reads_its_own_lineitem_only() {
+        validate(&format!(
+            "{} and exists (select * from lineitem l2 where l2.l_orderkey = o_orderkey and l2.l_quantity > 40)",
+            JOIN
+        ))
+        .unwrap();
+        match validate(
+            "select count(*) from lineitem l1, orders where l1.l_orderkey = o_orderkey and exists (select * from lineitem l2 where l2.l_orderkey = l1.l_orderkey and l2.l_suppkey <> l1.l_suppkey)",
+        ) {
+            Err(AqpError::Unsupported(message)) => assert!(message.contains("l1.l_suppkey")),
+            other => panic!("expected a correlated exists to be rejected, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn subquery_inside_case_is_unsupported() {
+        assert!(matches!(
+            validate("select sum(case when l_suppkey in (select s_suppkey from supplier) then 1 else 0 end) from lineitem, orders where l_orderkey = o_orderkey"),
+            Err(AqpError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn lineitem_alone_needs_no_join() {
+        validate("select count(*) from lineitem where l_quantity > 10").unwrap();
+        match validate("select count(*) from orders where o_totalprice > 10") {
+            Err(AqpError::Unsupported(message)) => {
+                assert!(message.contains("join orders with it on l_orderkey = o_orderkey"))
+            }
+            other => panic!("expected orders alone to be rejected, got {:?}", other),
+        }
+        match validate("select count(*) from lineitem, orders where l_quantity > 10") {
+            Err(AqpError::Unsupported(message)) => {
+                assert!(message.contains("table orders is not joined"))
+            }
+            other => panic!("expected an unjoined table to be rejected, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//workload files: many named queries answered in one run over the same samples
+//
+//  -- name: q1
+//  select count(*) from lineitem, orders
+//  where l_orderkey = o_orderkey and l_quantity > 20;
+//
+//a query ends with ';', a blank line or the end of the file. A `-- name: <name>` line
+//names the query after it, other lines starting with -- or # are comments, and queries
+//without a name are called q1, q2, ... by their position in the file
+use std::path::Path;
+
+use crate::error::AqpError;
+
+const NAME_PREFIX: &str = "-- name:";
+
+#[derive(Debug, Clone)]
+pub struct WorkloadQuery {
+    name: String,
+    sql: String,
+}
+
+impl WorkloadQuery {
+    pub fn new(name: String, sql: String) -> Self {
+        WorkloadQuery { name, sql }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_sql(&self) -> &str {
+        &self.sql
+    }
+}
+
+//split the text of a workload file into its queries
+pub fn parse_workload(text: &str) -> Vec<WorkloadQuery> {
+    let mut queries = Vec::new();
+    let mut name: Option<String> = None;
+    let mut lines: Vec<&str> = Vec::new();
+
+    let mut finish = |name: &mut Option<String>, lines: &mut Vec<&str>| {
+        if !lines.is_empty() {
+            let name = name
+                .take()
+                .unwrap_or_else(|| format!("q{}", queries.len() + 1));
+            queries.push(WorkloadQuery::new(name, lines.join(" ")));
+            lines.clear();
+        }
+    };
+
+    for line in text.lines() {
+        let line = line.trim();
+        if let Some(query_name) = line.strip_prefix(NAME_PREFIX) {
+            finish(&mut name, &mut lines);
+            name = Some(query_name.trim().to_string());
+        } else if line.is_empty() {
+            finish(&mut name, &mut lines);
+        } else if line.starts_with("--") || line.starts_with('#') {
+            continue;
+        } else if let Some(last) = line.strip_suffix(';') {
+            if !last.trim().is_empty() {
+                lines.push(last.trim());
+            }
+            finish(&mut name, &mut lines);
+        } else {
+            lines.push(line);
+        }
+    }
+    finish(&mut name, &mut lines);
+
+    queries
+}
+
+pub fn read_workload(path: &Path) -> Result<Vec<WorkloadQuery>, AqpError> {
+    let queries = parse_workload(&std::fs::read_to_string(path)?);
+    if queries.is_empty() {
+        return Err(AqpError::InvalidInput(format!(
+            "no queries in workload file {}",
+            path.display()
+        )));
+    }
+    Ok(queries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names_and_queries(text: &str) -> Vec<(String, String)> {
+        parse_workload(text)
+            .into_iter()
+            .map(|query| (query.name, query.sql))
+            .collect()
+    }
+
+    #[test]
+    fn named_and_unnamed_queries_end_at_a_semicolon_or_a_blank_line() {
+        let text = "-- name: big_orders\nselect count(*) from lineitem, orders\n  where l_orderkey = o_orderkey and o_totalprice > 100000;\nselect sum(l_quantity) from lineitem\n\n# a comment\n-- another one\nselect avg(l_tax) from lineitem\n-- name: last\nselect count(*) from lineitem where l_tax > 0.01";
+        assert_eq!(
+            names_and_queries(text),
+            vec![
+                (
+                    "big_orders".to_string(),
+                    "select count(*) from lineitem, orders where l_orderkey = o_orderkey and o_totalprice > 100000".to_string()
+                ),
+                ("q2".to_string(), "select sum(l_quantity) from lineitem".to_string()),
+                ("q3".to_string(), "select avg(l_tax) from lineitem".to_string()),
+                (
+                    "last".to_string(),
+                    "select count(*) from lineitem where l_tax > 0.01".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn lone_semicolons_and_unused_names_give_no_query() {
+        assert!(parse_workload(";\n\n-- name: nothing\n  ;\n-- only comments\n").is_empty());
+    }
+
+    #[test]
+    fn workload_file_without_queries_is_rejected() {
+        let path =
+            std::env::temp_dir().join(format!("aqprius_workload_{}.sql", std::process::id()));
+        std::fs::write(&path, "-- nothing to run\n").unwrap();
+        assert!(matches!(
+            read_workload(&path),
+            Err(AqpError::InvalidInput(_))
+        ));
+        std::fs::write(&path, "select count(*) from lineitem;").unwrap();
+        assert_eq!(read_workload(&path).unwrap()[0].get_name(), "q1");
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(read_workload(&path), Err(AqpError::Io(_))));
+    }
+}
@@ -0,0 +1,101 @@
+//empirical coverage of the 95% confidence intervals: the whole sample -> bootstrap -> CI
+//pipeline is repeated with different sample seeds on a generated database, and the share
+//of intervals holding the exact answer has to stay close to 95%. They answer thousands of
+//queries, so they are ignored by default: cargo test --release --test coverage -- --ignored
+use aqprius::Engine;
+use rusqlite::Connection;
+
+const SCALE_FACTOR: f64 = 0.002;
+const DATA_SEED: u64 = 7;
+const SAMPLE_FRACTION: f64 = 0.1;
+const BOOTSTRAP_SIZE: usize = 200;
+
+//overridable with AQPRIUS_COVERAGE_REPLICATES for a more (or less) thorough run
+const DEFAULT_REPLICATES: usize = 200;
+
+//with 200 replicates the standard deviation of the empirical coverage is about 1.5%,
+//so these bounds are about 3 standard deviations away from 95%
+const MIN_COVERAGE: f64 = 0.90;
+const MAX_COVERAGE: f64 = 0.99;
+
+//selections over the s2..s5 joins, completed with an aggregate
+const QUERY_BODIES: [&str; 4] = [
+    "from lineitem, orders where l_orderkey = o_orderkey and l_quantity > 20 and o_totalprice > 100000",
+    "from lineitem, orders, customer where l_orderkey = o_orderkey and o_custkey = c_custkey and l_quantity > 20 and c_acctbal > 0",
+    "from lineitem, orders, customer, nation where l_orderkey = o_orderkey and o_custkey = c_custkey and c_nationkey = n_nationkey and l_discount < 0.05 and n_regionkey < 3",
+    "from lineitem, orders, customer, nation, region where l_orderkey = o_orderkey and o_custkey = c_custkey and c_nationkey = n_nationkey and n_regionkey = r_regionkey and l_quantity < 30 and r_regionkey > 1",
+];
+
+fn replicates() -> usize {
+    std::env::var("AQPRIUS_COVERAGE_REPLICATES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_REPLICATES)
+}
+
+//share of the replicates whose interval holds the ground truth, for every query
+fn coverage(aggregate: &str) -> Vec<(String, f64)> {
+    let mut engine =
+        Engine::new(Connection::open_in_memory().unwrap()).with_bootstrap_size(BOOTSTRAP_SIZE);
+    engine.generate_tpch(SCALE_FACTOR, DATA_SEED).unwrap();
+
+    let queries: Vec<String> = QUERY_BODIES
+        .iter()
+        .map(|body| format!("select {} {}", aggregate, body))
+        .collect();
+    let ground_truths: Vec<_> = queries
+        .iter()
+        .map(|query| engine.ground_truth(query).unwrap().unwrap())
+        .collect();
+
+    let replicates = replicates();
+    let mut covered = vec![0; queries.len()];
+    for seed in 0..replicates as u64 {
+        engine.build_samples(SAMPLE_FRACTION, Some(seed)).unwrap();
+
+        for (i, query) in queries.iter().enumerate() {
+            let estimates = engine.estimate(query).unwrap();
+            let ground_truth = ground_truths[i].value_of(&estimates[0]).unwrap();
+            if estimates[0].contains(ground_truth) {
+                covered[i] += 1;
+            }
+        }
+    }
+
+    queries
+        .into_iter()
+        .zip(covered)
+        .map(|(query, covered)| (query, covered as f64 / replicates as f64))
+        .collect()
+}
+
+fn assert_coverage(aggregate: &str) {
+    for (query, coverage) in coverage(aggregate) {
+        assert!(
+            (MIN_COVERAGE..=MAX_COVERAGE).contains(&coverage),
+            "coverage {:.3} of the 95% interval is outside [{}, {}] for {}",
+            coverage,
+            MIN_COVERAGE,
+            MAX_COVERAGE,
+            query
+        );
+    }
+}
+
+#[test]
+#[ignore]
+fn count_interval_coverage() {
+    assert_coverage("count(*)");
+}
+
+#[test]
+#[ignore]
+fn sum_interval_coverage() {
+    assert_coverage("sum(l_extendedprice)");
+}
+
+#[test]
+#[ignore]
+fn avg_interval_coverage() {
+    assert_coverage("avg(l_extendedprice)");
+}